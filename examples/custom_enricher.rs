@@ -0,0 +1,83 @@
+//! Adds what else there is to know about each host by implementing
+//! [`Enricher`], here the network each one is in and who runs it, asked of
+//! Team Cymru's whois server.
+//!
+//! ```text
+//! cargo run --features examples --example custom_enricher -- 1.1.1.1 8.8.8.8
+//! ```
+
+use std::{env, io, net::IpAddr, time::Duration};
+
+use qapper::{Enricher, Enrichment, PortScanner, PortsStatus};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const WHOIS_SERVER: (&str, u16) = ("whois.cymru.com", 43);
+
+/// The autonomous system each host is in.
+struct Whois;
+
+impl Enricher for Whois {
+    fn name(&self) -> &str {
+        "asn"
+    }
+
+    // be nice to the whois server
+    fn concurrency(&self) -> usize {
+        4
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(10)
+    }
+
+    fn enrich<'a>(&'a self, ip: IpAddr, _status: &'a PortsStatus) -> Enrichment<'a> {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(WHOIS_SERVER).await?;
+            stream.write_all(format!(" -v {ip}\r\n").as_bytes()).await?;
+
+            let mut answer = String::new();
+            stream.read_to_string(&mut answer).await?;
+
+            // a header, then e.g. "13335 | 1.1.1.1 | 1.1.1.0/24 | AU | ... | CLOUDFLARENET, US"
+            let Some(line) = answer.lines().nth(1) else {
+                return Ok(None);
+            };
+            let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+            Ok(Some(
+                format!("AS{} {}", fields[0], fields.last().unwrap_or(&"")).into(),
+            ))
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let mut targets: Vec<IpAddr> = env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("targets are IP addresses"))
+        .collect();
+    if targets.is_empty() {
+        targets.push([1, 1, 1, 1].into());
+    }
+
+    let report = PortScanner::builder()
+        .ports("http,https".parse().expect("valid ports"))
+        .targets(targets)
+        .timeout(1000)
+        .enricher(Whois)
+        .build()?
+        .scan()
+        .await?;
+
+    for (ip, status) in &report.hosts {
+        match status.enrichments().get("asn") {
+            Some(asn) => println!("{ip}: {}", asn.as_str().unwrap_or_default()),
+            None => println!("{ip}: unknown"),
+        }
+    }
+
+    Ok(())
+}
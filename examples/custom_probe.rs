@@ -0,0 +1,67 @@
+//! Runs a probe of your own on each open port, here one checking whether
+//! Redis servers let anyone in without a password.
+//!
+//! ```text
+//! cargo run --features examples --example custom_probe -- 10.0.0.0 10.0.0.1
+//! ```
+
+use std::{env, io, net::IpAddr, time::Duration};
+
+use qapper::PortScanner;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+/// How long Redis gets to answer.
+const WAIT: Duration = Duration::from_secs(2);
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let mut targets: Vec<IpAddr> = env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("targets are IP addresses"))
+        .collect();
+    if targets.is_empty() {
+        targets.push([127, 0, 0, 1].into());
+    }
+
+    let scanner = PortScanner::builder()
+        .ports("redis,6380".parse().expect("valid ports"))
+        .targets(targets)
+        .timeout(1000)
+        // the scan waits for each probe, so keep them short
+        .on_checked_async(|ip, port, open| async move {
+            if !open {
+                return;
+            }
+
+            match timeout(WAIT, ping(ip, port)).await {
+                Ok(Ok(answer)) if answer.starts_with("+PONG") => {
+                    println!("{ip}:{port}: no password needed")
+                }
+                Ok(Ok(answer)) if answer.starts_with("-NOAUTH") => {
+                    println!("{ip}:{port}: password needed")
+                }
+                Ok(Ok(answer)) => println!("{ip}:{port}: not Redis? answered {answer:?}"),
+                Ok(Err(e)) => println!("{ip}:{port}: {e}"),
+                Err(_) => println!("{ip}:{port}: no answer"),
+            }
+        })
+        .build()?;
+
+    scanner.scan().await?;
+    Ok(())
+}
+
+/// Sends Redis a `PING`, returning the first line of its answer.
+async fn ping(ip: IpAddr, port: u16) -> io::Result<String> {
+    let mut stream = TcpStream::connect((ip, port)).await?;
+    stream.write_all(b"PING\r\n").await?;
+
+    let mut buf = [0; 256];
+    let len = stream.read(&mut buf).await?;
+    let answer = String::from_utf8_lossy(&buf[..len]);
+    Ok(answer.lines().next().unwrap_or_default().to_string())
+}
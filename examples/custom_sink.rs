@@ -0,0 +1,82 @@
+//! Writes results somewhere qapper doesn't know about by implementing
+//! [`ResultSink`], here a Markdown table of open ports.
+//!
+//! ```text
+//! cargo run --features examples --example custom_sink -- 192.168.1.1 > open.md
+//! ```
+
+use std::{
+    env,
+    io::{self, Write},
+    net::IpAddr,
+};
+
+use qapper::{service_name, PortScanner, Ports, ResultSink, ScanEvent, ScanReport};
+
+/// A Markdown table of open ports, with the service on each.
+struct MarkdownSink<W> {
+    out: W,
+    rows: Vec<ScanEvent>,
+}
+
+impl<W: Write + Send> ResultSink for MarkdownSink<W> {
+    // called as each port is checked
+    fn record(&mut self, event: &ScanEvent) -> io::Result<()> {
+        if event.open {
+            self.rows.push(event.clone());
+        }
+
+        Ok(())
+    }
+
+    // called once the scan is done
+    fn finish(&mut self, report: &ScanReport) -> io::Result<()> {
+        writeln!(self.out, "| Host | Port | Service |")?;
+        writeln!(self.out, "| --- | --- | --- |")?;
+
+        self.rows.sort_by_key(|event| (event.ip, event.port));
+        for event in &self.rows {
+            let service = match &event.service {
+                Some(service) => service.to_string(),
+                None => service_name(event.port).unwrap_or("?").to_string(),
+            };
+            writeln!(
+                self.out,
+                "| {} | {}/{} | {service} |",
+                event.ip, event.port, event.protocol
+            )?;
+        }
+
+        writeln!(self.out)?;
+        writeln!(self.out, "{} hosts up", report.hosts.len())
+    }
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let mut targets: Vec<IpAddr> = env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("targets are IP addresses"))
+        .collect();
+    if targets.is_empty() {
+        targets.push([127, 0, 0, 1].into());
+    }
+
+    let sink = MarkdownSink {
+        out: io::stdout(),
+        rows: vec![],
+    };
+
+    PortScanner::builder()
+        .ports(Ports::top(100))
+        .udp_ports("53,123,161".parse().expect("valid ports"))
+        .targets(targets)
+        .timeout(500)
+        .service_detection(1000)
+        .sink(sink)
+        .build()?
+        .scan()
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Embeds a scan in another program, handling ports as they're checked
+//! rather than waiting for the whole report, and giving up after a while.
+//!
+//! ```text
+//! cargo run --features examples --example stream -- 127.0.0.1 192.168.1.1
+//! ```
+
+use std::{env, io, net::IpAddr, pin::pin, time::Duration};
+
+use qapper::{PortScanner, Ports};
+use tokio_stream::StreamExt;
+
+/// How long the whole scan may take.
+const DEADLINE: Duration = Duration::from_secs(30);
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let mut targets: Vec<IpAddr> = env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("targets are IP addresses"))
+        .collect();
+    if targets.is_empty() {
+        targets.push([127, 0, 0, 1].into());
+    }
+
+    let scanner = PortScanner::builder()
+        .ports(Ports::top(100))
+        .targets(targets)
+        .timeout(500)
+        .service_detection(1000)
+        .build()?;
+
+    // ports not checked by the time the token is cancelled are skipped, and
+    // the stream ends
+    let cancel = scanner.cancellation_token();
+    tokio::spawn(async move {
+        tokio::time::sleep(DEADLINE).await;
+        cancel.cancel();
+    });
+
+    let mut events = pin!(scanner.scan_stream());
+    let mut checked = 0;
+    while let Some(event) = events.next().await {
+        checked += 1;
+        if !event.open {
+            continue;
+        }
+
+        match &event.service {
+            Some(service) => println!("{}:{} {service}", event.ip, event.port),
+            None => println!("{}:{}", event.ip, event.port),
+        }
+    }
+
+    eprintln!("{checked} ports checked");
+    Ok(())
+}
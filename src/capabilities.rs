@@ -0,0 +1,162 @@
+//! Which scan modes work where qapper runs, found by trying what each needs,
+//! e.g. opening a raw socket, rather than guessing from the user or OS.
+
+use std::{
+    fmt::{self, Display},
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket},
+};
+
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{exhaustion, probes::Bind};
+
+/// Whether a scan mode works here, and why or why not.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capability {
+    /// The mode, e.g. `traceroute`.
+    pub name: String,
+    pub supported: bool,
+    /// What it's missing, or what it uses, e.g. `raw sockets`.
+    pub detail: String,
+}
+
+impl Capability {
+    fn new(name: &str, supported: bool, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            supported,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let supported = if self.supported { "yes" } else { "no" };
+        write!(f, "{}: {supported} ({})", self.name, self.detail)
+    }
+}
+
+/// Tries out what each scan mode needs, without sending anything, and
+/// reports which will work.
+pub fn capabilities() -> Vec<Capability> {
+    let ping_sockets = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4)).is_ok();
+    let raw_sockets = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)).is_ok();
+    let ipv6 = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).is_ok();
+    // connecting a UDP socket only looks up the route
+    let ipv6_route = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.connect((PUBLIC_IPV6, 53)))
+        .is_ok();
+    let linux = cfg!(target_os = "linux");
+
+    let mut capabilities = vec![
+        match (ping_sockets, raw_sockets) {
+            (true, _) => Capability::new("ping", true, "unprivileged ICMP sockets"),
+            (false, true) => Capability::new("ping", true, "raw sockets"),
+            (false, false) => Capability::new(
+                "ping",
+                false,
+                "needs CAP_NET_RAW or net.ipv4.ping_group_range, connects to common ports instead",
+            ),
+        },
+        Capability::new(
+            "traceroute",
+            raw_sockets,
+            if raw_sockets {
+                "raw sockets"
+            } else {
+                "needs CAP_NET_RAW"
+            },
+        ),
+        Capability::new(
+            "os-guess",
+            raw_sockets || linux,
+            match (raw_sockets, linux) {
+                (true, true) => "TTLs and handshakes",
+                (true, false) => "TTLs only, handshakes need Linux",
+                (false, true) => "handshakes only, TTLs need CAP_NET_RAW",
+                (false, false) => "neither TTLs nor handshakes, leaving nothing to guess from",
+            },
+        ),
+        Capability::new(
+            "ipv6",
+            ipv6 && ipv6_route,
+            match (ipv6, ipv6_route) {
+                (true, true) => "routed",
+                (true, false) => "no route beyond the local network",
+                (false, _) => "disabled",
+            },
+        ),
+    ];
+
+    let ipv6_loopback = IpAddr::V6(Ipv6Addr::LOCALHOST);
+    let flow_label = Bind {
+        flow_label: Some(1),
+        ..Default::default()
+    };
+    capabilities.push(match (linux, ipv6) {
+        (false, _) => Capability::new("flow-label", false, "only supported on Linux"),
+        (true, false) => Capability::new("flow-label", false, "needs IPv6"),
+        (true, true) => match flow_label.tcp_socket(ipv6_loopback, 0) {
+            Ok(_) => Capability::new("flow-label", true, "flow label leases"),
+            Err(e) => Capability::new("flow-label", false, e.to_string()),
+        },
+    });
+
+    let headers = Bind {
+        hop_by_hop: true,
+        destination_options: true,
+        ..Default::default()
+    };
+    capabilities.push(match (linux, ipv6) {
+        (false, _) => Capability::new("ipv6-headers", false, "only supported on Linux"),
+        (true, false) => Capability::new("ipv6-headers", false, "needs IPv6"),
+        (true, true) => match headers.tcp_socket(ipv6_loopback, 0) {
+            Ok(_) => Capability::new("ipv6-headers", true, "extension header options"),
+            Err(e) => Capability::new("ipv6-headers", false, format!("needs CAP_NET_RAW: {e}")),
+        },
+    });
+
+    let interface = Bind {
+        interface: Some(LOOPBACK_INTERFACE.to_string()),
+        ..Default::default()
+    };
+    capabilities.push(if linux {
+        match interface.tcp_socket(Ipv4Addr::LOCALHOST.into(), 0) {
+            Ok(_) => Capability::new("interface", true, "SO_BINDTODEVICE"),
+            Err(e) => Capability::new("interface", false, e.to_string()),
+        }
+    } else {
+        Capability::new("interface", false, "only supported on Linux")
+    });
+
+    capabilities.push(match fs::metadata("/proc/net/arp") {
+        Ok(_) => Capability::new("mac", true, "the kernel's neighbor table"),
+        Err(_) => Capability::new("mac", false, "only supported on Linux"),
+    });
+
+    capabilities.push(match exhaustion::max_concurrency() {
+        Some(max) => Capability::new(
+            "concurrency",
+            true,
+            format!("at most {max} ports at once within the limit on open files"),
+        ),
+        None => Capability::new("concurrency", true, "no limit on open files"),
+    });
+
+    capabilities.push(Capability::new(
+        "pcap",
+        false,
+        "packets aren't captured, --capture-dir writes hexdumps of probe traffic instead",
+    ));
+
+    capabilities
+}
+
+/// An address on the internet to look up the route to, Cloudflare's DNS.
+const PUBLIC_IPV6: Ipv6Addr = Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111);
+
+/// Always there, so binding to it only fails without the right to bind.
+const LOOPBACK_INTERFACE: &str = "lo";
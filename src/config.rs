@@ -0,0 +1,263 @@
+//! Defaults for command line options from a config file, e.g.
+//!
+//! ```toml
+//! ports = "ssh,http,https"
+//! exclude_ports = "135-139,445"
+//! timeout = 500
+//! concurrency = 1000
+//! format = "json"
+//! service_detect = true
+//! ```
+//!
+//! Options given on the command line or in the environment take precedence.
+
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use clap::{parser::ValueSource, Arg, ArgMatches, CommandFactory};
+use qapper::Ports;
+use serde::{
+    de::{self, value::Error as ValueError, Visitor},
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+
+use crate::{logger::LogFormat, Config, Format, PortsArg};
+
+/// Read if no config file is given.
+const DEFAULT_PATH: &str = "qapper.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileConfig {
+    /// Ports to scan if none are given on the command line.
+    ports: Option<Ports>,
+    exclude_ports: Option<Ports>,
+    top_ports: Option<u8>,
+    udp: Option<Ports>,
+    verbose: Option<bool>,
+    quiet: Option<bool>,
+    log_format: Option<LogFormat>,
+    timeout: Option<u64>,
+    ping_timeout: Option<u64>,
+    retries: Option<u32>,
+    banner: Option<bool>,
+    banner_bytes: Option<usize>,
+    banner_wait: Option<u64>,
+    service_detect: Option<bool>,
+    tls_probe: Option<bool>,
+    http_probe: Option<bool>,
+    probe_wait: Option<u64>,
+    redact: Option<Vec<String>>,
+    credentials: Option<PathBuf>,
+    concurrency: Option<usize>,
+    max_rate: Option<u32>,
+    no_ping: Option<bool>,
+    format: Option<Format>,
+    open: Option<bool>,
+    show_latency: Option<bool>,
+    human: Option<bool>,
+    policy: Option<PathBuf>,
+    port_rules: Option<PathBuf>,
+}
+
+impl FileConfig {
+    /// Loads `path`, or `qapper.toml` in the working directory if not given
+    /// and it exists.
+    pub(crate) fn load(path: Option<&Path>) -> io::Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_PATH).exists() => Path::new(DEFAULT_PATH),
+            None => return Ok(Self::default()),
+        };
+
+        let text = fs::read_to_string(path)?;
+        let config: Self =
+            toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Checks numbers the way they are on the command line, e.g. that
+    /// `top_ports` is at most 100, which their types alone don't.
+    fn check(&self) -> io::Result<()> {
+        let command = Config::command();
+        let check = |key: &'static str, value: String| {
+            let Some(arg) = command.get_arguments().find(|arg| arg.get_id() == key) else {
+                return Ok(());
+            };
+
+            // a command of just the value, as the option may need others
+            let value_only = clap::Command::new(key)
+                .no_binary_name(true)
+                .arg(Arg::new(key).value_parser(arg.get_value_parser().clone()));
+            match value_only.try_get_matches_from([&value]) {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    let reason = e.source().map_or_else(|| e.to_string(), |e| e.to_string());
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid {key} = {value}: {reason}"),
+                    ))
+                }
+            }
+        };
+
+        macro_rules! check {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if let Some(value) = self.$field {
+                        check(stringify!($field), value.to_string())?;
+                    }
+                )*
+            };
+        }
+
+        check!(
+            top_ports,
+            timeout,
+            ping_timeout,
+            retries,
+            banner_bytes,
+            banner_wait,
+            probe_wait,
+            concurrency,
+            max_rate,
+        );
+        Ok(())
+    }
+
+    /// The options a config file can set, in order.
+    pub(crate) fn keys() -> &'static [&'static str] {
+        let mut keys: &'static [&'static str] = &[];
+        // serde names the fields when asked for a struct, which is all this
+        // deserializer is good for
+        let _ = Self::deserialize(FieldNames(&mut keys));
+        keys
+    }
+
+    /// Fills in whatever `matches` shows wasn't given on the command line or
+    /// in the environment.
+    pub(crate) fn apply(self, config: &mut Config, matches: &ArgMatches) {
+        let given = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+
+        macro_rules! fill {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if let Some(value) = self.$field {
+                        if !given(stringify!($field)) {
+                            config.$field = value.into();
+                        }
+                    }
+                )*
+            };
+        }
+
+        fill!(
+            exclude_ports,
+            top_ports,
+            udp,
+            verbose,
+            quiet,
+            log_format,
+            timeout,
+            ping_timeout,
+            retries,
+            banner,
+            banner_bytes,
+            banner_wait,
+            service_detect,
+            tls_probe,
+            http_probe,
+            probe_wait,
+            redact,
+            credentials,
+            concurrency,
+            max_rate,
+            no_ping,
+            format,
+            open,
+            show_latency,
+            human,
+            policy,
+            port_rules,
+        );
+
+        // either on the command line overrides the other in the file
+        if given("quiet") {
+            config.verbose = false;
+        } else if given("verbose") {
+            config.quiet = false;
+        }
+
+        let ports_given = matches!(config.ports, Some(PortsArg::Ports(_)))
+            || ["port_list", "ports_file", "top_ports"]
+                .into_iter()
+                .any(given);
+
+        if let Some(ports) = self.ports.filter(|_| !ports_given) {
+            config.port_list = Some(ports);
+        }
+    }
+}
+
+/// Records the field names of the struct it's asked to deserialize, and
+/// fails.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only structs have field names"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("only the field names were wanted"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(text: &str) -> io::Result<()> {
+        toml::from_str::<FileConfig>(text).unwrap().check()
+    }
+
+    #[test]
+    fn accepts_values_the_command_line_would() {
+        check("top_ports = 100\nconcurrency = 1\ntimeout = 0").unwrap();
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        assert_eq!(
+            check("top_ports = 200").unwrap_err().to_string(),
+            "invalid top_ports = 200: 200 is not in 1..=100"
+        );
+        assert!(check("max_rate = 0")
+            .unwrap_err()
+            .to_string()
+            .starts_with("invalid max_rate = 0: "));
+    }
+}
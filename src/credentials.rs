@@ -0,0 +1,213 @@
+use std::{fmt::Debug, fs, io, net::IpAddr, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ipnet::IpNet;
+use serde::Deserialize;
+
+use crate::{policy::deserialize_ports, ports::Ports};
+
+/// Secrets probes may use to authenticate, declared in a credentials file,
+/// e.g.
+///
+/// ```toml
+/// [[http_basic]]
+/// subnet = "10.0.0.0/8"
+/// ports = "8080,9090"
+/// username = "monitor"
+/// password_env = "QAPPER_HTTP_PASSWORD"
+/// ```
+///
+/// Secrets are given inline (`password`), or read from an environment
+/// variable (`password_env`) or a file (`password_file`) when loading. Entries
+/// without a subnet or ports match all of them, and the first entry matching
+/// a port is used. Secrets are redacted from logs and results.
+#[derive(Debug, Default, Deserialize)]
+pub struct Credentials {
+    #[serde(default)]
+    http_basic: Vec<HttpBasic>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HttpBasic {
+    subnet: Option<IpNet>,
+
+    #[serde(default, deserialize_with = "deserialize_ports")]
+    ports: Option<Ports>,
+
+    username: String,
+
+    password: Option<Secret>,
+    password_env: Option<String>,
+    password_file: Option<String>,
+}
+
+/// Keeps secrets out of `Debug` output.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct Secret(String);
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "[REDACTED]")
+    }
+}
+
+impl Credentials {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut credentials: Self =
+            toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        for entry in &mut credentials.http_basic {
+            entry.resolve()?;
+        }
+
+        Ok(credentials)
+    }
+
+    /// Value of the `Authorization` header to send to `ip:port`, if any.
+    pub(crate) fn http_authorization(&self, ip: &IpAddr, port: u16) -> Option<String> {
+        let entry = self.http_basic.iter().find(|entry| {
+            entry.subnet.is_none_or(|subnet| subnet.contains(ip))
+                && entry
+                    .ports
+                    .as_ref()
+                    .is_none_or(|ports| ports.contains(port))
+        })?;
+
+        let password = entry.password.as_ref().map_or("", |secret| &secret.0);
+        let token = STANDARD.encode(format!("{}:{password}", entry.username));
+        Some(format!("Basic {token}"))
+    }
+
+    /// Every secret, to be redacted wherever it could show up.
+    pub(crate) fn secrets(&self) -> impl Iterator<Item = &str> {
+        self.http_basic
+            .iter()
+            .filter_map(|entry| entry.password.as_ref())
+            .map(|secret| secret.0.as_str())
+            .filter(|secret| !secret.is_empty())
+    }
+}
+
+impl HttpBasic {
+    /// Reads the password from wherever the entry says it is.
+    fn resolve(&mut self) -> io::Result<()> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let password = match (
+            self.password.take(),
+            &self.password_env,
+            &self.password_file,
+        ) {
+            (Some(password), None, None) => password.0,
+            (None, Some(var), None) => std::env::var(var)
+                .map_err(|e| invalid(format!("password for {} from ${var}: {e}", self.username)))?,
+            (None, None, Some(path)) => fs::read_to_string(path)?.trim_end().to_string(),
+            _ => {
+                return Err(invalid(format!(
+                    "expected exactly one of password, password_env or password_file for {}",
+                    self.username
+                )))
+            }
+        };
+
+        self.password = Some(Secret(password));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(text: &str) -> io::Result<Credentials> {
+        let mut credentials: Credentials =
+            toml::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for entry in &mut credentials.http_basic {
+            entry.resolve()?;
+        }
+
+        Ok(credentials)
+    }
+
+    #[test]
+    fn picks_the_first_matching_entry() {
+        let credentials = credentials(
+            r#"
+            [[http_basic]]
+            subnet = "10.0.0.0/8"
+            ports = "8080"
+            username = "monitor"
+            password = "s3cret"
+
+            [[http_basic]]
+            username = "admin"
+            password = ""
+            "#,
+        )
+        .unwrap();
+
+        let ip = "10.1.2.3".parse().unwrap();
+        assert_eq!(
+            credentials.http_authorization(&ip, 8080).as_deref(),
+            Some("Basic bW9uaXRvcjpzM2NyZXQ=")
+        );
+        assert_eq!(
+            credentials.http_authorization(&ip, 80).as_deref(),
+            Some("Basic YWRtaW46")
+        );
+        assert_eq!(credentials.secrets().collect::<Vec<_>>(), ["s3cret"]);
+        assert!(!format!("{credentials:?}").contains("s3cret"));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let err = credentials("[[http_basic]\nusername = \"monitor\"").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = credentials("[[http_basic]]\nusername = \"monitor\"\nsubnet = \"10.0.0.0/33\"")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err =
+            credentials("[[http_basic]]\nusername = \"monitor\"\nports = \"1-x\"").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_missing_fields() {
+        let err = credentials("[[http_basic]]\npassword = \"s3cret\"").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("username"), "{err}");
+
+        let err = credentials("[[http_basic]]\nusername = \"monitor\"").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected exactly one of password, password_env or password_file for monitor"
+        );
+
+        let err = credentials(
+            "[[http_basic]]\nusername = \"monitor\"\npassword = \"a\"\npassword_env = \"B\"",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_missing_secrets() {
+        let err = credentials(
+            "[[http_basic]]\nusername = \"monitor\"\npassword_env = \"QAPPER_TEST_UNSET\"",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .starts_with("password for monitor from $QAPPER_TEST_UNSET: "));
+
+        let err = credentials(
+            "[[http_basic]]\nusername = \"monitor\"\npassword_file = \"/nonexistent/qapper\"",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
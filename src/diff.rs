@@ -0,0 +1,353 @@
+//! What changed between two scans of the same targets.
+
+use std::{fmt::Display, net::IpAddr};
+
+use serde::Serialize;
+
+use crate::{policy::Protocol, ports::PortsStatus, probes::Service, scanner::ScanReport};
+
+/// A difference between an earlier and a later scan, found with
+/// [`ScanReport::changes`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    /// The host responded, and didn't before. Its open ports follow as
+    /// [`Change::Opened`].
+    HostUp { ip: IpAddr },
+    /// The host responded before, and didn't this time.
+    HostDown { ip: IpAddr },
+    /// The port was checked both times and is now open.
+    Opened {
+        ip: IpAddr,
+        port: u16,
+        #[serde(skip_serializing_if = "Protocol::is_tcp")]
+        protocol: Protocol,
+    },
+    /// The port was open and is now closed.
+    Closed {
+        ip: IpAddr,
+        port: u16,
+        #[serde(skip_serializing_if = "Protocol::is_tcp")]
+        protocol: Protocol,
+    },
+    /// The port was open both times, and a different service was found on
+    /// it. Ports whose service was only identified once don't count.
+    ServiceChanged {
+        ip: IpAddr,
+        port: u16,
+        #[serde(skip_serializing_if = "Protocol::is_tcp")]
+        protocol: Protocol,
+        before: Service,
+        after: Service,
+    },
+    /// The port keeps opening and closing, `flips` times in all since it
+    /// was first seen to. Never found by [`ScanReport::changes`], as it
+    /// takes more than two scans to tell, but by `qapper --watch`.
+    Flapping {
+        ip: IpAddr,
+        port: u16,
+        #[serde(skip_serializing_if = "Protocol::is_tcp")]
+        protocol: Protocol,
+        flips: u32,
+    },
+}
+
+/// Formats as e.g. `10.0.0.1: 443 opened`, `10.0.0.1: udp 53 closed`,
+/// `10.0.0.1: 22 changed from ssh OpenSSH_8.9 to ssh OpenSSH_9.6`,
+/// `10.0.0.1: 8080 flapping, 4 flips` or `10.0.0.2: host down`.
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (ip, port, protocol, change) = match self {
+            Self::HostUp { ip } => return write!(f, "{ip}: host up"),
+            Self::HostDown { ip } => return write!(f, "{ip}: host down"),
+            Self::Flapping {
+                ip,
+                port,
+                protocol,
+                flips,
+            } => {
+                return match protocol {
+                    Protocol::Tcp => write!(f, "{ip}: {port} flapping, {flips} flips"),
+                    _ => write!(f, "{ip}: {protocol} {port} flapping, {flips} flips"),
+                }
+            }
+            Self::ServiceChanged {
+                ip,
+                port,
+                protocol,
+                before,
+                after,
+            } => {
+                return match protocol {
+                    Protocol::Tcp => write!(f, "{ip}: {port} changed from {before} to {after}"),
+                    _ => write!(
+                        f,
+                        "{ip}: {protocol} {port} changed from {before} to {after}"
+                    ),
+                }
+            }
+            Self::Opened { ip, port, protocol } => (ip, port, protocol, "opened"),
+            Self::Closed { ip, port, protocol } => (ip, port, protocol, "closed"),
+        };
+
+        match protocol {
+            Protocol::Tcp => write!(f, "{ip}: {port} {change}"),
+            _ => write!(f, "{ip}: {protocol} {port} {change}"),
+        }
+    }
+}
+
+impl Change {
+    /// The name of the change in event feeds: `host_new`, `host_gone`,
+    /// `port_opened`, `port_closed`, `service_changed` or `port_flapping`.
+    pub fn event(&self) -> &'static str {
+        match self {
+            Self::HostUp { .. } => "host_new",
+            Self::HostDown { .. } => "host_gone",
+            Self::Opened { .. } => "port_opened",
+            Self::Closed { .. } => "port_closed",
+            Self::ServiceChanged { .. } => "service_changed",
+            Self::Flapping { .. } => "port_flapping",
+        }
+    }
+
+    /// The host that changed, or whose port did.
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            Self::HostUp { ip } | Self::HostDown { ip } => *ip,
+            Self::Opened { ip, .. }
+            | Self::Closed { ip, .. }
+            | Self::ServiceChanged { ip, .. }
+            | Self::Flapping { ip, .. } => *ip,
+        }
+    }
+
+    /// The port that opened, closed, changed service or flaps, if it's not
+    /// a host that changed.
+    pub fn port(&self) -> Option<(Protocol, u16)> {
+        match self {
+            Self::HostUp { .. } | Self::HostDown { .. } => None,
+            Self::Opened { port, protocol, .. }
+            | Self::Closed { port, protocol, .. }
+            | Self::ServiceChanged { port, protocol, .. }
+            | Self::Flapping { port, protocol, .. } => Some((*protocol, *port)),
+        }
+    }
+}
+
+impl ScanReport {
+    /// What changed from this scan to `later`, by host. Ports only checked
+    /// in one of them don't count as changed.
+    pub fn changes(&self, later: &ScanReport) -> Vec<Change> {
+        let mut changes = vec![];
+        for (&ip, before) in &self.hosts {
+            if !later.hosts.contains_key(&ip) {
+                changes.push(Change::HostDown { ip });
+            }
+
+            if let Some(after) = later.hosts.get(&ip) {
+                port_changes(ip, Protocol::Tcp, Some(before), after, &mut changes);
+                if let Some(after) = after.udp() {
+                    port_changes(ip, Protocol::Udp, before.udp(), after, &mut changes);
+                }
+            }
+        }
+
+        for (&ip, after) in &later.hosts {
+            if !self.hosts.contains_key(&ip) {
+                changes.push(Change::HostUp { ip });
+                port_changes(ip, Protocol::Tcp, None, after, &mut changes);
+                if let Some(after) = after.udp() {
+                    port_changes(ip, Protocol::Udp, None, after, &mut changes);
+                }
+            }
+        }
+
+        changes
+    }
+}
+
+/// Adds the ports of `ip` which opened, closed or changed service from
+/// `before` to `after`. Without `before`, every open port counts as opened.
+fn port_changes(
+    ip: IpAddr,
+    protocol: Protocol,
+    before: Option<&PortsStatus>,
+    after: &PortsStatus,
+    changes: &mut Vec<Change>,
+) {
+    // reports keep their ports sorted
+    let was_closed =
+        |port: &u16| before.is_none_or(|before| before.closed().binary_search(port).is_ok());
+    let was_open =
+        |port: &u16| before.is_some_and(|before| before.open().binary_search(port).is_ok());
+
+    changes.extend(
+        after
+            .open()
+            .iter()
+            .filter(|port| was_closed(port))
+            .map(|&port| Change::Opened { ip, port, protocol }),
+    );
+
+    changes.extend(
+        after
+            .closed()
+            .iter()
+            .filter(|port| was_open(port))
+            .map(|&port| Change::Closed { ip, port, protocol }),
+    );
+
+    let Some(before) = before else {
+        return;
+    };
+
+    changes.extend(after.services().iter().filter_map(|(&port, service)| {
+        let earlier = before.services().get(&port)?;
+        (earlier != service).then(|| Change::ServiceChanged {
+            ip,
+            port,
+            protocol,
+            before: earlier.clone(),
+            after: service.clone(),
+        })
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    fn report(hosts: &[(IpAddr, Value)]) -> ScanReport {
+        let mut report = ScanReport::default();
+        for (ip, status) in hosts {
+            let status = serde_json::from_value(status.clone()).unwrap();
+            report.hosts.insert(*ip, status);
+        }
+
+        report
+    }
+
+    fn ssh(version: &str) -> Service {
+        Service {
+            name: "ssh".to_string(),
+            version: Some(version.to_string()),
+            details: None,
+        }
+    }
+
+    #[test]
+    fn finds_ports_opened_and_closed() {
+        let before = report(&[(IP, json!({ "open": [22], "closed": [80, 443] }))]);
+        let after = report(&[(IP, json!({ "open": [80], "closed": [22, 443] }))]);
+        assert_eq!(
+            before.changes(&after),
+            [
+                Change::Opened {
+                    ip: IP,
+                    port: 80,
+                    protocol: Protocol::Tcp
+                },
+                Change::Closed {
+                    ip: IP,
+                    port: 22,
+                    protocol: Protocol::Tcp
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_ports_checked_once() {
+        let before = report(&[(IP, json!({ "open": [22], "closed": [] }))]);
+        let after = report(&[(IP, json!({ "open": [80], "closed": [] }))]);
+        assert!(before.changes(&after).is_empty());
+    }
+
+    #[test]
+    fn finds_udp_ports_opened() {
+        let before = report(&[(
+            IP,
+            json!({ "open": [], "udp": { "open": [], "closed": [53] } }),
+        )]);
+        let after = report(&[(IP, json!({ "open": [], "udp": { "open": [53] } }))]);
+        assert_eq!(
+            before.changes(&after),
+            [Change::Opened {
+                ip: IP,
+                port: 53,
+                protocol: Protocol::Udp
+            }]
+        );
+    }
+
+    #[test]
+    fn finds_services_changed() {
+        let before = report(&[(
+            IP,
+            json!({ "open": [22], "services": { "22": ssh("OpenSSH_8.9") } }),
+        )]);
+        let after = report(&[(
+            IP,
+            json!({ "open": [22], "services": { "22": ssh("OpenSSH_9.6") } }),
+        )]);
+
+        let changes = before.changes(&after);
+        assert_eq!(
+            changes,
+            [Change::ServiceChanged {
+                ip: IP,
+                port: 22,
+                protocol: Protocol::Tcp,
+                before: ssh("OpenSSH_8.9"),
+                after: ssh("OpenSSH_9.6"),
+            }]
+        );
+        assert_eq!(
+            changes[0].to_string(),
+            "10.0.0.1: 22 changed from ssh OpenSSH_8.9 to ssh OpenSSH_9.6"
+        );
+        assert!(before.changes(&before).is_empty());
+    }
+
+    #[test]
+    fn ignores_services_identified_once() {
+        let before = report(&[(IP, json!({ "open": [22] }))]);
+        let after = report(&[(
+            IP,
+            json!({ "open": [22], "services": { "22": ssh("OpenSSH_9.6") } }),
+        )]);
+        assert!(before.changes(&after).is_empty());
+    }
+
+    #[test]
+    fn finds_hosts_up_with_their_open_ports() {
+        let before = report(&[]);
+        let after = report(&[(IP, json!({ "open": [22], "closed": [80] }))]);
+        assert_eq!(
+            before.changes(&after),
+            [
+                Change::HostUp { ip: IP },
+                Change::Opened {
+                    ip: IP,
+                    port: 22,
+                    protocol: Protocol::Tcp
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_hosts_down() {
+        let before = report(&[(IP, json!({ "open": [22] }))]);
+        let changes = before.changes(&report(&[]));
+        assert_eq!(changes, [Change::HostDown { ip: IP }]);
+        assert_eq!(changes[0].event(), "host_gone");
+    }
+}
@@ -0,0 +1,563 @@
+//! Just enough DNS to look up SRV records, e.g. `_ldap._tcp.example.com`,
+//! which is how services like Active Directory, SIP and XMPP are published,
+//! to look up the names of addresses, and to resolve host names with a name
+//! server of our choosing. SRV and PTR queries go to the name servers in
+//! `/etc/resolv.conf`, over TCP if the answer doesn't fit in a datagram.
+
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::Range,
+    time::Duration,
+};
+
+use qapper::{Enricher, Enrichment, PortsStatus, Protocol};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::timeout,
+};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// How long each name server gets to answer.
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// A host and port a service is published on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SrvRecord {
+    pub(crate) target: String,
+    pub(crate) port: u16,
+}
+
+/// The protocol a service is published for, from the second label of its
+/// name, e.g. `_udp` in `_sip._udp.example.com`.
+pub(crate) fn protocol(name: &str) -> Protocol {
+    match name.split('.').nth(1) {
+        Some(label) if label.eq_ignore_ascii_case("_udp") => Protocol::Udp,
+        _ => Protocol::Tcp,
+    }
+}
+
+/// Looks up the SRV records of `name`, trying each name server in turn.
+pub(crate) async fn lookup_srv(name: &str) -> io::Result<Vec<SrvRecord>> {
+    ask_name_servers(|server| srv_records(server, name)).await
+}
+
+/// The names `ip` has in PTR records, trying each name server in turn.
+pub(crate) async fn lookup_ptr(ip: IpAddr) -> io::Result<Vec<String>> {
+    let name = reverse_name(ip);
+    ask_name_servers(|server| ptr_records(server, &name)).await
+}
+
+/// Adds the names in each host's PTR records to the results, for --rdns.
+pub(crate) struct ReverseDns;
+
+impl Enricher for ReverseDns {
+    fn name(&self) -> &str {
+        "rdns"
+    }
+
+    fn concurrency(&self) -> usize {
+        crate::MAX_RDNS_LOOKUPS
+    }
+
+    fn enrich<'a>(&'a self, ip: IpAddr, _status: &'a PortsStatus) -> Enrichment<'a> {
+        Box::pin(async move {
+            match lookup_ptr(ip).await {
+                Ok(names) if !names.is_empty() => Ok(Some(names.into())),
+                Ok(_) => Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+/// Asks each name server in `/etc/resolv.conf` in turn until one answers,
+/// even if only to say there's nothing to find.
+async fn ask_name_servers<T, F, Fut>(ask: F) -> io::Result<T>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let servers = name_servers()?;
+    let mut last_error = io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no name servers in {RESOLV_CONF}"),
+    );
+
+    for server in servers {
+        match timeout(TIMEOUT, ask(server)).await {
+            Ok(Ok(answer)) => return Ok(answer),
+            Ok(Err(e)) if e.kind() == io::ErrorKind::NotFound => return Err(e),
+            Ok(Err(e)) => last_error = e,
+            Err(_) => last_error = io::Error::new(io::ErrorKind::TimedOut, "no answer"),
+        }
+    }
+
+    Err(last_error)
+}
+
+/// The name PTR records of `ip` are under, e.g. `4.3.2.1.in-addr.arpa` for
+/// 1.2.3.4.
+fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::new();
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name + "ip6.arpa"
+        }
+    }
+}
+
+/// Every IPv4 and IPv6 address `server` says `name` has, sorted.
+pub(crate) async fn lookup_host(name: &str, server: SocketAddr) -> io::Result<Vec<IpAddr>> {
+    let mut addrs = vec![];
+    for kind in [TYPE_A, TYPE_AAAA] {
+        let response = timeout(TIMEOUT, query(server, name, kind))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no answer"))??;
+
+        // names with only one kind of address answer the other with nothing
+        let answers = match answers(&response, kind) {
+            Ok(answers) => answers,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        for range in answers {
+            let data = &response[range];
+            match data.len() {
+                4 => addrs.push(IpAddr::from(Ipv4Addr::from(
+                    <[u8; 4]>::try_from(data).expect("checked length"),
+                ))),
+                16 => addrs.push(IpAddr::from(Ipv6Addr::from(
+                    <[u8; 16]>::try_from(data).expect("checked length"),
+                ))),
+                _ => {}
+            }
+        }
+    }
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses for {name}"),
+        ));
+    }
+
+    addrs.sort();
+    addrs.dedup();
+    Ok(addrs)
+}
+
+fn name_servers() -> io::Result<Vec<SocketAddr>> {
+    let conf = fs::read_to_string(RESOLV_CONF)?;
+    Ok(conf
+        .lines()
+        .filter_map(|line| line.strip_prefix("nameserver"))
+        .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect())
+}
+
+/// The SRV records `server` has for `name`, ordered by priority.
+async fn srv_records(server: SocketAddr, name: &str) -> io::Result<Vec<SrvRecord>> {
+    srv(&query(server, name, TYPE_SRV).await?, name)
+}
+
+/// The SRV records for `name` in `response`, ordered by priority.
+fn srv(response: &[u8], name: &str) -> io::Result<Vec<SrvRecord>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid DNS response");
+    let not_found = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no SRV records for {name}"),
+        )
+    };
+
+    let answers = answers(response, TYPE_SRV).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => not_found(),
+        _ => e,
+    })?;
+
+    let mut records = vec![];
+    for range in answers {
+        let (priority, port) = match &response[range.clone()] {
+            [p1, p2, _, _, o1, o2, ..] => (
+                u16::from_be_bytes([*p1, *p2]),
+                u16::from_be_bytes([*o1, *o2]),
+            ),
+            _ => return Err(invalid()),
+        };
+
+        // the target may point back into the rest of the message
+        let (target, _) = read_name(response, range.start + 6).ok_or_else(invalid)?;
+        // "." means the service isn't offered
+        if !target.is_empty() {
+            records.push((priority, SrvRecord { target, port }));
+        }
+    }
+
+    if records.is_empty() {
+        return Err(not_found());
+    }
+
+    records.sort_by_key(|(priority, _)| *priority);
+    Ok(records.into_iter().map(|(_, record)| record).collect())
+}
+
+/// The names in the PTR records `server` has for `name`.
+async fn ptr_records(server: SocketAddr, name: &str) -> io::Result<Vec<String>> {
+    let response = query(server, name, TYPE_PTR).await?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid DNS response");
+    answers(&response, TYPE_PTR)?
+        .into_iter()
+        .map(|range| Ok(read_name(&response, range.start).ok_or_else(invalid)?.0))
+        .collect()
+}
+
+/// Asks `server` for the `kind` records of `name`, returning the whole
+/// response.
+async fn query(server: SocketAddr, name: &str, kind: u16) -> io::Result<Vec<u8>> {
+    // unguessable, so answers can't be spoofed without seeing the query
+    let id = RandomState::new().build_hasher().finish() as u16;
+    let request = request(id, name, kind)?;
+
+    let local: IpAddr = if server.is_ipv4() {
+        [0; 4].into()
+    } else {
+        [0u16; 8].into()
+    };
+
+    let socket = UdpSocket::bind((local, 0)).await?;
+    socket.connect(server).await?;
+    socket.send(&request).await?;
+
+    let mut buf = vec![0; 1232];
+    let len = loop {
+        let len = socket.recv(&mut buf).await?;
+        if buf[..len].starts_with(&id.to_be_bytes()) {
+            break len;
+        }
+    };
+    buf.truncate(len);
+
+    // truncated, so ask again over TCP
+    if buf.get(2).is_some_and(|flags| flags & 0x02 != 0) {
+        return timeout(TIMEOUT, query_tcp(server, &request))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no answer over TCP"))?;
+    }
+
+    Ok(buf)
+}
+
+/// Sends `request` to `server` over TCP, for answers too big for a datagram.
+async fn query_tcp(server: SocketAddr, request: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(server).await?;
+    let mut framed = (request.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(request);
+    stream.write_all(&framed).await?;
+
+    let len = stream.read_u16().await?;
+    let mut response = vec![0; len as usize];
+    stream.read_exact(&mut response).await?;
+    if response.get(..2) != request.get(..2) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "DNS response to another query",
+        ));
+    }
+
+    Ok(response)
+}
+
+/// A recursive query for the `kind` records of `name`.
+fn request(id: u16, name: &str, kind: u16) -> io::Result<Vec<u8>> {
+    let mut request = id.to_be_bytes().to_vec();
+    // recursion desired, one question
+    request.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid name: {name}"),
+            ));
+        }
+
+        request.push(label.len() as u8);
+        request.extend_from_slice(label.as_bytes());
+    }
+
+    request.push(0);
+    request.extend_from_slice(&kind.to_be_bytes());
+    request.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(request)
+}
+
+/// Where the data of the `kind` records answered in `response` is, failing
+/// with [`io::ErrorKind::NotFound`] if there are none.
+fn answers(response: &[u8], kind: u16) -> io::Result<Vec<Range<usize>>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid DNS response");
+    let u16_at = |at: usize| {
+        response
+            .get(at..at + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(invalid)
+    };
+
+    match response.get(3).ok_or_else(invalid)? & 0x0f {
+        0 | 3 => {}
+        rcode => return Err(io::Error::other(format!("DNS error {rcode}"))),
+    }
+
+    let (questions, count) = (u16_at(4)?, u16_at(6)?);
+    let mut at = 12;
+    for _ in 0..questions {
+        at = read_name(response, at).ok_or_else(invalid)?.1 + 4;
+    }
+
+    let mut answers = vec![];
+    for _ in 0..count {
+        at = read_name(response, at).ok_or_else(invalid)?.1;
+        let (answer_kind, len) = (u16_at(at)?, u16_at(at + 8)? as usize);
+        let data = at + 10..at + 10 + len;
+        if data.end > response.len() {
+            return Err(invalid());
+        }
+        at = data.end;
+
+        // CNAMEs come along with what they point to
+        if answer_kind == kind {
+            answers.push(data);
+        }
+    }
+
+    if answers.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no records"));
+    }
+
+    Ok(answers)
+}
+
+/// Reads the possibly compressed name at `at` in `message`, returning it and
+/// where whatever follows it starts.
+fn read_name(message: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    // pointers can only go back, but a limit keeps loops out anyway
+    for _ in 0..128 {
+        let len = *message.get(at)? as usize;
+        match len {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(at + 1)));
+            }
+            0xc0.. => {
+                let pointer = u16::from_be_bytes([*message.get(at)?, *message.get(at + 1)?]);
+                end.get_or_insert(at + 2);
+                at = (pointer & 0x3fff) as usize;
+            }
+            _ => {
+                let label = message.get(at + 1..at + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                at += 1 + len;
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPE_CNAME: u16 = 5;
+
+    /// A response to a query for the `kind` records of `name`, answering
+    /// with `answers`, each the type and data of a record for the name
+    /// asked about.
+    fn response(name: &str, kind: u16, answers: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut response = request(0x1234, name, kind).unwrap();
+        response[2..4].copy_from_slice(&[0x81, 0x80]);
+        response[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        for (kind, data) in answers {
+            // the name asked about, right after the header
+            response.extend_from_slice(&[0xc0, 12]);
+            response.extend_from_slice(&kind.to_be_bytes());
+            response.extend_from_slice(&CLASS_IN.to_be_bytes());
+            response.extend_from_slice(&300u32.to_be_bytes());
+            response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            response.extend_from_slice(data);
+        }
+
+        response
+    }
+
+    fn srv_data(priority: u16, port: u16, target: &[u8]) -> Vec<u8> {
+        let mut data = priority.to_be_bytes().to_vec();
+        data.extend_from_slice(&[0, 5]); // weight
+        data.extend_from_slice(&port.to_be_bytes());
+        data.extend_from_slice(target);
+        data
+    }
+
+    #[test]
+    fn reads_compressed_names() {
+        let mut message = request(1, "example.com", TYPE_A).unwrap();
+        let www = message.len();
+        message.extend_from_slice(b"\x03www\xc0\x0c");
+        assert_eq!(
+            read_name(&message, 12),
+            Some(("example.com".to_string(), www - 4))
+        );
+        assert_eq!(
+            read_name(&message, www),
+            Some(("www.example.com".to_string(), www + 6))
+        );
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        let mut message = vec![0; 12];
+        // a pointer to itself
+        message.extend_from_slice(&[0xc0, 12]);
+        assert_eq!(read_name(&message, 12), None);
+
+        // and two labels pointing at each other
+        let mut message = vec![0; 12];
+        message.extend_from_slice(b"\x01a\xc0\x10\x01b\xc0\x0c");
+        assert_eq!(read_name(&message, 12), None);
+    }
+
+    #[test]
+    fn rejects_pointers_past_the_end() {
+        let mut message = vec![0; 12];
+        message.extend_from_slice(&[0xc0, 0xff]);
+        assert_eq!(read_name(&message, 12), None);
+        assert_eq!(read_name(&message, 13), None);
+    }
+
+    #[test]
+    fn reads_srv_records_by_priority() {
+        let name = "_ldap._tcp.example.com";
+        // the second target is compressed, pointing at "example.com" in the
+        // question
+        let response = response(
+            name,
+            TYPE_SRV,
+            &[
+                (
+                    TYPE_SRV,
+                    srv_data(20, 3268, b"\x03dc2\x07example\x03com\x00"),
+                ),
+                (TYPE_SRV, srv_data(10, 389, b"\x03dc1\xc0\x17")),
+            ],
+        );
+
+        assert_eq!(
+            srv(&response, name).unwrap(),
+            [
+                SrvRecord {
+                    target: "dc1.example.com".to_string(),
+                    port: 389
+                },
+                SrvRecord {
+                    target: "dc2.example.com".to_string(),
+                    port: 3268
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn srv_target_of_root_means_no_service() {
+        let name = "_xmpp._tcp.example.com";
+        let response = response(name, TYPE_SRV, &[(TYPE_SRV, srv_data(0, 0, b"\x00"))]);
+        let e = srv(&response, name).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn skips_cnames_to_the_records_asked_for() {
+        let response = response(
+            "www.example.com",
+            TYPE_A,
+            &[
+                (TYPE_CNAME, b"\x03web\xc0\x10".to_vec()),
+                (TYPE_A, vec![192, 0, 2, 1]),
+            ],
+        );
+        let answers = answers(&response, TYPE_A).unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(response[answers[0].clone()], [192, 0, 2, 1]);
+    }
+
+    #[test]
+    fn rejects_truncated_answers() {
+        let name = "_sip._udp.example.com";
+        let response = response(
+            name,
+            TYPE_SRV,
+            &[
+                (TYPE_SRV, srv_data(10, 5060, b"\x03sip\xc0\x16")),
+                (TYPE_SRV, srv_data(20, 5060, b"\x04sip2\xc0\x16")),
+            ],
+        );
+        assert!(srv(&response, name).is_ok());
+
+        for len in 0..response.len() {
+            let e = answers(&response[..len], TYPE_SRV).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{len} bytes");
+            assert!(srv(&response[..len], name).is_err(), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn reports_errors_and_empty_answers() {
+        let mut refused = response("example.com", TYPE_A, &[]);
+        refused[3] |= 5;
+        assert_eq!(
+            answers(&refused, TYPE_A).unwrap_err().kind(),
+            io::ErrorKind::Other
+        );
+
+        let mut nxdomain = response("example.com", TYPE_A, &[]);
+        nxdomain[3] |= 3;
+        assert_eq!(
+            answers(&nxdomain, TYPE_A).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn reverses_addresses() {
+        assert_eq!(
+            reverse_name("192.0.2.1".parse().unwrap()),
+            "1.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name("2001:db8::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+}
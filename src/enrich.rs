@@ -0,0 +1,103 @@
+//! Enrichers add what else there is to know about each host once the scan
+//! is done, e.g. its reverse DNS name, where it is or who owns it. Each runs
+//! on its own share of hosts at once and with its own timeout, so a slow one
+//! holds up neither the scan nor the others.
+
+use std::{
+    collections::BTreeMap, future::Future, io, net::IpAddr, pin::Pin, sync::Arc, time::Duration,
+};
+
+use log::{error, trace};
+use serde_json::Value;
+use tokio::{sync::Semaphore, task::JoinSet, time::timeout};
+
+use crate::ports::PortsStatus;
+
+/// What an enricher found out about a host, or `None` if nothing.
+pub type Enrichment<'a> = Pin<Box<dyn Future<Output = io::Result<Option<Value>>> + Send + 'a>>;
+
+/// Looks up something about each host which responded, after the scan.
+/// Enrichers are given to [`PortScannerBuilder::enricher`], and what they
+/// find is in [`PortsStatus::enrichments`] under their name.
+///
+/// ```no_run
+/// use std::net::IpAddr;
+///
+/// use qapper::{Enricher, Enrichment, PortsStatus};
+///
+/// /// Flags hosts running both SSH and Telnet.
+/// struct Legacy;
+///
+/// impl Enricher for Legacy {
+///     fn name(&self) -> &str {
+///         "legacy"
+///     }
+///
+///     fn enrich<'a>(&'a self, _ip: IpAddr, status: &'a PortsStatus) -> Enrichment<'a> {
+///         let legacy = status.open().contains(&22) && status.open().contains(&23);
+///         Box::pin(async move { Ok(legacy.then(|| "telnet next to ssh".into())) })
+///     }
+/// }
+/// ```
+///
+/// [`PortScannerBuilder::enricher`]: crate::PortScannerBuilder::enricher
+pub trait Enricher: Send + Sync {
+    /// What its results are listed under, e.g. `rdns`.
+    fn name(&self) -> &str;
+
+    /// How many hosts to enrich at once.
+    fn concurrency(&self) -> usize {
+        DEFAULT_CONCURRENCY
+    }
+
+    /// How long each host may take, after which it's left without.
+    fn timeout(&self) -> Duration {
+        DEFAULT_TIMEOUT
+    }
+
+    fn enrich<'a>(&'a self, ip: IpAddr, status: &'a PortsStatus) -> Enrichment<'a>;
+}
+
+const DEFAULT_CONCURRENCY: usize = 16;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs every enricher on every host in `hosts`, one enricher after the
+/// other. Hosts they fail, time out or panic on are left without.
+pub(crate) async fn run(
+    enrichers: &[Arc<dyn Enricher>],
+    hosts: &mut BTreeMap<IpAddr, PortsStatus>,
+) {
+    for enricher in enrichers {
+        let name = enricher.name().to_string();
+        let limit = Arc::new(Semaphore::new(enricher.concurrency().max(1)));
+        let mut enriching = JoinSet::new();
+        for (&ip, status) in hosts.iter() {
+            let enricher = Arc::clone(enricher);
+            let limit = Arc::clone(&limit);
+            let status = status.clone();
+            enriching.spawn(async move {
+                let _permit = limit.acquire().await.unwrap();
+                (
+                    ip,
+                    timeout(enricher.timeout(), enricher.enrich(ip, &status)).await,
+                )
+            });
+        }
+
+        while let Some(enriched) = enriching.join_next().await {
+            match enriched {
+                Ok((ip, Ok(Ok(Some(value))))) => {
+                    if let Some(status) = hosts.get_mut(&ip) {
+                        status.enrich(name.clone(), value);
+                    }
+                }
+                Ok((_, Ok(Ok(None)))) => {}
+                Ok((ip, Ok(Err(e)))) => trace!("Failed to enrich {ip} with {name}: {e}"),
+                Ok((ip, Err(_))) => trace!("Enriching {ip} with {name} timed out"),
+                // the enricher panicked, which leaves that host without
+                Err(e) => error!("Enriching a host with {name} failed: {e}"),
+            }
+        }
+    }
+}
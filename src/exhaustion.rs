@@ -0,0 +1,281 @@
+//! Recovering from running out of sockets, e.g. file descriptors (EMFILE) or
+//! buffer space (ENOBUFS), mid-scan. Connects failing that way say nothing
+//! about the port, so they're retried after backing off, and fewer ports are
+//! checked at once from then on. Scans are kept within the file descriptor
+//! limit to begin with, so that's rarely needed.
+
+use std::{io, sync::Mutex, time::Duration};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+/// How long to back off before the first retry, doubled for each one after.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Times a port is retried before giving up on it.
+const MAX_RETRIES: u32 = 5;
+
+/// File descriptors left for everything but checking ports, e.g. pinging,
+/// logging and writing results.
+const RESERVED_FDS: u64 = 64;
+
+/// File descriptors each port being checked may hold at once, as some probes
+/// connect again before the first connection is closed.
+const FDS_PER_PORT: u64 = 2;
+
+/// How often the scan ran out of sockets, reported in
+/// [`ScanReport::exhaustion`](crate::ScanReport::exhaustion).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocketExhaustion {
+    /// How many connects failed for lack of sockets.
+    pub failures: usize,
+    /// How many ports were checked again after backing off.
+    pub retried: usize,
+    /// Ports still failing after every retry, left out of the results.
+    pub given_up: usize,
+    /// How many ports were checked at once by the end of the scan.
+    pub concurrency: usize,
+}
+
+/// Counts the ports being checked, limiting them once the system runs out
+/// of sockets.
+#[derive(Debug, Default)]
+pub(crate) struct SocketLimit {
+    state: Mutex<LimitState>,
+    freed: Notify,
+}
+
+#[derive(Debug, Default)]
+struct LimitState {
+    in_use: usize,
+    /// Unlimited until sockets run out.
+    limit: Option<usize>,
+    failures: usize,
+    retried: usize,
+    given_up: usize,
+}
+
+/// A port being checked, no longer counted once dropped.
+pub(crate) struct SocketPermit<'a> {
+    limit: &'a SocketLimit,
+}
+
+impl SocketLimit {
+    /// Waits until another port may be checked.
+    pub(crate) async fn acquire(&self) -> SocketPermit<'_> {
+        loop {
+            // registered before checking, so a permit freed in between wakes it
+            let freed = self.freed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.limit.is_none_or(|limit| state.in_use < limit) {
+                    state.in_use += 1;
+                    return SocketPermit { limit: self };
+                }
+            }
+
+            freed.await;
+        }
+    }
+
+    /// Notes that checking a port failed for lack of sockets on its
+    /// `attempt`th retry, halving how many ports are checked at once.
+    /// Returns how long to back off before retrying, or `None` to give up.
+    pub(crate) fn exhausted(&self, attempt: u32, e: &io::Error) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+
+        // about as many sockets as the system allows are open right now
+        let limit = (state.in_use / 2).max(1);
+        if state.limit.is_none_or(|current| limit < current) {
+            error!("Ran out of sockets ({e}), checking at most {limit} ports at once from now on");
+            state.limit = Some(limit);
+        }
+
+        if attempt >= MAX_RETRIES {
+            state.given_up += 1;
+            return None;
+        }
+
+        state.retried += 1;
+        Some(MIN_BACKOFF * 2u32.pow(attempt))
+    }
+
+    /// What happened, if sockets ever ran out.
+    pub(crate) fn report(&self) -> Option<SocketExhaustion> {
+        let state = self.state.lock().unwrap();
+        state.limit.map(|concurrency| SocketExhaustion {
+            failures: state.failures,
+            retried: state.retried,
+            given_up: state.given_up,
+            concurrency,
+        })
+    }
+}
+
+impl Drop for SocketPermit<'_> {
+    fn drop(&mut self) {
+        self.limit.state.lock().unwrap().in_use -= 1;
+        self.limit.freed.notify_waiters();
+    }
+}
+
+/// Whether `e` means the system ran out of sockets, file descriptors or
+/// local ports rather than saying anything about the port.
+pub(crate) fn is_exhaustion(e: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    if let Some(code) = e.raw_os_error() {
+        if [libc::EMFILE, libc::ENFILE, libc::ENOBUFS].contains(&code) {
+            return true;
+        }
+    }
+
+    // every ephemeral port is taken
+    e.kind() == io::ErrorKind::AddrNotAvailable
+}
+
+/// How many ports can be checked at once without running out of file
+/// descriptors, if the system limits them.
+pub(crate) fn max_concurrency() -> Option<usize> {
+    let limit = open_files_limit()?;
+    Some((limit.saturating_sub(RESERVED_FDS) / FDS_PER_PORT).max(1) as usize)
+}
+
+/// The soft limit on open file descriptors (RLIMIT_NOFILE), if there is one.
+#[cfg(target_os = "linux")]
+fn open_files_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    // SAFETY: the kernel only writes to `limit`
+    let ret = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+    (ret == 0 && limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_files_limit() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{timeout, Instant};
+
+    use super::*;
+
+    fn emfile() -> io::Error {
+        io::Error::other("Too many open files")
+    }
+
+    async fn permits(limit: &SocketLimit, count: usize) -> Vec<SocketPermit<'_>> {
+        let mut permits = vec![];
+        for _ in 0..count {
+            permits.push(limit.acquire().await);
+        }
+
+        permits
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn is_unlimited_until_sockets_run_out() {
+        let limit = SocketLimit::default();
+        let start = Instant::now();
+        assert_eq!(permits(&limit, 1000).await.len(), 1000);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert_eq!(limit.report(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn halves_concurrency_when_sockets_run_out() {
+        let limit = SocketLimit::default();
+        let mut permits = permits(&limit, 8).await;
+        assert_eq!(limit.exhausted(0, &emfile()), Some(MIN_BACKOFF));
+
+        // at most 4 ports at once from now on
+        permits.truncate(4);
+        assert!(timeout(Duration::from_secs(60), limit.acquire())
+            .await
+            .is_err());
+
+        // freeing one lets the next through, without waiting
+        permits.pop();
+        let start = Instant::now();
+        permits.push(limit.acquire().await);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // running out again with fewer in use lowers it further
+        permits.truncate(2);
+        limit.exhausted(0, &emfile());
+        drop(permits);
+        let permit = limit.acquire().await;
+        limit.exhausted(0, &emfile());
+        assert!(timeout(Duration::from_secs(60), limit.acquire())
+            .await
+            .is_err());
+        drop(permit);
+
+        assert_eq!(
+            limit.report(),
+            Some(SocketExhaustion {
+                failures: 3,
+                retried: 3,
+                given_up: 0,
+                concurrency: 1,
+            })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wakes_waiters_as_permits_free_up() {
+        let limit = SocketLimit::default();
+        let permit = limit.acquire().await;
+        limit.exhausted(0, &emfile());
+
+        let (_, waited) = tokio::join!(
+            async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                drop(permit);
+            },
+            async {
+                let start = Instant::now();
+                let _permit = limit.acquire().await;
+                start.elapsed()
+            }
+        );
+        assert_eq!(waited, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn backs_off_then_gives_up() {
+        let limit = SocketLimit::default();
+        let backoffs: Vec<_> = (0..=MAX_RETRIES)
+            .map(|attempt| limit.exhausted(attempt, &emfile()))
+            .collect();
+        assert_eq!(
+            backoffs,
+            [100, 200, 400, 800, 1600]
+                .map(|millis| Some(Duration::from_millis(millis)))
+                .into_iter()
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+
+        let report = limit.report().unwrap();
+        assert_eq!((report.retried, report.given_up), (5, 1));
+    }
+
+    #[test]
+    fn recognizes_exhaustion() {
+        assert!(is_exhaustion(&io::ErrorKind::AddrNotAvailable.into()));
+        assert!(!is_exhaustion(&io::ErrorKind::ConnectionRefused.into()));
+        assert!(!is_exhaustion(&io::ErrorKind::TimedOut.into()));
+
+        #[cfg(target_os = "linux")]
+        for code in [libc::EMFILE, libc::ENFILE, libc::ENOBUFS] {
+            assert!(is_exhaustion(&io::Error::from_raw_os_error(code)));
+        }
+    }
+}
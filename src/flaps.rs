@@ -0,0 +1,197 @@
+//! Flap detection for --watch: ports seen to open or close are checked
+//! again several times before the next scan, and for as long as they keep
+//! flipping, so services which crash-loop show up as flapping rather than
+//! as a port that happened to be open, or closed, each time it was scanned.
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use qapper::{Change, PortsStatus, Protocol, ScanReport};
+
+/// A port of a host, e.g. `(10.0.0.1, tcp, 443)`.
+type Port = (IpAddr, Protocol, u16);
+
+#[derive(Debug, Default)]
+pub(crate) struct Flaps {
+    watched: BTreeMap<Port, Flapping>,
+}
+
+#[derive(Debug)]
+struct Flapping {
+    open: bool,
+    /// How often the port opened or closed since it was first seen to.
+    flips: u32,
+    /// `flips` as of the previous scan.
+    scanned: u32,
+    /// `flips` as last reported.
+    reported: u32,
+}
+
+impl Flaps {
+    /// The ports to check again before the next scan, by host.
+    pub(crate) fn watched(&self) -> impl Iterator<Item = Port> + '_ {
+        self.watched.keys().copied()
+    }
+
+    /// Takes in a full scan, which found `changes` since the one before.
+    /// Ports which flipped start being watched, those which didn't flip
+    /// since the previous scan stop, and those which keep flipping are
+    /// returned as [`Change::Flapping`].
+    pub(crate) fn scanned(&mut self, report: &ScanReport, changes: &[Change]) -> Vec<Change> {
+        self.checked(report);
+        self.watched
+            .retain(|_, flapping| flapping.flips > flapping.scanned);
+
+        // hosts just up list all their open ports as opened
+        let new_hosts: Vec<IpAddr> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::HostUp { ip } => Some(*ip),
+                _ => None,
+            })
+            .collect();
+
+        for change in changes {
+            let (port, open) = match *change {
+                Change::Opened { ip, port, protocol } => ((ip, protocol, port), true),
+                Change::Closed { ip, port, protocol } => ((ip, protocol, port), false),
+                _ => continue,
+            };
+
+            if !new_hosts.contains(&port.0) {
+                self.watched.entry(port).or_insert(Flapping {
+                    open,
+                    flips: 1,
+                    scanned: 0,
+                    reported: 1,
+                });
+            }
+        }
+
+        let mut flapping = vec![];
+        for (&(ip, protocol, port), watched) in &mut self.watched {
+            if watched.flips > watched.reported {
+                flapping.push(Change::Flapping {
+                    ip,
+                    port,
+                    protocol,
+                    flips: watched.flips,
+                });
+                watched.reported = watched.flips;
+            }
+
+            watched.scanned = watched.flips;
+        }
+
+        flapping
+    }
+
+    /// Counts the watched ports which opened or closed since they were last
+    /// checked, going by `report`, a scan of them or of everything.
+    pub(crate) fn checked(&mut self, report: &ScanReport) {
+        for (&(ip, protocol, port), watched) in &mut self.watched {
+            let status = report.hosts.get(&ip).and_then(|status| match protocol {
+                Protocol::Udp => status.udp(),
+                _ => Some(status),
+            });
+
+            // hosts gone quiet count as closed, ports not checked as before
+            let open = match status {
+                Some(status) if was_checked(status, port) => {
+                    status.open().binary_search(&port).is_ok()
+                }
+                Some(_) => continue,
+                None => false,
+            };
+
+            if open != watched.open {
+                watched.open = open;
+                watched.flips += 1;
+            }
+        }
+    }
+}
+
+// reports keep their ports sorted
+fn was_checked(status: &PortsStatus, port: u16) -> bool {
+    status.open().binary_search(&port).is_ok() || status.closed().binary_search(&port).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+
+    fn report(open: &[u16], closed: &[u16]) -> ScanReport {
+        let mut report = ScanReport::default();
+        let status = json!({ "open": open, "closed": closed });
+        report
+            .hosts
+            .insert(IP, serde_json::from_value(status).unwrap());
+        report
+    }
+
+    fn opened(port: u16) -> Change {
+        Change::Opened {
+            ip: IP,
+            port,
+            protocol: Protocol::Tcp,
+        }
+    }
+
+    #[test]
+    fn watches_ports_which_flip() {
+        let mut flaps = Flaps::default();
+        assert!(flaps.scanned(&report(&[80], &[]), &[opened(80)]).is_empty());
+        assert_eq!(
+            flaps.watched().collect::<Vec<_>>(),
+            [(IP, Protocol::Tcp, 80)]
+        );
+    }
+
+    #[test]
+    fn counts_flips_between_scans() {
+        let mut flaps = Flaps::default();
+        flaps.scanned(&report(&[80], &[]), &[opened(80)]);
+        flaps.checked(&report(&[], &[80]));
+        flaps.checked(&report(&[80], &[]));
+        flaps.checked(&report(&[], &[80]));
+
+        assert_eq!(
+            flaps.scanned(&report(&[80], &[]), &[]),
+            [Change::Flapping {
+                ip: IP,
+                port: 80,
+                protocol: Protocol::Tcp,
+                flips: 5
+            }]
+        );
+    }
+
+    #[test]
+    fn stops_watching_ports_which_settle() {
+        let mut flaps = Flaps::default();
+        flaps.scanned(&report(&[80], &[]), &[opened(80)]);
+        flaps.checked(&report(&[80], &[]));
+        assert!(flaps.scanned(&report(&[80], &[]), &[]).is_empty());
+        assert_eq!(flaps.watched().count(), 0);
+    }
+
+    #[test]
+    fn ignores_ports_of_new_hosts() {
+        let mut flaps = Flaps::default();
+        let changes = [Change::HostUp { ip: IP }, opened(80)];
+        flaps.scanned(&report(&[80], &[]), &changes);
+        assert_eq!(flaps.watched().count(), 0);
+    }
+
+    #[test]
+    fn ignores_ports_not_checked() {
+        let mut flaps = Flaps::default();
+        flaps.scanned(&report(&[80], &[]), &[opened(80)]);
+        flaps.checked(&report(&[], &[443]));
+        assert!(flaps.scanned(&report(&[80], &[]), &[]).is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+//! Shell completions, a man page and example config files, built from the
+//! command line definition at runtime so they always match the flags of the
+//! version installed.
+
+use std::io::{self, Write};
+
+use clap::{Arg, ArgAction, Command, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use clap_mangen::Man;
+
+use crate::config::FileConfig;
+
+/// Where config file comments wrap.
+const WIDTH: usize = 78;
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Artifact {
+    /// Completions for a shell, e.g. "qapper generate completions bash > /etc/bash_completion.d/qapper"
+    Completions { shell: Shell },
+
+    /// A man page, e.g. "qapper generate man > /usr/local/share/man/man1/qapper.1"
+    Man,
+
+    /// A config file for a kind of scan, listing every option it can set with its help and default, e.g. "qapper generate plan web > qapper.toml"
+    Plan {
+        #[arg(value_enum, default_value_t = Plan::Full)]
+        plan: Plan,
+    },
+}
+
+/// Kinds of scans there are example config files for.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub(crate) enum Plan {
+    /// Nothing set, every option left at its default
+    Full,
+    /// A fast sweep of the most common ports, showing only open ones
+    Quick,
+    /// Web servers, with TLS and HTTP probes
+    Web,
+    /// Identifying what runs on common TCP and UDP ports, saved as JSON
+    Services,
+}
+
+impl Plan {
+    /// The options set, as TOML values.
+    fn options(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::Full => &[],
+            Self::Quick => &[("top_ports", "20"), ("timeout", "300"), ("open", "true")],
+            Self::Web => &[
+                ("ports", r#""http,https,8000-8999,8443""#),
+                ("service_detect", "true"),
+                ("tls_probe", "true"),
+                ("http_probe", "true"),
+            ],
+            Self::Services => &[
+                ("top_ports", "100"),
+                ("udp", r#""domain,ntp,snmp,isakmp""#),
+                ("service_detect", "true"),
+                ("banner", "true"),
+                ("format", r#""json""#),
+            ],
+        }
+    }
+}
+
+/// Writes `artifact` for `cmd`, the command line of qapper, to `out`.
+pub(crate) fn write(artifact: &Artifact, mut cmd: Command, out: &mut impl Write) -> io::Result<()> {
+    match artifact {
+        Artifact::Completions { shell } => {
+            let name = cmd.get_name().to_string();
+            clap_complete::generate(*shell, &mut cmd, name, out);
+            Ok(())
+        }
+        Artifact::Man => Man::new(cmd).render(out),
+        Artifact::Plan { plan } => {
+            let text = plan_file(*plan, &cmd);
+            toml::from_str::<FileConfig>(&text).expect("example plans only set known options");
+            out.write_all(text.as_bytes())
+        }
+    }
+}
+
+/// A config file for `plan`, with every option it could set commented out
+/// unless the plan sets it.
+fn plan_file(plan: Plan, cmd: &Command) -> String {
+    let version = cmd.get_version().unwrap_or_default();
+    let about = plan
+        .to_possible_value()
+        .and_then(|value| value.get_help().map(ToString::to_string))
+        .unwrap_or_default();
+
+    let mut text = format!(
+        "# qapper {version} config: {about}.\n\
+         # Pass it with --config, or save it as qapper.toml where scans are run.\n\
+         # Options given on the command line or as QAPPER_* environment\n\
+         # variables take precedence.\n"
+    );
+
+    for &key in FileConfig::keys() {
+        let Some(arg) = cmd.get_arguments().find(|arg| arg.get_id() == key) else {
+            continue;
+        };
+
+        text.push('\n');
+        let help = arg.get_help().map(ToString::to_string).unwrap_or_default();
+        for line in wrap(&help, WIDTH - 2) {
+            text.push_str(&format!("# {line}\n"));
+        }
+
+        match plan.options().iter().find(|(name, _)| *name == key) {
+            Some((_, value)) => text.push_str(&format!("{key} = {value}\n")),
+            None => text.push_str(&format!("# {key} = {}\n", default_value(arg))),
+        }
+    }
+
+    text
+}
+
+/// The default of `arg` as a TOML value, or `...` if it has none.
+fn default_value(arg: &Arg) -> String {
+    if matches!(arg.get_action(), ArgAction::SetTrue) {
+        return "false".to_string();
+    }
+
+    let Some(value) = arg.get_default_values().first() else {
+        return "...".to_string();
+    };
+
+    let value = value.to_string_lossy();
+    if value.parse::<f64>().is_ok() {
+        value.into_owned()
+    } else {
+        format!("{value:?}")
+    }
+}
+
+/// `text` split into lines of at most `width` characters, unless a word is
+/// longer.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
@@ -0,0 +1,69 @@
+//! Quickly scan open ports on a set of IP addresses.
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use qapper::{PortScanner, Ports};
+//!
+//! let ports: Ports = "22,80,443".parse().unwrap();
+//! let addrs = vec!["127.0.0.1".parse().unwrap()];
+//!
+//! let scanner = PortScanner::builder()
+//!     .ports(ports)
+//!     .targets(addrs)
+//!     .timeout(1000)
+//!     .build()?;
+//!
+//! for (ip, status) in scanner.scan().await?.hosts {
+//!     println!("{ip}: {status}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod capabilities;
+mod credentials;
+mod diff;
+mod enrich;
+mod exhaustion;
+mod fake;
+mod mac;
+mod names;
+mod os;
+mod pacing;
+mod policy;
+mod port_rules;
+mod ports;
+mod probes;
+mod rfc3339;
+mod sample;
+mod scanner;
+#[cfg(feature = "tower")]
+mod service;
+mod services;
+mod sink;
+mod slots;
+mod stats;
+
+pub use capabilities::{capabilities, Capability};
+pub use credentials::Credentials;
+pub use diff::Change;
+pub use enrich::{Enricher, Enrichment};
+pub use exhaustion::SocketExhaustion;
+pub use mac::MacAddress;
+pub use names::NameStatus;
+pub use os::{OsFamily, OsGuess};
+pub use policy::{Policy, PolicyEffect, Protocol};
+pub use port_rules::PortRules;
+pub use ports::{ParsePortsError, Ports, PortsStatus};
+pub use probes::{HttpInfo, Service, SourceAddress, TlsInfo};
+pub use sample::{sample_subnet, subnet_hosts, SampleStatus, MAX_SUBNET_HOSTS};
+pub use scanner::{PortScanner, PortScannerBuilder, ScanEvent, ScanReport};
+#[cfg(feature = "tower")]
+pub use service::{ScanRequest, ScanService};
+pub use services::{service_name, service_port};
+pub use sink::{CheckpointSink, CsvSink, Facility, JsonSink, ResultSink, SyslogSink, TextSink};
+pub use slots::{ProbeSlots, SlotStats};
+pub use stats::{group_digits, ScanStats};
+/// Re-exported so cancelling scans doesn't need the same version of
+/// `tokio-util` as qapper, see [`PortScanner::cancellation_token`].
+pub use tokio_util::sync::CancellationToken;
@@ -0,0 +1,142 @@
+//! Logs to stderr, as text for people or as one JSON object per line for log
+//! shippers, e.g.
+//!
+//! ```json
+//! {"level":"ERROR","message":"...","target":"qapper::scanner","time":"2024-05-01T12:00:00.123Z"}
+//! ```
+//!
+//! Messages about addresses given with --trace-target are logged whatever
+//! the level.
+
+use std::{
+    io::{self, Write},
+    net::IpAddr,
+    time::SystemTime,
+};
+
+use clap::ValueEnum;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde::Deserialize;
+use serde_json::json;
+use simplelog::{ColorChoice, ConfigBuilder, TermLogger, TerminalMode};
+
+/// Too chatty to be worth logging.
+const IGNORED: &str = "rustls";
+
+#[derive(Clone, Copy, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Logs messages up to `filter` in `format`, and every message mentioning
+/// one of `traced`.
+pub(crate) fn init(
+    filter: LevelFilter,
+    format: LogFormat,
+    traced: &[IpAddr],
+) -> Result<(), SetLoggerError> {
+    let max = if traced.is_empty() {
+        filter
+    } else {
+        LevelFilter::Trace
+    };
+
+    let logger: Box<dyn Log> = match format {
+        LogFormat::Text => {
+            let config = ConfigBuilder::new()
+                .set_level_padding(simplelog::LevelPadding::Off)
+                .set_time_level(LevelFilter::Off)
+                .set_location_level(LevelFilter::Off)
+                .add_filter_ignore_str(IGNORED)
+                .build();
+
+            // logs never go to stdout, where results may be piped
+            TermLogger::new(max, config, TerminalMode::Stderr, ColorChoice::Auto)
+        }
+        LogFormat::Json => Box::new(JsonLogger { filter: max }),
+    };
+
+    log::set_max_level(max);
+    if traced.is_empty() {
+        log::set_boxed_logger(logger)
+    } else {
+        log::set_boxed_logger(Box::new(TargetLogger {
+            inner: logger,
+            filter,
+            traced: traced.iter().map(ToString::to_string).collect(),
+        }))
+    }
+}
+
+struct JsonLogger {
+    filter: LevelFilter,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter && !metadata.target().starts_with(IGNORED)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = json!({
+            "time": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        });
+
+        // one write per line, so lines from different threads don't mix
+        let _ = io::stderr()
+            .lock()
+            .write_all(format!("{line}\n").as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Passes on messages up to `filter`, and those of any level which mention
+/// one of the `traced` addresses.
+struct TargetLogger {
+    inner: Box<dyn Log>,
+    filter: LevelFilter,
+    traced: Vec<String>,
+}
+
+impl TargetLogger {
+    /// Whether `message` mentions a traced address on its own, e.g.
+    /// `10.0.0.5:22` but not `10.0.0.50`.
+    fn mentions_traced(&self, message: &str) -> bool {
+        let part_of_address = |c: char| c.is_ascii_hexdigit() || c == '.';
+        self.traced.iter().any(|addr| {
+            message.match_indices(addr.as_str()).any(|(at, _)| {
+                let before = message[..at].chars().next_back();
+                let after = message[at + addr.len()..].chars().next();
+                !before.is_some_and(part_of_address) && !after.is_some_and(part_of_address)
+            })
+        })
+    }
+}
+
+impl Log for TargetLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= self.filter || self.mentions_traced(&record.args().to_string()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
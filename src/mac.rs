@@ -0,0 +1,321 @@
+//! MAC addresses of hosts on the local network, from the kernel's neighbor
+//! cache, which pinging and connecting to them fills in, and the vendors they
+//! were assigned to.
+
+use std::{collections::HashMap, fmt::Display, fs, net::IpAddr, process::Command, sync::LazyLock};
+
+use serde::{Deserialize, Serialize};
+
+/// OUI databases shipped with nmap, Wireshark and the IEEE data packages,
+/// the first found of which is used instead of [`VENDORS`].
+const OUI_FILES: &[&str] = &[
+    "/usr/share/nmap/nmap-mac-prefixes",
+    "/usr/share/wireshark/manuf",
+    "/usr/share/ieee-data/oui.txt",
+    "/usr/share/hwdata/oui.txt",
+];
+
+/// Vendors commonly found on office and home networks, by OUI. Sorted so it
+/// can be binary searched.
+const VENDORS: &[(u32, &str)] = &[
+    (0x00000C, "Cisco"),
+    (0x000048, "Seiko Epson"),
+    (0x000074, "Ricoh"),
+    (0x000085, "Canon"),
+    (0x0000AA, "Xerox"),
+    (0x0000BC, "Rockwell Automation"),
+    (0x000393, "Apple"),
+    (0x000400, "Lexmark"),
+    (0x00044B, "NVIDIA"),
+    (0x0004F2, "Polycom"),
+    (0x000569, "VMware"),
+    (0x00090F, "Fortinet"),
+    (0x000B82, "Grandstream"),
+    (0x000B86, "Aruba Networks"),
+    (0x000C29, "VMware"),
+    (0x000C42, "MikroTik"),
+    (0x000DB9, "PC Engines"),
+    (0x000E58, "Sonos"),
+    (0x000E8C, "Siemens"),
+    (0x001018, "Broadcom"),
+    (0x001124, "Apple"),
+    (0x001132, "Synology"),
+    (0x001422, "Dell"),
+    (0x00155D, "Microsoft Hyper-V"),
+    (0x001565, "Yealink"),
+    (0x00156D, "Ubiquiti"),
+    (0x00163E, "Xen"),
+    (0x001788, "Philips Lighting"),
+    (0x00180A, "Cisco Meraki"),
+    (0x001A11, "Google"),
+    (0x001A1E, "Aruba Networks"),
+    (0x001B17, "Palo Alto Networks"),
+    (0x001BA9, "Brother"),
+    (0x001C14, "VMware"),
+    (0x001E8F, "Canon"),
+    (0x002590, "Super Micro"),
+    (0x002673, "Ricoh"),
+    (0x002722, "Ubiquiti"),
+    (0x003048, "Super Micro"),
+    (0x00408C, "Axis Communications"),
+    (0x005056, "VMware"),
+    (0x0080F4, "Schneider Electric"),
+    (0x00907F, "WatchGuard"),
+    (0x0090A9, "Western Digital"),
+    (0x00C0EE, "Kyocera"),
+    (0x00E04C, "Realtek"),
+    (0x080027, "VirtualBox"),
+    (0x18B430, "Nest Labs"),
+    (0x240AC4, "Espressif"),
+    (0x24A43C, "Ubiquiti"),
+    (0x28CDC1, "Raspberry Pi"),
+    (0x30AEA4, "Espressif"),
+    (0x3C5AB4, "Google"),
+    (0x44D9E7, "Ubiquiti"),
+    (0x4C5E0C, "MikroTik"),
+    (0x525400, "QEMU/KVM"),
+    (0x5CCF7F, "Espressif"),
+    (0x687251, "Ubiquiti"),
+    (0x802AA8, "Ubiquiti"),
+    (0x84F3EB, "Espressif"),
+    (0xA4CF12, "Espressif"),
+    (0xAC1F6B, "Super Micro"),
+    (0xACCC8E, "Axis Communications"),
+    (0xB827EB, "Raspberry Pi"),
+    (0xB8A44F, "Axis Communications"),
+    (0xD83ADD, "Raspberry Pi"),
+    (0xDC9FDB, "Ubiquiti"),
+    (0xDCA632, "Raspberry Pi"),
+    (0xE45F01, "Raspberry Pi"),
+    (0xEC086B, "TP-Link"),
+    (0xECFABC, "Espressif"),
+    (0xF09FC2, "Ubiquiti"),
+    (0xF4F5D8, "Google"),
+    (0xFCECDA, "Ubiquiti"),
+];
+
+/// Vendors by OUI from the first of the [`OUI_FILES`] there is, if any.
+static OUI_FILE: LazyLock<Option<HashMap<u32, String>>> = LazyLock::new(|| {
+    OUI_FILES
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .map(|text| text.lines().filter_map(parse_oui_line).collect())
+});
+
+/// A host's MAC address and who it was assigned to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MacAddress {
+    /// E.g. `00:11:32:0a:bc:de`.
+    pub address: String,
+    /// The vendor the address was assigned to, or `locally administered`
+    /// for addresses made up by the host, like randomized Wi-Fi addresses and
+    /// those of most virtual machines and containers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+}
+
+impl MacAddress {
+    fn new(octets: [u8; 6]) -> Self {
+        let address = octets
+            .iter()
+            .map(|octet| format!("{octet:02x}"))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        Self {
+            address,
+            vendor: vendor(octets),
+        }
+    }
+}
+
+impl Display for MacAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.vendor {
+            Some(vendor) => write!(f, "{} ({vendor})", self.address),
+            None => write!(f, "{}", self.address),
+        }
+    }
+}
+
+/// The MAC addresses of the hosts the kernel has resolved, IPv4 ones from
+/// ARP and IPv6 ones from NDP. Empty where there's no way to tell.
+pub(crate) fn neighbors() -> HashMap<IpAddr, MacAddress> {
+    let mut neighbors = HashMap::new();
+
+    // IP address, HW type, flags, HW address, mask, device
+    if let Ok(arp) = fs::read_to_string("/proc/net/arp") {
+        for line in arp.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // flags other than ATF_COM are entries still waiting to resolve
+            if let [ip, _, "0x2" | "0x6", mac, ..] = fields[..] {
+                if let (Ok(ip), Some(mac)) = (ip.parse(), parse_mac(mac)) {
+                    neighbors.insert(ip, MacAddress::new(mac));
+                }
+            }
+        }
+    }
+
+    // the NDP cache is only exposed over netlink, which `ip` speaks
+    if let Ok(output) = Command::new("ip").args(["-6", "neigh", "show"]).output() {
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let mut fields = line.split_whitespace();
+            let ip = fields.next().and_then(|ip| ip.parse().ok());
+            let mac = fields
+                .skip_while(|&field| field != "lladdr")
+                .nth(1)
+                .and_then(parse_mac);
+
+            if let (Some(ip), Some(mac)) = (ip, mac) {
+                neighbors.insert(ip, MacAddress::new(mac));
+            }
+        }
+    }
+
+    neighbors
+}
+
+/// Who `mac` was assigned to, if known.
+fn vendor(mac: [u8; 6]) -> Option<String> {
+    let oui = u32::from_be_bytes([0, mac[0], mac[1], mac[2]]);
+    if let Some(vendors) = &*OUI_FILE {
+        if let Some(vendor) = vendors.get(&oui) {
+            return Some(vendor.clone());
+        }
+    } else if let Ok(idx) = VENDORS.binary_search_by_key(&oui, |(oui, _)| *oui) {
+        return Some(VENDORS[idx].1.to_string());
+    }
+
+    // the U/L bit
+    (mac[0] & 0x02 != 0).then(|| "locally administered".to_string())
+}
+
+/// `mac` written like `00:11:32:0a:bc:de`, unless it's all zeros.
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let octets: Vec<u8> = mac
+        .split(':')
+        .map(|octet| u8::from_str_radix(octet, 16).ok())
+        .collect::<Option<_>>()?;
+
+    let octets: [u8; 6] = octets.try_into().ok()?;
+    (octets != [0; 6]).then_some(octets)
+}
+
+/// An OUI and its vendor from a line of any of the [`OUI_FILES`], e.g.
+/// `001132 Synology` (nmap), `00:11:32\tSynology\tSynology Incorporated`
+/// (Wireshark) or `00-11-32   (hex)\t\tSynology Incorporated` (IEEE).
+fn parse_oui_line(line: &str) -> Option<(u32, String)> {
+    let (prefix, rest) = line.split_once(char::is_whitespace)?;
+    let hex: String = prefix.chars().filter(|c| *c != ':' && *c != '-').collect();
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let oui = u32::from_str_radix(&hex, 16).ok()?;
+    let rest = rest.trim().trim_start_matches("(hex)").trim();
+    // Wireshark's long names come after the short ones
+    let vendor = rest.rsplit('\t').next()?.trim();
+    (!vendor.is_empty()).then(|| (oui, vendor.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_vendors_sorted() {
+        assert!(VENDORS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn parses_macs() {
+        for (mac, expected) in [
+            (
+                "00:11:32:0a:bc:de",
+                Some([0x00, 0x11, 0x32, 0x0a, 0xbc, 0xde]),
+            ),
+            ("B8:27:EB:1:2:3", Some([0xb8, 0x27, 0xeb, 0x01, 0x02, 0x03])),
+            // incomplete ARP entries
+            ("00:00:00:00:00:00", None),
+            ("00:11:32:0a:bc", None),
+            ("00:11:32:0a:bc:de:f0", None),
+            ("00-11-32-0a-bc-de", None),
+            ("00:11:32:0a:bc:xx", None),
+            ("", None),
+        ] {
+            assert_eq!(parse_mac(mac), expected, "{mac:?}");
+        }
+    }
+
+    #[test]
+    fn parses_oui_lines() {
+        for (line, expected) in [
+            ("001132 Synology", Some((0x001132, "Synology"))),
+            (
+                "00:11:32\tSynology\tSynology Incorporated",
+                Some((0x001132, "Synology Incorporated")),
+            ),
+            (
+                "00-11-32   (hex)\t\tSynology Incorporated",
+                Some((0x001132, "Synology Incorporated")),
+            ),
+            (
+                "B8:27:EB\tRaspberr\tRaspberry Pi Foundation",
+                Some((0xb827eb, "Raspberry Pi Foundation")),
+            ),
+            // comments, blank lines and longer prefixes
+            ("# Wireshark manuf", None),
+            ("", None),
+            ("001132", None),
+            ("00:11:32 ", None),
+            ("00:1B:C5:00:00:00/36\tConverg\tConverging Systems", None),
+            ("0011ZZ Nobody", None),
+        ] {
+            let parsed = parse_oui_line(line);
+            assert_eq!(
+                parsed.as_ref().map(|(oui, vendor)| (*oui, vendor.as_str())),
+                expected,
+                "{line:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn formats_addresses() {
+        let mac = MacAddress::new([0x00, 0x11, 0x32, 0x0a, 0xbc, 0xde]);
+        assert_eq!(mac.address, "00:11:32:0a:bc:de");
+
+        let mac = MacAddress {
+            address: "00:11:32:0a:bc:de".to_string(),
+            vendor: Some("Synology".to_string()),
+        };
+        assert_eq!(mac.to_string(), "00:11:32:0a:bc:de (Synology)");
+
+        let mac = MacAddress {
+            vendor: None,
+            ..mac
+        };
+        assert_eq!(mac.to_string(), "00:11:32:0a:bc:de");
+    }
+
+    #[test]
+    fn finds_vendors() {
+        // a randomized address, with the U/L bit set
+        assert_eq!(
+            vendor([0x0a, 0xf1, 0xe2, 0x01, 0x02, 0x03]).as_deref(),
+            Some("locally administered")
+        );
+
+        // which database is used depends on what's installed
+        if OUI_FILE.is_none() {
+            for (mac, expected) in [
+                ([0x00, 0x11, 0x32, 0, 0, 1], Some("Synology")),
+                ([0xb8, 0x27, 0xeb, 0, 0, 1], Some("Raspberry Pi")),
+                ([0x52, 0x54, 0x00, 0, 0, 1], Some("QEMU/KVM")),
+                ([0x00, 0x00, 0x01, 0, 0, 1], None),
+            ] {
+                assert_eq!(vendor(mac).as_deref(), expected, "{mac:02x?}");
+            }
+        }
+    }
+}
@@ -1,62 +1,1508 @@
-mod ports;
-mod scanner;
+mod config;
+mod dns;
+mod flaps;
+mod generate;
+mod logger;
+mod metrics;
+mod progress;
+mod reflector;
+mod signing;
+mod timing;
+mod web;
+mod webhook;
 
-use std::net::IpAddr;
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    net::{AddrParseError, IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
-use clap::Parser;
-use log::{warn, LevelFilter, SetLoggerError};
-use ports::Ports;
-use scanner::PortScanner;
-use simplelog::{ColorChoice, ConfigBuilder as LoggerConfigBuilder, TermLogger, TerminalMode};
+use age::{
+    armor::{ArmoredWriter, Format as ArmorFormat},
+    x25519::Recipient,
+};
+use clap::{
+    builder::TypedValueParser, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
+use config::FileConfig;
+use flaps::Flaps;
+use generate::Artifact;
+use ipnet::IpNet;
+use log::{error, trace, warn, LevelFilter};
+use logger::LogFormat;
+use metrics::Metrics;
+use progress::Progress;
+use qapper::{
+    sample_subnet, subnet_hosts, CancellationToken, Change, CheckpointSink, Credentials, CsvSink,
+    Facility, JsonSink, ParsePortsError, Policy, PortRules, PortScanner, PortScannerBuilder, Ports,
+    PortsStatus, Protocol, ScanEvent, ScanReport, SyslogSink, TextSink, MAX_SUBNET_HOSTS,
+};
+use regex::Regex;
+use serde::Deserialize;
+use timing::Timing;
+use tokio::{net::TcpListener, sync::Semaphore, task::JoinSet};
+use web::HttpUrl;
+use webhook::Webhook;
 
 #[tokio::main]
 async fn main() {
-    let config = Config::parse();
+    let matches = command().get_matches();
+    let mut config = Config::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    FileConfig::load(config.config.as_deref())
+        .expect("Failed to load config file!")
+        .apply(&mut config, &matches);
+    if let Some(timing) = config.timing {
+        timing.apply(&mut config, &matches);
+    }
+
+    let traced = &config.trace_target;
     if config.verbose {
-        init_logger(LevelFilter::Trace).map(|()| warn!("Verbose mode ON"))
+        logger::init(LevelFilter::Trace, config.log_format, traced)
+            .map(|()| warn!("Verbose mode ON"))
+    } else if config.quiet {
+        logger::init(LevelFilter::Off, config.log_format, traced)
     } else {
-        init_logger(LevelFilter::Error)
+        logger::init(LevelFilter::Error, config.log_format, traced)
     }
     .expect("Failed to initialize logger!");
 
-    // leaky leaky...
-    let addrs: &'static [IpAddr] = Box::leak(config.addrs.into_boxed_slice());
-    let on_checked = move |_ip, _port, _open: bool| {};
+    match &config.command {
+        Some(Command::Diff { old, new }) => {
+            let changed = diff(old, new, config.format).expect("Failed to compare reports!");
+            std::process::exit(if changed { 1 } else { 0 });
+        }
+        Some(Command::Reflect {
+            listen,
+            max_ports,
+            timeout,
+        }) => {
+            let listener = TcpListener::bind(listen)
+                .await
+                .expect("Failed to listen for scan requests!");
+            reflector::serve(listener, *max_ports, *timeout).await;
+            return;
+        }
+        Some(Command::Generate { artifact }) => {
+            generate::write(artifact, command(), &mut io::stdout()).expect("Failed to generate!");
+            return;
+        }
+        Some(Command::Verify { file, key }) => {
+            let valid = signing::verify(file, key).expect("Failed to verify signature!");
+            if valid {
+                println!("{}: signature matches", file.display());
+            } else {
+                println!("{}: modified, or signed with another key", file.display());
+            }
+
+            std::process::exit(if valid { 0 } else { 1 });
+        }
+        Some(Command::Capabilities) => {
+            capabilities(config.format).expect("Failed to write capabilities!");
+            return;
+        }
+        None => {}
+    }
+
+    let policy = load_policy(&config);
+    let sign_key = config
+        .sign_key
+        .as_deref()
+        .map(|path| signing::load_key(path).expect("Failed to load signing key!"));
+
+    let destination: Box<dyn Write + Send> = match &config.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).expect("Failed to create output file!"),
+        )),
+        None => Box::new(io::stdout()),
+    };
+
+    // results to be encrypted are kept in memory until then so they never
+    // reach the disk in plaintext
+    let plaintext = Plaintext::default();
+    let (mut out, destination): (Box<dyn Write + Send>, _) = if config.encrypt_to.is_empty() {
+        (destination, None)
+    } else {
+        (Box::new(plaintext.clone()), Some(destination))
+    };
+
+    let armor = config.output.is_none();
+    let encrypt_output = |destination: Option<Box<dyn Write + Send>>| {
+        if let Some(destination) = destination {
+            encrypt(&plaintext.take(), &config.encrypt_to, armor, destination)
+                .expect("Failed to encrypt results!");
+        }
+    };
+
+    // signs what ends up on disk, i.e. after encrypting
+    let sign_output = || {
+        if let (Some(key), Some(path)) = (&sign_key, &config.output) {
+            let signature = signing::sign(path, key).expect("Failed to sign results!");
+            trace!("Signed the results, in {}", signature.display());
+        }
+    };
+
+    let mut targets = std::mem::take(&mut config.addrs);
+    let mut ports = match config.ports.take() {
+        Some(PortsArg::Ports(ports)) => ports,
+        Some(PortsArg::Addr(target)) => {
+            targets.insert(0, target);
+            Ports::default()
+        }
+        None => Ports::default(),
+    };
+
+    let mut targets = resolve_targets(targets, &config).await;
+    if !config.exclude_rdns.is_empty() {
+        exclude_by_rdns(&mut targets, &config.exclude_rdns).await;
+    }
+
+    if let Some(port_list) = &config.port_list {
+        ports.extend(port_list.iter());
+    }
+
+    if let Some(path) = &config.ports_file {
+        let port_list = Ports::load(path).expect("Failed to load ports file!");
+        ports.extend(port_list.iter());
+    }
+
+    if let Some(n) = config.top_ports {
+        ports.extend(Ports::top(n.into()).iter());
+    }
+
+    // like nmap, scan the most common ports if none were given
+    if ports.is_empty() && config.udp.is_none() {
+        ports = Ports::top(DEFAULT_TOP_PORTS);
+    }
+
+    let mut udp_ports = config.udp.clone().unwrap_or_default();
+    if let Some(excluded) = &config.exclude_ports {
+        ports.exclude(excluded);
+        udp_ports.exclude(excluded);
+    }
+
+    if config.dry_run {
+        for ip in &targets.addrs {
+            writeln!(out, "{ip}:").expect("Failed to write results!");
+
+            let effects = policy.review(ip, &ports);
+            if effects.is_empty() {
+                writeln!(out, "\tall {} ports allowed", ports.len())
+                    .expect("Failed to write results!");
+            }
+
+            for effect in effects {
+                writeln!(out, "\t{effect}").expect("Failed to write results!");
+            }
+        }
+
+        out.flush().expect("Failed to write results!");
+        encrypt_output(destination);
+        sign_output();
+        return;
+    }
+
+    let (targets, ports) = match &config.deepen {
+        Some(deep_ports) => deepen(&config, &ports, &udp_ports, targets, deep_ports).await,
+        None => (targets, ports),
+    };
+
+    // hosts down are never checked, so this is only an upper bound
+    let progress = Arc::new(Progress::new(
+        targets.addrs.len() * (ports.len() + udp_ports.len()) + targets.services.len(),
+        config.human,
+    ));
+    let on_checked = {
+        let progress = Arc::clone(&progress);
+        move |_ip, _port, _open: bool| progress.checked()
+    };
+
+    let mut builder =
+        scanner_builder(&config, &ports, &udp_ports, &targets, policy).on_checked(on_checked);
+
+    if config.deepen.is_some() {
+        // the sweep found which hosts are up
+        builder = builder
+            .service_detection(config.probe_wait)
+            .discovery(false);
+
+        // nothing responded, but the report should still say so
+        if targets.addrs.is_empty() {
+            builder = builder.simulate([]);
+        }
+    }
+
+    // likewise if every target was left out
+    if !config.exclude_rdns.is_empty() && targets.addrs.is_empty() && targets.services.is_empty() {
+        builder = builder.simulate([]);
+    }
+
+    if config.rdns {
+        builder = builder.enricher(dns::ReverseDns);
+    }
+
+    builder = match config.format {
+        Format::Text => {
+            let mut sink = TextSink::new(out)
+                .open_only(config.open)
+                .show_latency(config.show_latency)
+                .human(config.human);
+            if config.rdns {
+                sink = sink.group_by_domain("rdns");
+            }
+
+            builder.sink(sink)
+        }
+        Format::Json => builder.sink(JsonSink::new(out).open_only(config.open)),
+        Format::Csv => builder.sink(CsvSink::new(out).open_only(config.open)),
+    };
+
+    if let Some(path) = &config.simulate {
+        let file = File::open(path).expect("Failed to open simulated results!");
+        let events: Vec<ScanEvent> = serde_json::from_reader(io::BufReader::new(file))
+            .expect("Failed to parse simulated results!");
+
+        builder = builder.simulate(events);
+    }
+
+    if let Some(seed) = config.fake {
+        builder = builder.fake(seed);
+    }
+
+    if let Some(addr) = config.syslog {
+        let sink = SyslogSink::connect(addr).expect("Failed to connect to syslog server!");
+        builder = builder.sink(sink.facility(config.syslog_facility));
+    }
+
+    if let Some(url) = &config.self_scan {
+        let events = reflector::self_scan(url, &ports, &udp_ports)
+            .await
+            .expect("Failed to get results from the reflector!");
+        builder = builder.simulate(events);
+    }
+
+    if let Some(path) = &config.resume {
+        let (checkpoint, events) = CheckpointSink::open(path).expect("Failed to load scan state!");
+        builder = builder.resume(events).sink(checkpoint);
+    }
+
+    let metrics = Arc::new(Metrics::default());
+    if let Some(addr) = config.metrics {
+        let listener = TcpListener::bind(addr)
+            .await
+            .expect("Failed to listen for metrics requests!");
+        tokio::spawn(Arc::clone(&metrics).serve(listener));
+    }
 
-    let scanner = PortScanner::new(config.ports, addrs, config.timeout, on_checked)
-        .expect("Failed to create port scanner!");
+    let scanner = builder.build().expect("Failed to create port scanner!");
+    let cancel = scanner.cancellation_token();
+    tokio::spawn(interrupt(cancel.clone()));
+
+    let status = config
+        .stats_every
+        .map(|secs| tokio::spawn(progress.report(Duration::from_secs(secs), scanner.slots())));
+    let started = Instant::now();
+    let report = scanner.scan().await.expect("Failed to write results!");
+    // Ctrl-C quits straight away from now on
+    cancel.cancel();
+    if let Some(status) = status {
+        status.abort();
+    }
+    metrics.record(&report, started.elapsed());
+    encrypt_output(destination);
+    sign_output();
+    notify(&config, &ScanReport::default().changes(&report)).await;
+
+    if report.partial {
+        std::process::exit(INTERRUPTED);
+    }
+
+    if let Some(interval) = config.watch {
+        watch(
+            &config, &ports, &udp_ports, &targets, report, interval, &metrics,
+        )
+        .await;
+    }
+}
+
+/// Exit code after Ctrl-C, as shells use for processes killed by SIGINT.
+const INTERRUPTED: i32 = 130;
+
+/// Stops the scan on the first Ctrl-C, so the results so far are still
+/// written, marked partial, and quits on the second, or on the first once
+/// `cancel` is cancelled.
+async fn interrupt(cancel: CancellationToken) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
 
-    let map = scanner.scan().await;
-    for (ip, status) in map.iter() {
-        println!("{ip}:\n\t{}", status.to_string().replace(";", "\n\t"));
+    if !cancel.is_cancelled() {
+        error!("Interrupted, writing the results so far. Press Ctrl-C again to quit");
+        cancel.cancel();
+        let _ = tokio::signal::ctrl_c().await;
     }
+
+    std::process::exit(INTERRUPTED);
 }
 
-fn init_logger(filter: LevelFilter) -> Result<(), SetLoggerError> {
-    let config = LoggerConfigBuilder::new()
-        .set_level_padding(simplelog::LevelPadding::Off)
-        .set_time_level(LevelFilter::Off)
-        .set_location_level(LevelFilter::Off)
-        .build();
+/// Sweeps `targets` for `ports` and `udp_ports`, returning the hosts which
+/// responded and the ports to scan them for in depth: `deep_ports` and those
+/// swept.
+async fn deepen(
+    config: &Config,
+    ports: &Ports,
+    udp_ports: &Ports,
+    targets: Targets,
+    deep_ports: &Ports,
+) -> (Targets, Ports) {
+    let report = scanner_builder(config, ports, udp_ports, &targets, load_policy(config))
+        .build()
+        .expect("Failed to create port scanner!")
+        .scan()
+        .await
+        .expect("Failed to sweep for live hosts!");
+
+    // without pings, only hosts with something open are known to be there
+    let has_open = |status: &PortsStatus| {
+        !status.open().is_empty() || status.udp().is_some_and(|udp| !udp.open().is_empty())
+    };
+    let live: HashSet<IpAddr> = report
+        .hosts
+        .iter()
+        .filter(|(_, status)| !config.no_ping || has_open(status))
+        .map(|(&ip, _)| ip)
+        .collect();
+
+    trace!(
+        "{} of {} hosts responded to the sweep, scanning them in depth",
+        live.len(),
+        targets.addrs.len()
+    );
 
-    TermLogger::init(filter, config, TerminalMode::Mixed, ColorChoice::Auto)
+    let mut ports = ports.clone();
+    ports.extend(deep_ports.iter());
+    let targets = Targets {
+        addrs: targets
+            .addrs
+            .into_iter()
+            .filter(|ip| live.contains(ip))
+            .collect(),
+        ..targets
+    };
+
+    (targets, ports)
+}
+
+/// A scanner configured from the command line, without sinks.
+fn scanner_builder(
+    config: &Config,
+    ports: &Ports,
+    udp_ports: &Ports,
+    targets: &Targets,
+    policy: Policy,
+) -> PortScannerBuilder {
+    let mut builder = PortScanner::builder()
+        .ports(ports.clone())
+        .udp_ports(udp_ports.clone())
+        .targets(targets.addrs.as_slice())
+        .timeout(config.timeout)
+        .retries(config.retries)
+        .discovery(!config.no_ping)
+        .traceroute(config.traceroute)
+        .os_guess(config.os_guess)
+        .mac_lookup(config.mac)
+        .policy(policy);
+
+    if let Some(ping_timeout) = config.ping_timeout {
+        builder = builder.ping_timeout(ping_timeout);
+    }
+
+    if let Some(concurrency) = config.concurrency {
+        builder = builder.concurrency(concurrency);
+    }
+
+    if let Some(rate) = config.max_rate {
+        builder = builder.max_rate(rate);
+    }
+
+    if let Some(batch_size) = config.batch_size {
+        builder = builder.batch_size(batch_size);
+    }
+
+    if config.banner {
+        builder = builder.banner(config.banner_bytes, config.banner_wait);
+    }
+
+    if config.service_detect {
+        builder = builder.service_detection(config.probe_wait);
+    }
+
+    if config.tls_probe {
+        builder = builder.tls_probe(config.probe_wait);
+    }
+
+    if config.http_probe {
+        builder = builder.http_probe(config.probe_wait);
+    }
+
+    if let Some(hold) = config.hold {
+        builder = builder.hold(hold);
+    }
+
+    for pattern in &config.redact {
+        builder = builder.redact(pattern);
+    }
+
+    for &(ip, protocol, port) in &targets.services {
+        builder = builder.target_ports(ip, protocol, Ports::from(vec![port]));
+    }
+
+    // probes ask for the name, unless told otherwise below
+    for (name, addrs) in &targets.names {
+        builder = builder.target_name(name, addrs.iter().copied());
+        for &ip in addrs {
+            builder = builder.target_vhost(ip, name);
+        }
+    }
+
+    for (subnet, addrs) in &targets.samples {
+        builder = builder.target_sample(*subnet, addrs.iter().copied());
+    }
+
+    for name in &targets.unresolved {
+        builder = builder.unresolved(name);
+    }
+
+    for &(ip, scope_id) in &targets.scopes {
+        builder = builder.target_scope(ip, scope_id);
+    }
+
+    for vhost in &config.vhost {
+        builder = match vhost.ip {
+            Some(ip) => builder.target_vhost(ip, &vhost.name),
+            None => builder.vhost(&vhost.name),
+        };
+    }
+
+    if let Some(path) = &config.credentials {
+        let credentials = Credentials::load(path).expect("Failed to load credentials file!");
+        builder = builder.credentials(credentials);
+    }
+
+    if let Some(path) = &config.port_rules {
+        let rules = PortRules::load(path).expect("Failed to load port rules file!");
+        builder = builder.port_rules(rules);
+    }
+
+    if let Some(dir) = &config.capture_dir {
+        builder = builder.capture_dir(dir);
+    }
+
+    if let Some(server) = config.stun_server {
+        builder = builder.stun_server(server);
+    }
+
+    if let Some(ip) = config.source_ip {
+        builder = builder.source_ip(ip);
+    }
+
+    if let Some(interface) = &config.interface {
+        builder = builder.interface(interface);
+    }
+
+    if let Some(port) = config.source_port {
+        builder = builder.source_port(port);
+    }
+
+    if let Some(label) = config.flow_label {
+        builder = builder.flow_label(label);
+    }
+
+    if config.ipv6_headers.contains(&Ipv6Header::HopByHop) {
+        builder = builder.hop_by_hop_options(true);
+    }
+
+    if config.ipv6_headers.contains(&Ipv6Header::Destination) {
+        builder = builder.destination_options(true);
+    }
+
+    builder
+}
+
+fn load_policy(config: &Config) -> Policy {
+    match &config.policy {
+        Some(path) => Policy::load(path).expect("Failed to load policy file!"),
+        None => Policy::default(),
+    }
+}
+
+/// Prints what changed from the report in `old` to the one in `new`,
+/// returning whether anything did.
+fn diff(old: &Path, new: &Path, format: Format) -> io::Result<bool> {
+    let load = |path: &Path| -> io::Result<ScanReport> {
+        let file = File::open(path)?;
+        serde_json::from_reader(io::BufReader::new(file))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+
+    let changes = load(old)?.changes(&load(new)?);
+    let mut out = io::stdout();
+    if let Format::Csv = format {
+        writeln!(out, "event,ip,protocol,port")?;
+    }
+
+    write_changes(&mut out, &changes, format, None)?;
+    out.flush()?;
+    Ok(!changes.is_empty())
+}
+
+/// Writes `changes`, found at `time` if given, as lines of text, or as
+/// events for change feeds: a JSON object per line, or CSV rows without the
+/// header.
+fn write_changes(
+    out: &mut impl Write,
+    changes: &[Change],
+    format: Format,
+    time: Option<&str>,
+) -> io::Result<()> {
+    for change in changes {
+        match format {
+            Format::Json => {
+                let mut line = serde_json::to_value(change)?;
+                line["event"] = change.event().into();
+                if let Some(time) = time {
+                    line["time"] = time.into();
+                }
+
+                writeln!(out, "{line}")?;
+            }
+            Format::Csv => {
+                if let Some(time) = time {
+                    write!(out, "{time},")?;
+                }
+
+                let (protocol, port) = match change.port() {
+                    Some((protocol, port)) => (protocol.to_string(), port.to_string()),
+                    None => Default::default(),
+                };
+                writeln!(out, "{},{},{protocol},{port}", change.event(), change.ip())?;
+            }
+            Format::Text => match time {
+                Some(time) => writeln!(out, "{time} {change}")?,
+                None => writeln!(out, "{change}")?,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints which scan modes work here.
+fn capabilities(format: Format) -> io::Result<()> {
+    let mut out = io::stdout();
+    let capabilities = qapper::capabilities();
+    match format {
+        Format::Json => writeln!(out, "{}", serde_json::to_string_pretty(&capabilities)?)?,
+        Format::Text | Format::Csv => {
+            for capability in &capabilities {
+                writeln!(out, "{capability}")?;
+            }
+        }
+    }
+
+    out.flush()
+}
+
+/// Scans again every `interval` after the `previous` scan, printing only
+/// what changed each time, and which ports flap, and recording results in
+/// `metrics`. Runs until interrupted.
+async fn watch(
+    config: &Config,
+    ports: &Ports,
+    udp_ports: &Ports,
+    targets: &Targets,
+    mut previous: ScanReport,
+    interval: Duration,
+    metrics: &Metrics,
+) {
+    let mut out = io::stdout();
+    if let Format::Csv = config.format {
+        writeln!(out, "time,event,ip,protocol,port").expect("Failed to write results!");
+    }
+
+    let mut flaps = Flaps::default();
+    loop {
+        check_flaps(config, &mut flaps, interval).await;
+
+        let started = Instant::now();
+        let scanned =
+            match scanner_builder(config, ports, udp_ports, targets, load_policy(config)).build() {
+                Ok(scanner) => scanner.scan().await,
+                Err(e) => Err(e),
+            };
+
+        let report = match scanned {
+            Ok(report) => report,
+            Err(e) => {
+                // keep watching, the network may well recover
+                error!("Scan failed: {e}");
+                metrics.error();
+                continue;
+            }
+        };
+
+        metrics.record(&report, started.elapsed());
+
+        let time = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+        let mut changes = previous.changes(&report);
+        let flapping = flaps.scanned(&report, &changes);
+        changes.extend(flapping);
+        write_changes(&mut out, &changes, config.format, Some(&time))
+            .expect("Failed to write results!");
+        out.flush().expect("Failed to write results!");
+        notify(config, &changes).await;
+        previous = report;
+    }
+}
+
+/// Waits `interval`, checking the ports in `flaps` --flap-checks times
+/// meanwhile, evenly spread.
+async fn check_flaps(config: &Config, flaps: &mut Flaps, interval: Duration) {
+    let checks = match flaps.watched().next() {
+        Some(_) => config.flap_checks,
+        None => 0,
+    };
+
+    let pause = interval / (checks + 1);
+    for _ in 0..checks {
+        tokio::time::sleep(pause).await;
+
+        // only the flapping ports, whether their hosts answer pings or not
+        let none = Ports::default();
+        let mut builder = scanner_builder(
+            config,
+            &none,
+            &none,
+            &Targets::default(),
+            load_policy(config),
+        )
+        .discovery(false);
+        for (ip, protocol, port) in flaps.watched() {
+            builder = builder.target_ports(ip, protocol, Ports::from(vec![port]));
+        }
+
+        let scanned = match builder.build() {
+            Ok(scanner) => scanner.scan().await,
+            Err(e) => Err(e),
+        };
+
+        match scanned {
+            Ok(report) => flaps.checked(&report),
+            Err(e) => error!("Checking flapping ports failed: {e}"),
+        }
+    }
+
+    tokio::time::sleep(pause).await;
+}
+
+/// Posts the ports which opened among `changes` to the webhook, if one was
+/// given.
+async fn notify(config: &Config, changes: &[Change]) {
+    if let Some(webhook) = &config.webhook {
+        // the URL is left out, it often holds a secret token
+        if let Err(e) = webhook.notify(changes, SystemTime::now()).await {
+            error!("Failed to post to webhook: {e}");
+        }
+    }
+}
+
+/// How many of the most common ports to scan when none are given.
+const DEFAULT_TOP_PORTS: usize = 100;
+
+/// Results written in memory, shared with the sink writing them.
+#[derive(Clone, Default)]
+struct Plaintext(Arc<Mutex<Vec<u8>>>);
+
+impl Plaintext {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl Write for Plaintext {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encrypts `plaintext` with age so that any of `recipients` can decrypt it.
+/// `armor` writes it PEM-encoded rather than binary, e.g. for terminals.
+fn encrypt(
+    plaintext: &[u8],
+    recipients: &[Recipient],
+    armor: bool,
+    out: impl Write,
+) -> io::Result<()> {
+    let encryptor = age::Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient),
+    )
+    .map_err(io::Error::other)?;
+
+    let format = if armor {
+        ArmorFormat::AsciiArmor
+    } else {
+        ArmorFormat::Binary
+    };
+
+    let mut writer = encryptor.wrap_output(ArmoredWriter::wrap_output(out, format)?)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?.finish()?.flush()
 }
 
 /// Program to quickly scan open ports
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Config {
-    /// Comma-separated list of ports or port ranges, e.g. "443,3000-5000". Ranges are inclusive: e.g. 23-45 will scan ports 23, ..., 45
-    ports: Ports,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// IP addresses to scan. Can be either IPv4 or IPv6
-    addrs: Vec<IpAddr>,
+    /// Comma-separated list of ports, port ranges or service names, e.g. "ssh,443,3000-5000". Ranges are inclusive: e.g. 23-45 will scan ports 23, ..., 45. Either limit can be left out, e.g. "1024-", and "all" or "-" scans every port. If no ports are given in any way, the 100 most common ones are scanned
+    #[arg(required_unless_present_any = ["simulate", "self_scan", "srv"])]
+    ports: Option<PortsArg>,
+
+    /// IP addresses, subnets like "10.0.0.0/24" or host names to scan, separated by spaces or commas. Link-local IPv6 addresses take the interface they're on, e.g. "fe80::1%eth0". Host names are scanned at every address they resolve to, e.g. each backend behind round-robin DNS, grouped under the name in the results with ports open on only some of them flagged
+    #[arg(value_delimiter = ',')]
+    addrs: Vec<Target>,
+
+    /// Also scan the hosts and ports published in the DNS SRV records of this name, e.g. "_ldap._tcp.example.com", only on the ports published. Can be given multiple times
+    #[arg(long, value_name = "NAME")]
+    srv: Vec<String>,
+
+    /// Only scan this share of the hosts in each subnet given, picked at random, e.g. "10%", and estimate how many hosts each has up, to decide whether a full sweep is worth it. Hosts are only counted up if they answer pings
+    #[arg(long, value_name = "PERCENT", value_parser = share, conflicts_with = "no_ping")]
+    sample: Option<f64>,
+
+    /// Seed for the random choices of the scan, i.e. which hosts --sample picks, so a scan can be reproduced exactly. The seed used is logged with --verbose [default: random]
+    #[arg(long, value_name = "SEED", requires = "sample")]
+    seed: Option<u64>,
+
+    /// What to do about host names which don't resolve: stop before scanning anything, skip them, or ask --fallback-resolver and skip them if it doesn't know them either. Names skipped are listed in the results
+    #[arg(long, value_enum, value_name = "ACTION", default_value_t = Unresolved::Abort)]
+    on_unresolved: Unresolved,
+
+    /// Name server to ask with --on-unresolved retry, e.g. "1.1.1.1" or "10.0.0.53:5353"
+    #[arg(long, value_name = "ADDR", value_parser = name_server, required_if_eq("on_unresolved", "retry"))]
+    fallback_resolver: Option<SocketAddr>,
+
+    /// Ports to scan, in the same format as the positional ports, e.g. -p- for every port
+    #[arg(
+        short = 'p',
+        long = "ports",
+        value_name = "PORTS",
+        allow_hyphen_values = true
+    )]
+    port_list: Option<Ports>,
+
+    /// Read ports to scan from a file, one list of ports per line in the same format as the positional ports. Anything after a '#' is a comment
+    #[arg(long, value_name = "PATH")]
+    ports_file: Option<PathBuf>,
+
+    /// Scan the N ports most often found open (at most 100), in addition to any ports given
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=100))]
+    top_ports: Option<u8>,
+
+    /// UDP ports to probe, in the same format as the ports, e.g. "3478". Ports known to run a service, like TFTP on 69, STUN on 3478, Steam game servers on 27015-27020, Minecraft Bedrock on 19132 or Quake 3 on 27960, are sent a request it answers. Only ports that answer are reported open, except syslog on 514, which is open if it stays silent while the host reports other ports closed
+    #[arg(long, value_name = "PORTS")]
+    udp: Option<Ports>,
+
+    /// Ports to leave out of the scan, in the same format as the ports, e.g. "135-139,445"
+    #[arg(long, value_name = "PORTS")]
+    exclude_ports: Option<Ports>,
+
+    /// Leave out targets whose reverse DNS name matches this pattern, where "*" stands for anything, e.g. "*.printer.corp". Can be given more than once
+    #[arg(long, value_name = "PATTERN", value_parser = rdns_pattern)]
+    exclude_rdns: Vec<Regex>,
+
+    /// Look up the reverse DNS names of hosts which responded, once the scan is done, and include them in the results. Text results group hosts by the domain of their name, with how many hosts and open ports each has
+    #[arg(long, default_value_t = false)]
+    rdns: bool,
 
     /// Emit verbose logs about the process
     #[arg(short, long, default_value_t = false)]
     verbose: bool,
 
+    /// Don't log anything, not even errors, leaving only the results. Logs never go to stdout either way
+    #[arg(short, long, default_value_t = false, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Format of the logs, e.g. "json" for one JSON object per line with the time, level, module and message, for log shippers
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Log everything about scanning this address, e.g. "10.0.0.5", as --verbose would but for it alone: every attempt, how long it took and how it failed, to debug one host without drowning in logs about the rest. Can be given multiple times
+    #[arg(long, value_name = "ADDR")]
+    trace_target: Vec<IpAddr>,
+
+    /// Print how far along the scan is to stderr every this many seconds: the share of ports checked, the current rate, how long it'll likely take and, when only so many ports are checked at once, how many hosts are waiting their turn, e.g. to show unattended scans in CI logs are still alive
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    stats_every: Option<u64>,
+
     /// Timeout (ms) when trying to connect to a port to check if it's "open"
     #[arg(short, long, default_value_t = 1000)]
     timeout: u64,
+
+    /// Timeout (ms) when pinging each host to see if it's up before scanning it, so hosts that are down don't hold up discovery. Also applies to connecting to common ports instead where pinging isn't allowed or possible [default: 2000, or --timeout for connecting]
+    #[arg(long, value_name = "MS")]
+    ping_timeout: Option<u64>,
+
+    /// How many times to try connecting again to TCP ports which didn't answer within the timeout, in case the packets were lost
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Timing template setting the timeout, retries, concurrency and rate at once, from paranoid (-T0) to insane (-T5). Options given along with it take precedence
+    #[arg(short = 'T', long, value_enum, value_name = "TEMPLATE")]
+    timing: Option<Timing>,
+
+    /// Read what open ports send right after connecting. Shown in verbose logs and JSON/CSV results
+    #[arg(long, default_value_t = false)]
+    banner: bool,
+
+    /// Maximum number of bytes of each banner to read
+    #[arg(long, default_value_t = 256, requires = "banner")]
+    banner_bytes: usize,
+
+    /// How long (ms) to wait for each banner
+    #[arg(long, default_value_t = 500, requires = "banner")]
+    banner_wait: u64,
+
+    /// Probe open ports to identify the service and its version, sending well-known ports (HTTP, TLS, SSH, SMTP, Kerberos, LDAP, ...) requests their service answers and trying a greeting, HTTP and TLS on any other, flagging likely Active Directory domain controllers and services expecting the PROXY protocol
+    #[arg(long, default_value_t = false)]
+    service_detect: bool,
+
+    /// Attempt a TLS handshake with every open port and report the negotiated version and ALPN protocol, and the certificate's names and expiry. Handshakes are completed in TLS 1.2 or 1.3; servers only speaking older versions are reported with the version they pick alone. Shown in verbose logs and JSON/CSV results
+    #[arg(long, default_value_t = false)]
+    tls_probe: bool,
+
+    /// Send GET / to every open port and report the status code, Server header and page title of web servers, and whether they serve gRPC, /healthz or Prometheus /metrics. Goes over TLS, in HTTP/2 if negotiated, with --tls-probe. Shown in verbose logs and JSON/CSV results
+    #[arg(long, default_value_t = false)]
+    http_probe: bool,
+
+    /// Virtual host name for TLS and HTTP probes to ask for, as the server name (SNI) and Host header, either for all addresses or, as IP=NAME, for one. Can be given multiple times [default: the address]
+    #[arg(long, value_name = "[IP=]NAME")]
+    vhost: Vec<VhostArg>,
+
+    /// How long (ms) to wait for responses to service detection, TLS and HTTP probes
+    #[arg(long, default_value_t = 1000)]
+    probe_wait: u64,
+
+    /// Connect to each open port once more after probing it and hold the connection this long (ms), flagging ports which hang up without a word. Middleboxes like SYN proxies accept connections for hosts that aren't there, then drop them, whereas services keep them or say something first
+    #[arg(long, value_name = "MS")]
+    hold: Option<u64>,
+
+    /// Regex whose matches are replaced with [REDACTED] in banners, service versions, certificate names and HTTP headers and titles before they're output. Can be given multiple times
+    #[arg(long, value_name = "REGEX")]
+    redact: Vec<String>,
+
+    /// Credentials file with secrets probes authenticate with, e.g. HTTP basic auth for --http-probe. Secrets are redacted from logs and results
+    #[arg(long, value_name = "FILE")]
+    credentials: Option<PathBuf>,
+
+    /// Write a hexdump of the bytes sent and received while grabbing banners, detecting services and probing UDP ports to a file per port in this directory, to troubleshoot misdetections
+    #[arg(long, value_name = "DIR")]
+    capture_dir: Option<PathBuf>,
+
+    /// Ask this STUN server, e.g. "stun.l.google.com:19302", which address the scan comes from, and include it in the results. Behind NAT, that's the address targets log and allowlists have to let through
+    #[arg(long, value_name = "HOST:PORT", value_parser = resolve)]
+    stun_server: Option<SocketAddr>,
+
+    /// Connect and ping from this local address, on hosts with more than one, instead of whichever the routing table picks. Targets must be of the same IP version
+    #[arg(long, value_name = "ADDR")]
+    source_ip: Option<IpAddr>,
+
+    /// Connect and ping through this network interface, e.g. "eth1", regardless of the routing table. Linux only
+    #[arg(long, value_name = "NAME")]
+    interface: Option<String>,
+
+    /// Check TCP ports from this source port, e.g. 53 or 20, to test firewall rules letting traffic through by source port. Ports below 1024 need root. Probes of open ports still connect from any port
+    #[arg(long, value_name = "PORT")]
+    source_port: Option<u16>,
+
+    /// Send IPv6 packets with this flow label, from 0 to 1048575, to test how firewalls and load balancers handle it. Pings are sent without it. Linux only
+    #[arg(long, value_name = "LABEL", value_parser = clap::value_parser!(u32).range(..=0xfffff))]
+    flow_label: Option<u32>,
+
+    /// Send IPv6 packets with these empty extension headers, e.g. "hop-by-hop,destination", to test whether firewalls drop them. Pings are sent without them. Needs root or CAP_NET_RAW, Linux only
+    #[arg(long, value_enum, value_name = "HEADERS", value_delimiter = ',')]
+    ipv6_headers: Vec<Ipv6Header>,
+
+    /// Maximum number of ports checked at once across all addresses, shared out fairly so hosts with few ports aren't kept waiting by ones with many. Never more than the limit on open files (ulimit -n) allows [default: unlimited]
+    #[arg(short, long)]
+    concurrency: Option<usize>,
+
+    /// How many ports of each host to start checking at once, waiting for them all before the next batch, to keep memory in check when scanning every port [default: 1024]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..).map(|n| n as usize))]
+    batch_size: Option<usize>,
+
+    /// Maximum number of ports checked and hosts pinged per second across all addresses [default: unlimited]
+    #[arg(long, value_name = "RATE", value_parser = clap::value_parser!(u32).range(1..))]
+    max_rate: Option<u32>,
+
+    /// Sweep the targets for the ports given first, then scan the hosts which answered pings, or had any of those ports open with --no-ping, for these ports too with service detection. All ports unless given
+    #[arg(long, value_name = "PORTS", num_args = 0..=1, default_missing_value = "all", conflicts_with_all = ["watch", "simulate", "fake", "resume", "self_scan", "dry_run"])]
+    deepen: Option<Ports>,
+
+    /// Don't ping addresses first, assume they're all up
+    #[arg(long, default_value_t = false)]
+    no_ping: bool,
+
+    /// Trace the route to hosts which don't answer pings or have no open ports, to show where packets to them are lost. Needs root or CAP_NET_RAW
+    #[arg(long, default_value_t = false)]
+    traceroute: bool,
+
+    /// Guess which OS family each host runs from the TTL of its ping replies and how it answered the first connection to it. TTLs are only seen with root or CAP_NET_RAW
+    #[arg(long, default_value_t = false)]
+    os_guess: bool,
+
+    /// Look up the MAC address and vendor of hosts on the local network, e.g. to tell apart the devices in a DHCP range
+    #[arg(long, default_value_t = false)]
+    mac: bool,
+
+    /// Write results to a file instead of stdout. Logs are still printed to the terminal
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Also send a syslog message (RFC 5424) over UDP to this server for each open port found, e.g. "logs.example.com:514"
+    #[arg(long, value_name = "HOST:PORT", value_parser = resolve)]
+    syslog: Option<SocketAddr>,
+
+    /// Syslog facility to send messages as, e.g. "local0"
+    #[arg(long, value_name = "FACILITY", default_value_t = Facility::default(), requires = "syslog")]
+    syslog_facility: Facility,
+
+    /// Encrypt the results with age to the given recipient public key (age1...). Can be given multiple times; stdout gets ASCII-armored output
+    #[arg(long, value_name = "RECIPIENT")]
+    encrypt_to: Vec<Recipient>,
+
+    /// Sign the results file with this ed25519 private key (PKCS#8 PEM, e.g. from "openssl genpkey -algorithm ed25519"), writing the signature next to it with .sig appended, so it can be shown unmodified later with "qapper verify". Signs the encrypted results with --encrypt-to
+    #[arg(long, value_name = "FILE", requires = "output")]
+    sign_key: Option<PathBuf>,
+
+    /// Format of the results. CSV has a row for each port as it's checked, so hosts found to be behind a middlebox once the scan is done, e.g. with --hold, are only flagged in text and JSON results
+    #[arg(short, long, value_enum, default_value_t = Format::Text, global = true)]
+    format: Format,
+
+    /// Only show open ports in the results
+    #[arg(long, default_value_t = false)]
+    open: bool,
+
+    /// Show how long connecting to each open port took in the text results. Always included in JSON/CSV results
+    #[arg(long, default_value_t = false)]
+    show_latency: bool,
+
+    /// Separate thousands in counts and rates and show long durations in minutes and hours, e.g. "12,345 ports ... in 2m 3s", in the text results and --stats-every lines. JSON and CSV results, and text without it, always use plain numbers, the same in every locale, for scripts
+    #[arg(long, default_value_t = false)]
+    human: bool,
+
+    /// Policy file declaring per-subnet maximum rates and forbidden ports/protocols
+    #[arg(long)]
+    policy: Option<PathBuf>,
+
+    /// Rules file declaring more TCP ports to check on hosts with particular ports open or services running, e.g. 8000-8099 if 8080 is open
+    #[arg(long, value_name = "PATH")]
+    port_rules: Option<PathBuf>,
+
+    /// Scan again this long after each scan finishes, e.g. "5m", printing only the ports that opened, closed or changed service and hosts that went up or down since the previous scan, as port_opened, port_closed, service_changed, host_new and host_gone events with --format json or csv. Runs until interrupted
+    #[arg(long, value_name = "INTERVAL", value_parser = humantime::parse_duration, conflicts_with_all = ["output", "encrypt_to", "simulate", "fake", "resume", "dry_run"])]
+    watch: Option<Duration>,
+
+    /// With --watch, check ports which opened or closed this many times before the next scan, and for as long as they keep doing so, to catch services that crash-loop. Ports which flip again are printed as flapping, with how often they have, or as port_flapping events with --format json or csv, with the count in "flips" for JSON. 0 turns this off
+    #[arg(long, value_name = "COUNT", default_value_t = 10, requires = "watch")]
+    flap_checks: u32,
+
+    /// With --watch, serve Prometheus metrics on this address at /metrics, e.g. "127.0.0.1:9100": whether each host is up and each port seen open still is, and how many scans ran, failed and took. /report serves the results of the last complete scan as JSON, never those of one still running. /healthz answers 200 while qapper runs, and /readyz once the first scan has finished, for liveness and readiness probes
+    #[arg(long, value_name = "ADDR", requires = "watch")]
+    metrics: Option<SocketAddr>,
+
+    /// POST newly open ports to this URL as JSON, e.g. a Slack or Teams incoming webhook: every open port found by the scan and, with --watch, the ports that opened since the previous one. The message is in "text", the ports in "opened"
+    #[arg(long, value_name = "URL")]
+    webhook: Option<Webhook>,
+
+    /// Don't scan anything, only report which parts of the scan the policy would block or throttle
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Replay results from a JSON file (a list of {"ip", "port", "open"} objects) instead of scanning
+    #[arg(long)]
+    simulate: Option<PathBuf>,
+
+    /// Generate plausible results from a seed instead of scanning, e.g. for demos
+    #[arg(long, value_name = "SEED", conflicts_with = "simulate")]
+    fake: Option<u64>,
+
+    /// Ask the reflector at this URL, e.g. one run with "qapper reflect" on a server outside the network, to scan this machine's public address for the ports and report what it found, to see which ports are reachable from the internet. Takes the place of addresses
+    #[arg(long, value_name = "URL", conflicts_with_all = ["addrs", "srv", "simulate", "fake", "resume", "watch", "dry_run"])]
+    self_scan: Option<HttpUrl>,
+
+    /// Record each port checked in a state file, and skip ports already recorded there when it exists, e.g. to carry on after a scan died halfway. Their results are included in the output
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["simulate", "fake"])]
+    resume: Option<PathBuf>,
+
+    /// Config file with defaults for the options above, e.g. timeout = 500 or ports = "ssh,https". Options given here take precedence [default: qapper.toml, if it exists]
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+}
+
+/// Prefix of the environment variables options can also be given in, e.g.
+/// `QAPPER_TIMEOUT=500` for `--timeout 500`, to configure containers through
+/// their environment alone.
+const ENV_PREFIX: &str = "QAPPER_";
+
+/// The command line, with every option also read from an environment
+/// variable named after it. Options given on the command line take
+/// precedence, and those in the environment over the config file.
+fn command() -> clap::Command {
+    Config::command().mut_args(|arg| {
+        let name = format!("{ENV_PREFIX}{}", arg.get_id().as_str().to_uppercase());
+        arg.env(name)
+    })
+}
+
+/// Looks up `value`, e.g. `example.com:3478`, taking the first address found.
+fn resolve(value: &str) -> io::Result<SocketAddr> {
+    value.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses found for {value}"),
+        )
+    })
+}
+
+/// A percentage, e.g. "10%", as a share from 0 to 1.
+fn share(value: &str) -> Result<f64, String> {
+    let percent: f64 = value
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|e| format!("{e}"))?;
+    if percent > 0.0 && percent <= 100.0 {
+        Ok(percent / 100.0)
+    } else {
+        Err("expected a percentage above 0 and up to 100".to_string())
+    }
+}
+
+/// A host name pattern, e.g. "*.printer.corp", matching names regardless of
+/// case.
+fn rdns_pattern(value: &str) -> Result<Regex, regex::Error> {
+    let pattern = regex::escape(value.trim_end_matches('.')).replace(r"\*", ".*");
+    Regex::new(&format!("(?i)^{pattern}$"))
+}
+
+/// A name server's address, on port 53 unless given.
+fn name_server(value: &str) -> Result<SocketAddr, AddrParseError> {
+    value
+        .parse()
+        .or_else(|_| value.parse().map(|ip| SocketAddr::new(ip, 53)))
+}
+
+/// The index of network interface `zone`, e.g. "eth0", or `zone` itself if
+/// it's a number.
+fn interface_index(zone: &str) -> Result<u32, String> {
+    if let Ok(index) = zone.parse() {
+        return Ok(index);
+    }
+
+    // keeps the name from pointing anywhere else in /sys
+    let is_name = !zone.is_empty()
+        && !zone.starts_with('.')
+        && zone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.@".contains(c));
+
+    let index = is_name
+        .then(|| fs::read_to_string(format!("/sys/class/net/{zone}/ifindex")).ok())
+        .flatten()
+        .and_then(|index| index.trim().parse().ok());
+
+    index.ok_or_else(|| format!("no such network interface: {zone}"))
+}
+
+/// The first positional argument, which is the first address instead when
+/// the ports come from elsewhere, e.g. `qapper --top-ports 10 10.0.0.1`.
+#[derive(Clone, Debug)]
+enum PortsArg {
+    Ports(Ports),
+    Addr(Target),
+}
+
+impl FromStr for PortsArg {
+    type Err = ParsePortsError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = value.parse() {
+            return Ok(Self::Addr(Target::Addr(ip)));
+        }
+
+        // service names win over host names, e.g. "http"
+        match value.parse() {
+            Ok(ports) => Ok(Self::Ports(ports)),
+            Err(e) => value.parse().map(Self::Addr).map_err(|_| e),
+        }
+    }
+}
+
+/// An address to scan, or a host name to scan every address of.
+#[derive(Clone, Debug)]
+enum Target {
+    Addr(IpAddr),
+    /// A link-local IPv6 address with the index of the interface it's on.
+    Scoped(Ipv6Addr, u32),
+    Subnet(IpNet),
+    Name(String),
+}
+
+impl FromStr for Target {
+    type Err = String;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = value.parse() {
+            return Ok(Self::Addr(ip));
+        }
+
+        if let Some((ip, zone)) = value.split_once('%') {
+            let ip = ip
+                .parse()
+                .map_err(|_| format!("not an IPv6 address: {ip}"))?;
+            return Ok(Self::Scoped(ip, interface_index(zone)?));
+        }
+
+        if let Ok(subnet) = value.parse::<IpNet>() {
+            if subnet_hosts(&subnet) > MAX_SUBNET_HOSTS.into() {
+                return Err(format!(
+                    "{subnet} has more than {MAX_SUBNET_HOSTS} hosts, split it up"
+                ));
+            }
+
+            return Ok(Self::Subnet(subnet.trunc()));
+        }
+
+        let is_label = |label: &str| {
+            (1..=63).contains(&label.len())
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+
+        if value.len() <= 253 && value.trim_end_matches('.').split('.').all(is_label) {
+            Ok(Self::Name(value.to_string()))
+        } else {
+            Err(format!("not an IP address, subnet or host name: {value}"))
+        }
+    }
+}
+
+/// The addresses to scan, the host names some of them were resolved from,
+/// the subnets sampled and services published in SRV records.
+#[derive(Debug, Default)]
+struct Targets {
+    addrs: Vec<IpAddr>,
+    names: Vec<(String, Vec<IpAddr>)>,
+    samples: Vec<(IpNet, Vec<IpAddr>)>,
+    /// Ports to scan on particular addresses only.
+    services: Vec<(IpAddr, Protocol, u16)>,
+    /// Host names skipped since they didn't resolve.
+    unresolved: Vec<String>,
+    /// Scope IDs of link-local IPv6 addresses.
+    scopes: Vec<(Ipv6Addr, u32)>,
+}
+
+/// Resolves the host names among `targets` to every address they have, and
+/// looks up the services published under the --srv names.
+async fn resolve_targets(targets: Vec<Target>, config: &Config) -> Targets {
+    let mut resolved = Targets::default();
+    let mut seen = HashSet::new();
+    let seed = config.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64)
+    });
+    if config.sample.is_some() && config.seed.is_none() {
+        trace!("Sampling with seed {seed}, give --seed {seed} to sample the same hosts again");
+    }
+
+    for target in targets {
+        let addrs = match target {
+            Target::Addr(ip) => vec![ip],
+            Target::Scoped(ip, scope_id) => {
+                resolved.scopes.push((ip, scope_id));
+                vec![ip.into()]
+            }
+            Target::Subnet(subnet) => match config.sample {
+                Some(share) => {
+                    let addrs = sample_subnet(&subnet, share, seed);
+                    resolved.samples.push((subnet, addrs.clone()));
+                    addrs
+                }
+                None => subnet.hosts().collect(),
+            },
+            Target::Name(name) => match resolve_name(&name, config).await {
+                Some(addrs) => {
+                    resolved.names.push((name, addrs.clone()));
+                    addrs
+                }
+                None => {
+                    resolved.unresolved.push(name);
+                    continue;
+                }
+            },
+        };
+
+        for ip in addrs {
+            if seen.insert(ip) {
+                resolved.addrs.push(ip);
+            }
+        }
+    }
+
+    for name in &config.srv {
+        let records = dns::lookup_srv(name)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to look up SRV records of {name}: {e}"));
+
+        for record in records {
+            // services often share hosts, which only need resolving once
+            if resolved.unresolved.contains(&record.target) {
+                continue;
+            }
+
+            let known = resolved
+                .names
+                .iter()
+                .find(|(name, _)| *name == record.target)
+                .map(|(_, addrs)| addrs.clone());
+            let addrs = match known {
+                Some(addrs) => addrs,
+                None => match resolve_name(&record.target, config).await {
+                    Some(addrs) => {
+                        resolved.names.push((record.target, addrs.clone()));
+                        addrs
+                    }
+                    None => {
+                        resolved.unresolved.push(record.target);
+                        continue;
+                    }
+                },
+            };
+
+            for ip in addrs {
+                resolved
+                    .services
+                    .push((ip, dns::protocol(name), record.port));
+            }
+        }
+    }
+
+    resolved
+}
+
+/// Most PTR lookups made at once by --exclude-rdns.
+const MAX_RDNS_LOOKUPS: usize = 64;
+
+/// Leaves out the targets with a name in reverse DNS matching any of
+/// `patterns`. Addresses whose names can't be looked up are kept.
+async fn exclude_by_rdns(targets: &mut Targets, patterns: &[Regex]) {
+    let limit = Arc::new(Semaphore::new(MAX_RDNS_LOOKUPS));
+    let mut lookups = JoinSet::new();
+    for &ip in &targets.addrs {
+        let limit = Arc::clone(&limit);
+        lookups.spawn(async move {
+            let _permit = limit.acquire().await.unwrap();
+            (ip, dns::lookup_ptr(ip).await)
+        });
+    }
+
+    let mut excluded = HashSet::new();
+    let mut failed = 0;
+    while let Some(lookup) = lookups.join_next().await {
+        match lookup {
+            Ok((ip, Ok(names))) => {
+                if let Some(name) = names
+                    .iter()
+                    .find(|name| patterns.iter().any(|pattern| pattern.is_match(name)))
+                {
+                    trace!("Leaving out {ip}, named {name}");
+                    excluded.insert(ip);
+                }
+            }
+            Ok((_, Err(e))) if e.kind() == io::ErrorKind::NotFound => {}
+            Ok((ip, Err(e))) => {
+                trace!("Failed to look up the name of {ip}: {e}");
+                failed += 1;
+            }
+            Err(e) => {
+                trace!("Failed to look up the name of a target: {e}");
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        error!("Failed to look up the names of {failed} targets, scanning them anyway");
+    }
+
+    if excluded.is_empty() {
+        return;
+    }
+
+    trace!("Left out {} targets by reverse DNS name", excluded.len());
+    let kept = |ip: &IpAddr| !excluded.contains(ip);
+    targets.addrs.retain(kept);
+    targets.services.retain(|(ip, _, _)| kept(ip));
+    for (_, addrs) in &mut targets.names {
+        addrs.retain(kept);
+    }
+
+    // so they don't count as down in the estimates
+    for (_, addrs) in &mut targets.samples {
+        addrs.retain(kept);
+    }
+}
+
+/// Every address `name` resolves to, or nothing if it doesn't and
+/// --on-unresolved says to skip it.
+async fn resolve_name(name: &str, config: &Config) -> Option<Vec<IpAddr>> {
+    let e = match tokio::net::lookup_host((name, 0)).await {
+        Ok(addrs) => {
+            let mut addrs: Vec<IpAddr> = addrs.map(|addr| addr.ip()).collect();
+            addrs.sort();
+            addrs.dedup();
+            return Some(addrs);
+        }
+        Err(e) => e,
+    };
+
+    match (config.on_unresolved, config.fallback_resolver) {
+        (Unresolved::Abort, _) => panic!("Failed to resolve {name}: {e}"),
+        (Unresolved::Retry, Some(server)) => match dns::lookup_host(name, server).await {
+            Ok(addrs) => return Some(addrs),
+            Err(e) => error!("Failed to resolve {name} with {server} either, skipping it: {e}"),
+        },
+        _ => error!("Failed to resolve {name}, skipping it: {e}"),
+    }
+
+    None
+}
+
+/// A virtual host name given with --vhost, optionally for a single address.
+#[derive(Clone, Debug)]
+struct VhostArg {
+    ip: Option<IpAddr>,
+    name: String,
+}
+
+impl FromStr for VhostArg {
+    type Err = AddrParseError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('=') {
+            Some((ip, name)) => Ok(Self {
+                ip: Some(ip.parse()?),
+                name: name.to_string(),
+            }),
+            None => Ok(Self {
+                ip: None,
+                name: value.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare two reports saved with --format json
+    ///
+    /// Prints which ports opened, closed or changed service and which hosts went up or down, and exits with status 1 if anything changed. With --format json or csv, each change is an event for change feeds: port_opened, port_closed, service_changed, host_new or host_gone, with the services in "before" and "after" for JSON. Reports saved with --open leave out closed ports, so ports closing don't show up
+    Diff {
+        /// The earlier report
+        old: PathBuf,
+
+        /// The later report
+        new: PathBuf,
+    },
+
+    /// Scan whoever asks, for --self-scan
+    ///
+    /// Answers GET /scan?ports=22,443&udp=53 by scanning the address the request came from and returning the results. No other address is ever scanned. Run it outside the network to check, e.g. on a cloud server, and reach it directly rather than through a proxy
+    Reflect {
+        /// Address to listen on, e.g. "0.0.0.0:8400"
+        listen: SocketAddr,
+
+        /// Most ports scanned for each request
+        #[arg(long, default_value_t = 1000)]
+        max_ports: usize,
+
+        /// Timeout (ms) when trying to connect to a port
+        #[arg(short, long, default_value_t = 1000)]
+        timeout: u64,
+    },
+
+    /// Check a results file against its signature, made with --sign-key
+    ///
+    /// Exits with status 1 if the file was modified since it was signed, or signed with another key
+    Verify {
+        /// The results file, with its signature next to it, e.g. scan.json and scan.json.sig
+        file: PathBuf,
+
+        /// Public key matching the signing key, as PEM, e.g. from "openssl pkey -pubout"
+        #[arg(long)]
+        key: PathBuf,
+    },
+
+    /// Check which scan modes work here
+    ///
+    /// Tries out what each needs, like raw sockets for --traceroute or Linux for --interface, without sending anything, to find out before scanning
+    Capabilities,
+
+    /// Print shell completions, a man page or an example config file
+    ///
+    /// Built from this version's options, so they always match the flags it has
+    Generate {
+        #[command(subcommand)]
+        artifact: Artifact,
+    },
+}
+
+/// What --on-unresolved does about host names which don't resolve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Unresolved {
+    Abort,
+    Skip,
+    Retry,
+}
+
+/// IPv6 extension headers --ipv6-headers adds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Ipv6Header {
+    HopByHop,
+    Destination,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Text,
+    Json,
+    Csv,
 }
@@ -0,0 +1,216 @@
+//! Serves the results of --watch scans for Prometheus to scrape, e.g.
+//!
+//! ```text
+//! qapper_host_up{host="10.0.0.1"} 1
+//! qapper_port_open{host="10.0.0.1",port="22",protocol="tcp"} 1
+//! qapper_scans_total 3
+//! ```
+//!
+//! Ports and hosts stay listed once they've been seen open or up, with 0 once
+//! they're not, so alerts can fire on them. `/healthz` and `/readyz` are
+//! there for liveness and readiness probes of orchestrators.
+//!
+//! `/report` serves the results of the last complete scan as JSON, numbered
+//! by scan. Each scan's results replace the last ones whole once it's done,
+//! so readers never see those of a scan still in progress.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use qapper::{Protocol, ScanReport};
+use serde::Serialize;
+use tokio::net::TcpListener;
+
+use crate::web::{self, Request, Response};
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    hosts: BTreeMap<IpAddr, bool>,
+    ports: BTreeMap<(IpAddr, u16, Protocol), bool>,
+    scans: u64,
+    errors: u64,
+    /// Total time spent scanning.
+    duration: Duration,
+    last_duration: Duration,
+    snapshot: Option<Arc<Snapshot>>,
+}
+
+/// The results of a complete scan, never changed once taken.
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    /// Which scan it was, counting from 1.
+    version: u64,
+    finished: String,
+    report: ScanReport,
+}
+
+impl Metrics {
+    /// Records the results of a finished scan, which took `duration`.
+    pub(crate) fn record(&self, report: &ScanReport, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.scans += 1;
+        state.duration += duration;
+        state.last_duration = duration;
+        state.snapshot = Some(Arc::new(Snapshot {
+            version: state.scans,
+            finished: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            report: report.clone(),
+        }));
+
+        for up in state.hosts.values_mut() {
+            *up = false;
+        }
+
+        for open in state.ports.values_mut() {
+            *open = false;
+        }
+
+        for (&ip, status) in &report.hosts {
+            state.hosts.insert(ip, true);
+            let udp = status.udp().map(|udp| (Protocol::Udp, udp));
+            for (protocol, status) in [(Protocol::Tcp, status)].into_iter().chain(udp) {
+                for &port in status.open() {
+                    state.ports.insert((ip, port, protocol), true);
+                }
+            }
+        }
+    }
+
+    /// Records a scan which failed.
+    pub(crate) fn error(&self) {
+        self.state.lock().unwrap().errors += 1;
+    }
+
+    /// The metrics in Prometheus' text format.
+    fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut text = String::new();
+        header(
+            &mut text,
+            "qapper_host_up",
+            "gauge",
+            "Whether the host responded in the last scan.",
+        );
+        for (ip, up) in &state.hosts {
+            let _ = writeln!(text, "qapper_host_up{{host=\"{ip}\"}} {}", *up as u8);
+        }
+
+        header(
+            &mut text,
+            "qapper_port_open",
+            "gauge",
+            "Whether the port was open in the last scan.",
+        );
+        for ((ip, port, protocol), open) in &state.ports {
+            let _ = writeln!(
+                text,
+                "qapper_port_open{{host=\"{ip}\",port=\"{port}\",protocol=\"{protocol}\"}} {}",
+                *open as u8
+            );
+        }
+
+        header(
+            &mut text,
+            "qapper_scans_total",
+            "counter",
+            "Scans finished.",
+        );
+        let _ = writeln!(text, "qapper_scans_total {}", state.scans);
+
+        header(
+            &mut text,
+            "qapper_scan_errors_total",
+            "counter",
+            "Scans which failed.",
+        );
+        let _ = writeln!(text, "qapper_scan_errors_total {}", state.errors);
+
+        header(
+            &mut text,
+            "qapper_scan_duration_seconds_total",
+            "counter",
+            "Time spent scanning.",
+        );
+        let _ = writeln!(
+            text,
+            "qapper_scan_duration_seconds_total {}",
+            state.duration.as_secs_f64()
+        );
+
+        header(
+            &mut text,
+            "qapper_last_scan_duration_seconds",
+            "gauge",
+            "How long the last scan took.",
+        );
+        let _ = writeln!(
+            text,
+            "qapper_last_scan_duration_seconds {}",
+            state.last_duration.as_secs_f64()
+        );
+
+        text
+    }
+
+    /// The last complete scan's results as JSON, if any scan has finished.
+    fn report(&self) -> Option<String> {
+        // serialized without holding the lock, as the snapshot never changes
+        let snapshot = self.state.lock().unwrap().snapshot.clone()?;
+        serde_json::to_string_pretty(&*snapshot).ok()
+    }
+
+    /// Whether a scan has finished, so there are results to scrape.
+    fn ready(&self) -> bool {
+        self.state.lock().unwrap().scans > 0
+    }
+
+    /// Answers requests for `/metrics`, `/report`, `/healthz` and `/readyz` on
+    /// `listener` until the program exits.
+    pub(crate) async fn serve(self: Arc<Self>, listener: TcpListener) {
+        web::serve(listener, "metrics", move |request: Request| {
+            let metrics = Arc::clone(&self);
+            async move {
+                match (request.method.as_str(), request.target.as_str()) {
+                    ("GET", "/metrics") => {
+                        Response::new("200 OK", "text/plain; version=0.0.4", metrics.render())
+                    }
+                    ("GET", "/report") => match metrics.report() {
+                        Some(json) => Response::new("200 OK", "application/json", json),
+                        None => Response::new(
+                            "503 Service Unavailable",
+                            "text/plain",
+                            "the first scan hasn't finished\n".into(),
+                        ),
+                    },
+                    // answering at all shows it's alive
+                    ("GET", "/healthz") => Response::new("200 OK", "text/plain", "ok\n".into()),
+                    ("GET", "/readyz") if metrics.ready() => {
+                        Response::new("200 OK", "text/plain", "ready\n".into())
+                    }
+                    ("GET", "/readyz") => Response::new(
+                        "503 Service Unavailable",
+                        "text/plain",
+                        "the first scan hasn't finished\n".into(),
+                    ),
+                    ("GET", _) => Response::error("404 Not Found"),
+                    _ => Response::error("405 Method Not Allowed"),
+                }
+            }
+        })
+        .await
+    }
+}
+
+fn header(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
@@ -0,0 +1,61 @@
+//! Host names resolving to several addresses, e.g. round-robin DNS, whose
+//! backends are meant to look the same from outside.
+
+use std::{collections::BTreeSet, net::IpAddr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ports::PortsStatus, scanner::ScanReport};
+
+/// The addresses a host name resolved to, each scanned on its own, and how
+/// their results differ.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameStatus {
+    pub addrs: Vec<IpAddr>,
+    /// Addresses which didn't respond.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub down: Vec<IpAddr>,
+    /// TCP ports open on some of the responding addresses but not others.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inconsistent: Vec<u16>,
+    /// Same for UDP ports.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inconsistent_udp: Vec<u16>,
+}
+
+impl NameStatus {
+    /// Whether every address responded with the same ports open.
+    pub fn is_consistent(&self) -> bool {
+        self.down.is_empty() && self.inconsistent.is_empty() && self.inconsistent_udp.is_empty()
+    }
+}
+
+impl ScanReport {
+    /// Groups `addrs` under `name`, comparing their results.
+    pub(crate) fn group(&mut self, name: String, addrs: Vec<IpAddr>) {
+        let up: Vec<&PortsStatus> = addrs.iter().filter_map(|ip| self.hosts.get(ip)).collect();
+        let status = NameStatus {
+            down: addrs
+                .iter()
+                .filter(|ip| !self.hosts.contains_key(ip))
+                .copied()
+                .collect(),
+            inconsistent: inconsistent(up.iter().map(|status| status.open())),
+            inconsistent_udp: inconsistent(
+                up.iter()
+                    .map(|status| status.udp().map_or(&[][..], PortsStatus::open)),
+            ),
+            addrs,
+        };
+
+        self.names.insert(name, status);
+    }
+}
+
+/// Ports in some of the lists of `open` ports but not all.
+fn inconsistent<'a>(open: impl Iterator<Item = &'a [u16]> + Clone) -> Vec<u16> {
+    let all: BTreeSet<u16> = open.clone().flatten().copied().collect();
+    all.into_iter()
+        .filter(|port| open.clone().any(|ports| !ports.contains(port)))
+        .collect()
+}
@@ -0,0 +1,306 @@
+//! Guessing which OS family a host runs from how its network stack answers:
+//! the TTL of its ping replies, and the window, window scale and options of
+//! the SYN-ACK it sent when a port was connected to. Stacks pick these
+//! differently, e.g. Windows starts at TTL 128 and leaves out timestamps,
+//! while Linux starts at 64 and scales its window by 2^7.
+
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+
+/// What a host's network stack gave away.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Fingerprint {
+    /// TTL of ping replies, which only raw ICMP sockets see.
+    pub(crate) ttl: Option<u8>,
+    pub(crate) tcp: Option<Handshake>,
+}
+
+/// How a host's SYN-ACK looked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Handshake {
+    window: u32,
+    /// The most data we may send at once, which is the host's MSS minus the
+    /// length of the options we agreed on.
+    mss: u32,
+    window_scale: Option<u8>,
+    timestamps: bool,
+}
+
+/// The OS family a host most likely runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OsFamily {
+    Linux,
+    Windows,
+    /// macOS and the BSDs.
+    Bsd,
+    /// Routers, switches, printers and other embedded stacks.
+    Network,
+}
+
+impl Display for OsFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Linux => "Linux",
+            Self::Windows => "Windows",
+            Self::Bsd => "macOS/BSD",
+            Self::Network => "network device",
+        })
+    }
+}
+
+/// A best guess at a host's OS family, with what it's based on.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OsGuess {
+    pub family: OsFamily,
+    /// TTL of the host's ping replies, if pinged over a raw socket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u8>,
+    /// Receive window of the host's SYN-ACK.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<u32>,
+    /// Window scale of the host's SYN-ACK, if it offered one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_scale: Option<u8>,
+    /// Whether the host's SYN-ACK had TCP timestamps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<bool>,
+}
+
+impl Display for OsGuess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut basis = vec![];
+        if let Some(ttl) = self.ttl {
+            basis.push(format!("ttl {ttl}"));
+        }
+
+        if let Some(window) = self.window {
+            basis.push(format!("window {window}"));
+        }
+
+        if let Some(scale) = self.window_scale {
+            basis.push(format!("wscale {scale}"));
+        }
+
+        match self.timestamps {
+            Some(true) => basis.push("timestamps".to_string()),
+            Some(false) => basis.push("no timestamps".to_string()),
+            None => {}
+        }
+
+        write!(f, "{} ({})", self.family, basis.join(", "))
+    }
+}
+
+impl Fingerprint {
+    /// The OS family that fits best, if anything was seen.
+    pub(crate) fn guess(&self) -> Option<OsGuess> {
+        use OsFamily::*;
+
+        let mut scores = [(Linux, 0), (Windows, 0), (Bsd, 0), (Network, 0)];
+        let mut score = |family: OsFamily, points: u32| {
+            for (candidate, score) in &mut scores {
+                if *candidate == family {
+                    *score += points;
+                }
+            }
+        };
+
+        // TTLs start at a power of two, or 255, and lose one per hop
+        match self.ttl {
+            Some(0..=64) => {
+                score(Linux, 3);
+                score(Bsd, 2);
+            }
+            Some(65..=128) => score(Windows, 3),
+            Some(129..) => score(Network, 3),
+            None => {}
+        }
+
+        if let Some(tcp) = &self.tcp {
+            if tcp.timestamps {
+                score(Linux, 1);
+                score(Bsd, 1);
+            } else {
+                score(Windows, 2);
+                score(Network, 1);
+            }
+
+            match tcp.window_scale {
+                None | Some(0) => score(Network, 2),
+                Some(5 | 6) => score(Bsd, 2),
+                Some(8) => score(Windows, 2),
+                Some(_) => score(Linux, 2),
+            }
+
+            // Linux offers a whole number of segments
+            match tcp.window {
+                65535 => {
+                    score(Windows, 1);
+                    score(Bsd, 1);
+                }
+                8192 => score(Windows, 2),
+                0..8192 => score(Network, 1),
+                window if tcp.mss > 0 && window % tcp.mss == 0 => score(Linux, 1),
+                _ => {}
+            }
+        }
+
+        // ties go to whichever comes first, the more common family
+        let (family, score) = scores
+            .into_iter()
+            .rev()
+            .max_by_key(|(_, score)| *score)
+            .expect("there are families");
+
+        (score > 0).then_some(OsGuess {
+            family,
+            ttl: self.ttl,
+            window: self.tcp.map(|tcp| tcp.window),
+            window_scale: self.tcp.and_then(|tcp| tcp.window_scale),
+            timestamps: self.tcp.map(|tcp| tcp.timestamps),
+        })
+    }
+}
+
+/// How the host at the other end of `stream` answered the handshake, where
+/// the OS tells.
+#[cfg(target_os = "linux")]
+pub(crate) fn handshake(stream: &TcpStream) -> Option<Handshake> {
+    use std::os::fd::AsRawFd;
+
+    // offsets into struct tcp_info, which has only grown since
+    const OPTIONS: usize = 5;
+    const WSCALE: usize = 6;
+    const SND_MSS: usize = 16;
+    const SND_WND: usize = 228;
+
+    const TCPI_OPT_TIMESTAMPS: u8 = 1;
+    const TCPI_OPT_WSCALE: u8 = 4;
+
+    let mut info = [0u8; 256];
+    let mut len = info.len() as libc::socklen_t;
+    // SAFETY: the kernel writes at most `len` bytes to `info`, and sets `len`
+    // to how many it wrote
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            info.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+
+    // the send window only made it into tcp_info with Linux 6.2
+    if ret != 0 || (len as usize) < SND_WND + 4 {
+        return None;
+    }
+
+    let u32_at = |at: usize| u32::from_ne_bytes(info[at..at + 4].try_into().unwrap());
+    let options = info[OPTIONS];
+    Some(Handshake {
+        window: u32_at(SND_WND),
+        mss: u32_at(SND_MSS),
+        window_scale: (options & TCPI_OPT_WSCALE != 0).then_some(info[WSCALE] & 0x0f),
+        timestamps: options & TCPI_OPT_TIMESTAMPS != 0,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn handshake(_stream: &TcpStream) -> Option<Handshake> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guess(ttl: Option<u8>, tcp: Option<(u32, u32, Option<u8>, bool)>) -> Option<OsFamily> {
+        let tcp = tcp.map(|(window, mss, window_scale, timestamps)| Handshake {
+            window,
+            mss,
+            window_scale,
+            timestamps,
+        });
+        Fingerprint { ttl, tcp }.guess().map(|guess| guess.family)
+    }
+
+    #[test]
+    fn guesses_from_ttls() {
+        use OsFamily::*;
+
+        for (ttl, expected) in [
+            (64, Linux),
+            (52, Linux),
+            (1, Linux),
+            (128, Windows),
+            (113, Windows),
+            (65, Windows),
+            (255, Network),
+            (240, Network),
+        ] {
+            assert_eq!(guess(Some(ttl), None), Some(expected), "ttl {ttl}");
+        }
+    }
+
+    #[test]
+    fn guesses_from_handshakes() {
+        use OsFamily::*;
+
+        for (ttl, tcp, expected) in [
+            // window of 45 segments, scaled by 2^7
+            (Some(64), (65160, 1448, Some(7), true), Linux),
+            (None, (65160, 1448, Some(7), true), Linux),
+            (Some(128), (65535, 1460, Some(8), false), Windows),
+            (None, (8192, 1460, Some(8), false), Windows),
+            (Some(64), (65535, 1448, Some(6), true), Bsd),
+            (None, (65535, 1448, Some(6), true), Bsd),
+            (Some(255), (4128, 536, None, false), Network),
+            (None, (4128, 536, Some(0), false), Network),
+            // the TTL outweighs a window that looks like another's
+            (Some(128), (65160, 1448, Some(7), false), Windows),
+        ] {
+            assert_eq!(
+                guess(ttl, Some(tcp)),
+                Some(expected),
+                "ttl {ttl:?}, {tcp:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn needs_something_to_go_on() {
+        assert_eq!(Fingerprint::default().guess(), None);
+    }
+
+    #[test]
+    fn formats_guesses() {
+        let tcp = Handshake {
+            window: 65160,
+            mss: 1448,
+            window_scale: Some(7),
+            timestamps: true,
+        };
+        let guess = Fingerprint {
+            ttl: Some(64),
+            tcp: Some(tcp),
+        }
+        .guess()
+        .unwrap();
+        assert_eq!(
+            guess.to_string(),
+            "Linux (ttl 64, window 65160, wscale 7, timestamps)"
+        );
+
+        let guess = Fingerprint {
+            ttl: Some(128),
+            tcp: None,
+        }
+        .guess()
+        .unwrap();
+        assert_eq!(guess.to_string(), "Windows (ttl 128)");
+    }
+}
@@ -0,0 +1,169 @@
+//! Pacing UDP probes to hosts which rate-limit their ICMP port unreachable
+//! messages, as RFC 1812 allows. Ports probed faster than the host reports
+//! them closed just go silent, which looks the same as a firewall.
+
+use std::{net::IpAddr, sync::Mutex, time::Duration};
+
+use log::trace;
+use tokio::time::Instant;
+
+/// Interval between probes once a host seems to rate-limit, doubled each
+/// time a port it left silent turns out to be closed, up to [`MAX_INTERVAL`].
+const MIN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The rate Linux limits ICMP errors to by default.
+const MAX_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks how a single host answers UDP probes, slowing them down once its
+/// closed ports start going silent.
+#[derive(Debug)]
+pub(crate) struct IcmpPacer {
+    ip: IpAddr,
+    state: Mutex<PacerState>,
+}
+
+#[derive(Debug, Default)]
+struct PacerState {
+    /// Whether the host reported any port closed.
+    reports_closed: bool,
+    interval: Option<Duration>,
+    next: Option<Instant>,
+}
+
+impl IcmpPacer {
+    pub(crate) fn new(ip: IpAddr) -> Self {
+        Self {
+            ip,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Waits for the next probe's turn, if probes are being paced.
+    pub(crate) async fn wait(&self) {
+        let slot = {
+            let mut state = self.state.lock().unwrap();
+            let Some(interval) = state.interval else {
+                return;
+            };
+
+            let slot = state
+                .next
+                .map_or(Instant::now(), |next| next.max(Instant::now()));
+            state.next = Some(slot + interval);
+            slot
+        };
+
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Notes that the host reported a port closed.
+    pub(crate) fn refused(&self) {
+        self.state.lock().unwrap().reports_closed = true;
+    }
+
+    /// Notes that a port on the host stayed silent. If the host reports other
+    /// ports closed, the silence may be its rate limit instead, so probes
+    /// start being paced and this returns whether to probe the port again.
+    pub(crate) fn silent(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.reports_closed {
+            return false;
+        }
+
+        if state.interval.is_none() {
+            self.pace(&mut state, MIN_INTERVAL);
+        }
+
+        true
+    }
+
+    /// Notes that a port which stayed silent was reported closed when probed
+    /// again, so the host does rate-limit, and probes slow down further.
+    pub(crate) fn missed(&self) {
+        let mut state = self.state.lock().unwrap();
+        let interval = state
+            .interval
+            .map_or(MIN_INTERVAL, |interval| (interval * 2).min(MAX_INTERVAL));
+        self.pace(&mut state, interval);
+    }
+
+    fn pace(&self, state: &mut PacerState, interval: Duration) {
+        if state.interval != Some(interval) {
+            trace!(
+                "{} may be rate-limiting ICMP port unreachable messages, pacing UDP probes to one every {}ms",
+                self.ip,
+                interval.as_millis()
+            );
+            state.interval = Some(interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    /// How long `probes` probes in a row wait for their turns.
+    async fn paced(pacer: &IcmpPacer, probes: u32) -> Duration {
+        let start = Instant::now();
+        for _ in 0..probes {
+            pacer.wait().await;
+        }
+
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn leaves_hosts_which_never_report_closed_ports_alone() {
+        let pacer = IcmpPacer::new(IP);
+        assert!(!pacer.silent());
+        assert_eq!(paced(&pacer, 10).await, Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_once_closed_ports_go_silent() {
+        let pacer = IcmpPacer::new(IP);
+        pacer.refused();
+        assert_eq!(paced(&pacer, 10).await, Duration::ZERO);
+
+        assert!(pacer.silent());
+        // the first probe goes right away
+        assert_eq!(paced(&pacer, 3).await, MIN_INTERVAL * 2);
+        assert!(pacer.silent());
+        assert_eq!(paced(&pacer, 2).await, MIN_INTERVAL * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slows_down_for_each_miss() {
+        let pacer = IcmpPacer::new(IP);
+        pacer.refused();
+        assert!(pacer.silent());
+        paced(&pacer, 1).await;
+
+        for interval in [100, 200, 400, 800, 1000, 1000] {
+            pacer.missed();
+            // the slot booked at the old interval first
+            paced(&pacer, 1).await;
+            assert_eq!(
+                paced(&pacer, 1).await,
+                Duration::from_millis(interval),
+                "{interval}ms"
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn catches_up_after_idling() {
+        let pacer = IcmpPacer::new(IP);
+        pacer.refused();
+        pacer.silent();
+        paced(&pacer, 1).await;
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(paced(&pacer, 2).await, MIN_INTERVAL);
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    fmt::Display,
+    fs, io,
+    net::IpAddr,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::ports::Ports;
+
+/// Restrictions on how hosts may be scanned, declared per subnet in a policy
+/// file, e.g.
+///
+/// ```toml
+/// [[rule]]
+/// subnet = "10.0.0.0/8"
+/// max_rate = 100
+/// forbidden_ports = "135-139,445"
+/// forbidden_protocols = ["icmp"]
+/// ```
+///
+/// When several rules match a host, all of them apply.
+#[derive(Debug, Default, Deserialize)]
+pub struct Policy {
+    #[serde(default, rename = "rule")]
+    rules: Vec<PolicyRule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyRule {
+    subnet: IpNet,
+
+    /// Maximum number of probes per second sent to all hosts in the subnet
+    max_rate: Option<u32>,
+
+    #[serde(default, deserialize_with = "deserialize_ports")]
+    forbidden_ports: Option<Ports>,
+
+    #[serde(default)]
+    forbidden_protocols: Vec<Protocol>,
+
+    #[serde(skip)]
+    limiter: RateLimiter,
+}
+
+/// Protocols used by the scanner which a policy can forbid.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// Pinging hosts to check if they're up. Hosts which can't be pinged are
+    /// assumed to be up.
+    Icmp,
+    /// Connecting to ports.
+    #[default]
+    Tcp,
+    /// Sending requests to UDP ports.
+    Udp,
+}
+
+impl Protocol {
+    pub(crate) fn is_tcp(&self) -> bool {
+        *self == Self::Tcp
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Icmp => write!(f, "icmp"),
+            Self::Tcp => write!(f, "tcp"),
+            Self::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// How a policy rule would affect scanning a host, see [`Policy::review`].
+#[derive(Debug)]
+pub enum PolicyEffect {
+    Forbidden { subnet: IpNet, protocol: Protocol },
+    ForbiddenPorts { subnet: IpNet, ports: Ports },
+    Throttled { subnet: IpNet, max_rate: u32 },
+}
+
+impl Display for PolicyEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Forbidden {
+                subnet,
+                protocol: Protocol::Icmp,
+            } => write!(
+                f,
+                "pinging forbidden by rule for {subnet}, host will be assumed up"
+            ),
+            Self::Forbidden {
+                subnet,
+                protocol: Protocol::Tcp,
+            } => write!(
+                f,
+                "connecting forbidden by rule for {subnet}, TCP ports will be skipped"
+            ),
+            Self::Forbidden {
+                subnet,
+                protocol: Protocol::Udp,
+            } => write!(
+                f,
+                "UDP probes forbidden by rule for {subnet}, UDP ports will be skipped"
+            ),
+            Self::ForbiddenPorts { subnet, ports } => write!(
+                f,
+                "{} ports forbidden by rule for {subnet}: {ports}",
+                ports.len()
+            ),
+            Self::Throttled { subnet, max_rate } => {
+                write!(f, "throttled to {max_rate} probes/s by rule for {subnet}")
+            }
+        }
+    }
+}
+
+impl Policy {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn allows_port(&self, ip: &IpAddr, port: u16) -> bool {
+        self.rules_for(ip)
+            .filter_map(|rule| rule.forbidden_ports.as_ref())
+            .all(|ports| !ports.contains(port))
+    }
+
+    pub fn allows_protocol(&self, ip: &IpAddr, protocol: Protocol) -> bool {
+        self.rules_for(ip)
+            .all(|rule| !rule.forbidden_protocols.contains(&protocol))
+    }
+
+    /// Lists what the policy would block or throttle when scanning `ports`
+    /// on `ip`, without scanning anything.
+    pub fn review(&self, ip: &IpAddr, ports: &Ports) -> Vec<PolicyEffect> {
+        let mut effects = vec![];
+        for rule in self.rules_for(ip) {
+            let subnet = rule.subnet;
+            for &protocol in &rule.forbidden_protocols {
+                effects.push(PolicyEffect::Forbidden { subnet, protocol });
+            }
+
+            if let Some(forbidden) = &rule.forbidden_ports {
+                let ports: Vec<u16> = ports
+                    .iter()
+                    .filter(|&port| forbidden.contains(port))
+                    .collect();
+
+                if !ports.is_empty() {
+                    effects.push(PolicyEffect::ForbiddenPorts {
+                        subnet,
+                        ports: ports.into(),
+                    });
+                }
+            }
+
+            if let Some(max_rate) = rule.max_rate {
+                effects.push(PolicyEffect::Throttled { subnet, max_rate });
+            }
+        }
+
+        effects
+    }
+
+    /// Waits until a probe can be sent to `ip` without exceeding the maximum
+    /// rate of any matching rule.
+    pub(crate) async fn throttle(&self, ip: &IpAddr) {
+        for rule in self.rules_for(ip) {
+            if let Some(rate) = rule.max_rate {
+                rule.limiter.wait(rate).await;
+            }
+        }
+    }
+
+    fn rules_for<'a>(&'a self, ip: &IpAddr) -> impl Iterator<Item = &'a PolicyRule> {
+        let ip = *ip;
+        self.rules
+            .iter()
+            .filter(move |rule| rule.subnet.contains(&ip))
+    }
+}
+
+/// Spaces out probes to at most a given rate.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    /// Waits for the next probe's turn at `rate` probes per second.
+    pub(crate) async fn wait(&self, rate: u32) {
+        let interval = Duration::from_secs(1) / rate.max(1);
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = next.map_or(Instant::now(), |next| next.max(Instant::now()));
+            *next = Some(slot + interval);
+            slot
+        };
+
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+pub(crate) fn deserialize_ports<'de, D>(deserializer: D) -> Result<Option<Ports>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+        [[rule]]
+        subnet = "10.0.0.0/8"
+        max_rate = 100
+        forbidden_ports = "135-139,445"
+
+        [[rule]]
+        subnet = "10.1.0.0/16"
+        forbidden_ports = "22"
+        forbidden_protocols = ["icmp", "udp"]
+    "#;
+
+    fn policy() -> Policy {
+        toml::from_str(POLICY).unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn allows_hosts_no_rule_matches() {
+        let policy = policy();
+        let outside = ip("192.0.2.1");
+        assert!(policy.allows_port(&outside, 445));
+        assert!(policy.allows_protocol(&outside, Protocol::Icmp));
+        assert!(policy
+            .review(&outside, &"1-65535".parse().unwrap())
+            .is_empty());
+
+        let empty = Policy::default();
+        assert!(empty.allows_port(&ip("10.0.0.1"), 445));
+    }
+
+    #[test]
+    fn denies_what_a_rule_forbids() {
+        let policy = policy();
+        let host = ip("10.2.0.1");
+        assert!(!policy.allows_port(&host, 137));
+        assert!(!policy.allows_port(&host, 445));
+        assert!(policy.allows_port(&host, 22));
+        assert!(policy.allows_port(&host, 140));
+        assert!(policy.allows_protocol(&host, Protocol::Icmp));
+        assert!(policy.allows_protocol(&host, Protocol::Tcp));
+    }
+
+    #[test]
+    fn applies_every_overlapping_rule() {
+        let policy = policy();
+        let host = ip("10.1.2.3");
+        // from the /8 and the /16 alike
+        assert!(!policy.allows_port(&host, 445));
+        assert!(!policy.allows_port(&host, 22));
+        assert!(policy.allows_port(&host, 80));
+        assert!(!policy.allows_protocol(&host, Protocol::Icmp));
+        assert!(!policy.allows_protocol(&host, Protocol::Udp));
+        assert!(policy.allows_protocol(&host, Protocol::Tcp));
+
+        let effects: Vec<String> = policy
+            .review(&host, &"20-25,130-140".parse().unwrap())
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            effects,
+            [
+                "5 ports forbidden by rule for 10.0.0.0/8: 135-139",
+                "throttled to 100 probes/s by rule for 10.0.0.0/8",
+                "pinging forbidden by rule for 10.1.0.0/16, host will be assumed up",
+                "UDP probes forbidden by rule for 10.1.0.0/16, UDP ports will be skipped",
+                "1 ports forbidden by rule for 10.1.0.0/16: 22",
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_out_forbidden_ports_not_scanned() {
+        let effects = policy().review(&ip("10.2.0.1"), &"80,443".parse().unwrap());
+        assert!(matches!(
+            effects[..],
+            [PolicyEffect::Throttled { max_rate: 100, .. }]
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        let bad_ports = "[[rule]]\nsubnet = \"10.0.0.0/8\"\nforbidden_ports = \"1-x\"";
+        assert!(toml::from_str::<Policy>(bad_ports).is_err());
+
+        let bad_subnet = "[[rule]]\nsubnet = \"10.0.0.0/33\"";
+        assert!(toml::from_str::<Policy>(bad_subnet).is_err());
+
+        let bad_protocol = "[[rule]]\nsubnet = \"10.0.0.0/8\"\nforbidden_protocols = [\"sctp\"]";
+        assert!(toml::from_str::<Policy>(bad_protocol).is_err());
+
+        assert!(toml::from_str::<Policy>("[[rule]]\nmax_rate = 1").is_err());
+    }
+}
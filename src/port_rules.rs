@@ -0,0 +1,178 @@
+use std::{fs, io, path::Path};
+
+use serde::Deserialize;
+
+use crate::{policy::Protocol, ports::Ports, scanner::ScanEvent};
+
+/// More ports to check on hosts found running something interesting,
+/// declared in a rules file, e.g.
+///
+/// ```toml
+/// [[rule]]
+/// open = "8080"
+/// scan = "8000-8099"
+///
+/// [[rule]]
+/// open = "445"
+/// scan = "135,137-139,3389,5985-5986"
+///
+/// [[rule]]
+/// service = "http"
+/// scan = "8000,8008,8080,8443,8888"
+/// ```
+///
+/// A rule matches an open TCP port listed in `open`, or running the service
+/// named by `service` if service detection is on, or both if it has both.
+/// Ports checked because of a rule can match rules in turn.
+#[derive(Debug, Default, Deserialize)]
+pub struct PortRules {
+    #[serde(default, rename = "rule")]
+    rules: Vec<PortRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PortRule {
+    open: Option<Ports>,
+    service: Option<String>,
+    scan: Ports,
+}
+
+impl PortRules {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let rules: Self =
+            toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        rules.check()?;
+        Ok(rules)
+    }
+
+    /// Checks that every rule has something to match.
+    fn check(&self) -> io::Result<()> {
+        if let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.open.is_none() && rule.service.is_none())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "rule scanning {} needs open ports or a service to match",
+                    rule.scan
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The ports to check because of what `event` found.
+    pub(crate) fn triggered_by<'a>(
+        &'a self,
+        event: &'a ScanEvent,
+    ) -> impl Iterator<Item = &'a Ports> {
+        let matched = event.open && event.protocol == Protocol::Tcp;
+        self.rules
+            .iter()
+            .filter(move |_| matched)
+            .filter(|rule| {
+                rule.open
+                    .as_ref()
+                    .is_none_or(|open| open.contains(event.port))
+            })
+            .filter(|rule| {
+                rule.service.as_ref().is_none_or(|name| {
+                    event
+                        .service
+                        .as_ref()
+                        .is_some_and(|service| service.name.eq_ignore_ascii_case(name))
+                })
+            })
+            .map(|rule| &rule.scan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        [[rule]]
+        open = "8080"
+        scan = "8000-8099"
+
+        [[rule]]
+        service = "http"
+        scan = "8443,8888"
+
+        [[rule]]
+        open = "80,8080"
+        service = "http"
+        scan = "3000"
+    "#;
+
+    fn rules(text: &str) -> io::Result<PortRules> {
+        let rules: PortRules =
+            toml::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        rules.check()?;
+        Ok(rules)
+    }
+
+    fn event(json: &str) -> ScanEvent {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn triggered(rules: &PortRules, event: &ScanEvent) -> Vec<String> {
+        rules.triggered_by(event).map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        let err = rules("[[rule]]\nscan = \"22\"").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rule scanning 22 needs open ports or a service to match"
+        );
+
+        assert!(rules("[[rule]]\nopen = \"80\"").is_err());
+        assert!(rules("[[rule]]\nopen = \"80\"\nscan = \"1-70000\"").is_err());
+        assert!(rules("[[rule]]\nopen = \"x\"\nscan = \"22\"").is_err());
+        assert!(rules("[[rule]]\nopen = \"80\"\nscan = \"22\"\nports = \"1\"").is_err());
+        assert!(rules("[[rule]\nopen = \"80\"").is_err());
+    }
+
+    #[test]
+    fn matches_open_tcp_ports() {
+        let rules = rules(RULES).unwrap();
+        let open = event(r#"{"ip": "192.0.2.1", "port": 8080, "open": true}"#);
+        assert_eq!(triggered(&rules, &open), ["8000-8099"]);
+
+        let closed = event(r#"{"ip": "192.0.2.1", "port": 8080, "open": false}"#);
+        assert!(triggered(&rules, &closed).is_empty());
+
+        let udp = event(r#"{"ip": "192.0.2.1", "port": 8080, "protocol": "udp", "open": true}"#);
+        assert!(triggered(&rules, &udp).is_empty());
+
+        let other = event(r#"{"ip": "192.0.2.1", "port": 22, "open": true}"#);
+        assert!(triggered(&rules, &other).is_empty());
+    }
+
+    #[test]
+    fn applies_every_overlapping_rule() {
+        let rules = rules(RULES).unwrap();
+        // the port, the service and both together
+        let http = event(
+            r#"{"ip": "192.0.2.1", "port": 8080, "open": true, "service": {"name": "HTTP"}}"#,
+        );
+        assert_eq!(triggered(&rules, &http), ["8000-8099", "8443,8888", "3000"]);
+
+        // rules with both need both to match
+        let elsewhere =
+            event(r#"{"ip": "192.0.2.1", "port": 81, "open": true, "service": {"name": "http"}}"#);
+        assert_eq!(triggered(&rules, &elsewhere), ["8443,8888"]);
+
+        let not_http =
+            event(r#"{"ip": "192.0.2.1", "port": 80, "open": true, "service": {"name": "ssh"}}"#);
+        assert!(triggered(&rules, &not_http).is_empty());
+    }
+}
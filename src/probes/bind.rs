@@ -0,0 +1,241 @@
+//! Where connections come from, on machines with more than one address or
+//! interface.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6},
+};
+
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, RawFd};
+
+use socket2::Type;
+use surge_ping::{Config as PingConfig, ICMP};
+use tokio::net::{TcpSocket, TcpStream, UdpSocket};
+
+/// The address, interface and port connections are made from, where not left
+/// to the OS.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Bind {
+    pub(crate) ip: Option<IpAddr>,
+    /// Only supported on Linux.
+    pub(crate) interface: Option<String>,
+    /// Used for port checks only, as the probes following them connect to
+    /// the same port again, which the old connection still blocks for a
+    /// while.
+    pub(crate) port: Option<u16>,
+    /// Indexes of the interfaces link-local IPv6 targets are on, e.g. 2 for
+    /// `fe80::1%2`.
+    pub(crate) scopes: HashMap<Ipv6Addr, u32>,
+    /// Flow label of IPv6 packets, only supported on Linux.
+    pub(crate) flow_label: Option<u32>,
+    /// Whether IPv6 packets carry a hop-by-hop options header, only supported
+    /// on Linux.
+    pub(crate) hop_by_hop: bool,
+    /// Whether IPv6 packets carry a destination options header, only
+    /// supported on Linux.
+    pub(crate) destination_options: bool,
+}
+
+/// An options header holding nothing but padding, which every node it's for
+/// has to parse but none acts on. The kernel fills in the next header.
+#[cfg(target_os = "linux")]
+const PADDING_OPTIONS: [u8; 8] = [0, 0, 1, 4, 0, 0, 0, 0];
+
+/// struct in6_flowlabel_req, which leases a flow label to a socket.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FlowLabelRequest {
+    dst: [u8; 16],
+    label: u32,
+    action: u8,
+    share: u8,
+    flags: u16,
+    expires: u16,
+    linger: u16,
+    pad: u32,
+}
+
+impl Bind {
+    /// Connects to `ip:port` over TCP.
+    pub(crate) async fn connect(&self, ip: IpAddr, port: u16) -> io::Result<TcpStream> {
+        let socket = self.tcp_socket(ip, 0)?;
+        socket.connect(self.addr(ip, port)).await
+    }
+
+    /// Connects to `ip:port` over TCP from the source port, if one was given,
+    /// to see whether it's open.
+    pub(crate) async fn check(&self, ip: IpAddr, port: u16) -> io::Result<TcpStream> {
+        let Some(source_port) = self.port else {
+            return self.connect(ip, port).await;
+        };
+
+        let stream = self
+            .tcp_socket(ip, source_port)?
+            .connect(self.addr(ip, port))
+            .await?;
+
+        // resetting rather than closing leaves nothing in TIME_WAIT, which
+        // would keep the next scan from connecting the same way for a minute
+        stream.set_zero_linger()?;
+        Ok(stream)
+    }
+
+    /// `ip:port`, with the scope ID of `ip` if it's link-local and the flow
+    /// label packets to it carry.
+    pub(crate) fn addr(&self, ip: IpAddr, port: u16) -> SocketAddr {
+        match ip {
+            IpAddr::V4(_) => SocketAddr::new(ip, port),
+            IpAddr::V6(ip) => {
+                // sin6_flowinfo is in network byte order
+                let flowinfo = self.flow_label.unwrap_or(0).to_be();
+                SocketAddrV6::new(ip, port, flowinfo, self.scope_id(ip)).into()
+            }
+        }
+    }
+
+    /// The scope ID of `ip`, 0 unless one was given.
+    pub(crate) fn scope_id(&self, ip: Ipv6Addr) -> u32 {
+        self.scopes.get(&ip).copied().unwrap_or(0)
+    }
+
+    /// A TCP socket to connect to `ip` from, on `source_port` unless it's 0.
+    pub(crate) fn tcp_socket(&self, ip: IpAddr, source_port: u16) -> io::Result<TcpSocket> {
+        let socket = match ip {
+            IpAddr::V4(_) => TcpSocket::new_v4()?,
+            IpAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        socket.bind_device(self.interface.as_deref().map(str::as_bytes))?;
+
+        if let IpAddr::V6(ip) = ip {
+            self.set_ipv6_options(&socket, ip)?;
+        }
+
+        if source_port != 0 {
+            // connections to different ports can share the source port
+            socket.set_reuseaddr(true)?;
+            #[cfg(unix)]
+            socket.set_reuseport(true)?;
+        }
+
+        if self.ip.is_some() || source_port != 0 {
+            let local = self.ip.unwrap_or(match ip {
+                IpAddr::V4(_) => [0; 4].into(),
+                IpAddr::V6(_) => [0u16; 8].into(),
+            });
+            socket.bind(SocketAddr::new(local, source_port))?;
+        }
+
+        Ok(socket)
+    }
+
+    /// A UDP socket to talk to `ip` from.
+    pub(crate) async fn udp(&self, ip: IpAddr) -> io::Result<UdpSocket> {
+        let local: IpAddr = match (self.ip, ip) {
+            (Some(source), _) => source,
+            (None, IpAddr::V4(_)) => [0; 4].into(),
+            (None, IpAddr::V6(_)) => [0u16; 8].into(),
+        };
+
+        let socket = UdpSocket::bind((local, 0)).await?;
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        socket.bind_device(self.interface.as_deref().map(str::as_bytes))?;
+
+        if let IpAddr::V6(ip) = ip {
+            self.set_ipv6_options(&socket, ip)?;
+        }
+
+        Ok(socket)
+    }
+
+    /// Whether any IPv6 extension headers or a flow label were asked for.
+    pub(crate) fn has_ipv6_options(&self) -> bool {
+        self.flow_label.is_some() || self.hop_by_hop || self.destination_options
+    }
+
+    /// Adds the IPv6 extension headers asked for to packets `socket` sends to
+    /// `ip`, and leases it the flow label asked for. Leasing a flow label
+    /// others use too and sending hop-by-hop or destination options need
+    /// CAP_NET_RAW.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn set_ipv6_options(&self, socket: &impl AsRawFd, ip: Ipv6Addr) -> io::Result<()> {
+        const IPV6_FL_A_GET: u8 = 0;
+        const IPV6_FL_S_ANY: u8 = 255;
+        const IPV6_FL_F_CREATE: u16 = 1;
+
+        let fd = socket.as_raw_fd();
+        if self.hop_by_hop {
+            set_ipv6_option(fd, libc::IPV6_HOPOPTS, &PADDING_OPTIONS)?;
+        }
+
+        if self.destination_options {
+            set_ipv6_option(fd, libc::IPV6_DSTOPTS, &PADDING_OPTIONS)?;
+        }
+
+        if let Some(label) = self.flow_label {
+            // shared, since every connection to `ip` gets a socket of its own
+            let request = FlowLabelRequest {
+                dst: ip.octets(),
+                label: label.to_be(),
+                action: IPV6_FL_A_GET,
+                share: IPV6_FL_S_ANY,
+                flags: IPV6_FL_F_CREATE,
+                expires: 0,
+                linger: 0,
+                pad: 0,
+            };
+            set_ipv6_option(fd, libc::IPV6_FLOWLABEL_MGR, &request)?;
+            set_ipv6_option(fd, libc::IPV6_FLOWINFO_SEND, &1)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn set_ipv6_options<S>(&self, _socket: &S, _ip: Ipv6Addr) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// How to set up pings over ICMP `version`, on a socket of type
+    /// `sock_type` if possible or else the other kind.
+    pub(crate) fn ping_config(&self, version: ICMP, sock_type: Type) -> PingConfig {
+        let mut config = PingConfig::builder()
+            .kind(version)
+            .sock_type_hint(sock_type);
+        if let Some(source) = self.ip {
+            config = config.bind(SocketAddr::new(source, 0));
+        }
+
+        if let Some(interface) = &self.interface {
+            config = config.interface(interface);
+        }
+
+        config.build()
+    }
+}
+
+/// Sets IPv6 socket option `name` of `fd` to `value`.
+#[cfg(target_os = "linux")]
+fn set_ipv6_option<T>(fd: RawFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    // SAFETY: the kernel reads at most the given size from `value`, which is
+    // that big
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_IPV6,
+            name,
+            (value as *const T).cast(),
+            size_of::<T>() as libc::socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
@@ -0,0 +1,85 @@
+//! Records the bytes probes exchange with a port, to troubleshoot
+//! misdetections and write new fingerprints.
+
+use std::fmt::{Display, Write};
+
+/// Everything sent to and received from a port while probing it, in order.
+#[derive(Debug, Default)]
+pub(crate) struct Transcript {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    Sent(Vec<u8>),
+    Received(Vec<u8>),
+    /// Probing carried on over a new connection.
+    Reconnected,
+}
+
+impl Transcript {
+    pub(crate) fn sent(&mut self, data: &[u8]) {
+        if !data.is_empty() {
+            self.entries.push(Entry::Sent(data.to_vec()));
+        }
+    }
+
+    pub(crate) fn received(&mut self, data: &[u8]) {
+        if !data.is_empty() {
+            self.entries.push(Entry::Received(data.to_vec()));
+        }
+    }
+
+    pub(crate) fn reconnected(&mut self) {
+        if !self.entries.is_empty() {
+            self.entries.push(Entry::Reconnected);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Formats as a hexdump of each chunk, e.g.
+///
+/// ```text
+/// > sent 18 bytes
+/// 00000000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|
+/// 00000010  0d 0a                                             |..|
+/// ```
+impl Display for Transcript {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for entry in &self.entries {
+            let (direction, data) = match entry {
+                Entry::Sent(data) => ("> sent", data),
+                Entry::Received(data) => ("< received", data),
+                Entry::Reconnected => {
+                    writeln!(f, "-- new connection --")?;
+                    continue;
+                }
+            };
+
+            writeln!(f, "{direction} {} bytes", data.len())?;
+            for (idx, line) in data.chunks(16).enumerate() {
+                let mut hex = String::new();
+                for (col, byte) in line.iter().enumerate() {
+                    let gap = if col == 8 { "  " } else { " " };
+                    write!(hex, "{}{byte:02x}", if col == 0 { "" } else { gap })?;
+                }
+
+                let ascii: String = line
+                    .iter()
+                    .map(|&byte| match byte {
+                        0x20..=0x7e => byte as char,
+                        _ => '.',
+                    })
+                    .collect();
+
+                writeln!(f, "{:08x}  {hex:<48}  |{ascii}|", idx * 16)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+//! Just enough of Kerberos and LDAP to recognize directory servers, Active
+//! Directory domain controllers in particular, and what they say about the
+//! domain they serve.
+
+use std::collections::HashMap;
+
+use super::Service;
+
+/// Realm asked for in Kerberos requests. Realms KDCs answer with that differ
+/// from it are their own.
+const REALM: &str = "QAPPER.INVALID";
+
+/// Root DSE attributes asked for, which Active Directory fills in with the
+/// domain it serves.
+const ROOT_DSE_ATTRIBUTES: &[&str] = &[
+    "defaultNamingContext",
+    "namingContexts",
+    "ldapServiceName",
+    "domainFunctionality",
+    "isGlobalCatalogReady",
+    "vendorVersion",
+];
+
+/// Builds an AS-REQ for a made-up principal, framed for TCP. KDCs answer it
+/// with an error, which is all we need.
+pub(super) fn kerberos_request() -> Vec<u8> {
+    let principal = |name_type: u8, names: &[&str]| {
+        let names: Vec<u8> = names
+            .iter()
+            .flat_map(|name| der(0x1b, name.as_bytes()))
+            .collect();
+        der(
+            0x30,
+            &[
+                der(0xa0, &der(0x02, &[name_type])),
+                der(0xa1, &der(0x30, &names)),
+            ]
+            .concat(),
+        )
+    };
+
+    let etypes = [18, 17, 23].map(|etype| der(0x02, &[etype])).concat();
+    let body = [
+        der(0xa0, &der(0x03, &[0, 0x40, 0, 0, 0x10])), // kdc-options
+        der(0xa1, &principal(1, &["qapper"])),         // cname
+        der(0xa2, &der(0x1b, REALM.as_bytes())),       // realm
+        der(0xa3, &principal(2, &["krbtgt", REALM])),  // sname
+        der(0xa5, &der(0x18, b"20370913024805Z")),     // till
+        der(0xa7, &der(0x02, &[0x51, 0x41, 0x50, 0x52])), // nonce
+        der(0xa8, &der(0x30, &etypes)),
+    ]
+    .concat();
+
+    let request = der(
+        0x6a,
+        &der(
+            0x30,
+            &[
+                der(0xa1, &der(0x02, &[5])),  // pvno
+                der(0xa2, &der(0x02, &[10])), // msg-type
+                der(0xa4, &der(0x30, &body)),
+            ]
+            .concat(),
+        ),
+    );
+
+    let mut framed = (request.len() as u32).to_be_bytes().to_vec();
+    framed.extend_from_slice(&request);
+    framed
+}
+
+/// Recognizes the KDC's answer to [`kerberos_request`], noting the realm if
+/// it gave its own.
+pub(super) fn kerberos_service(response: &[u8]) -> Option<Service> {
+    let (tag, message, _) = tlv(response.get(4..)?)?;
+    // KRB-ERROR, or AS-REP if the made-up principal somehow exists
+    if tag != 0x7e && tag != 0x6b {
+        return None;
+    }
+
+    let (0x30, mut fields, _) = tlv(message)? else {
+        return None;
+    };
+
+    let mut realm = None;
+    while let Some((tag, value, rest)) = tlv(fields) {
+        if tag == 0xa9 {
+            realm = tlv(value).map(|(_, realm, _)| String::from_utf8_lossy(realm).into_owned());
+        }
+
+        fields = rest;
+    }
+
+    Some(Service {
+        name: "kerberos".to_string(),
+        version: None,
+        details: realm
+            .filter(|realm| !realm.is_empty() && realm != REALM)
+            .map(|realm| format!("realm {realm}")),
+    })
+}
+
+/// Builds an anonymous search for the root DSE, which describes the server.
+pub(super) fn ldap_request() -> Vec<u8> {
+    let attributes: Vec<u8> = ROOT_DSE_ATTRIBUTES
+        .iter()
+        .flat_map(|attribute| der(0x04, attribute.as_bytes()))
+        .collect();
+
+    let search = [
+        der(0x04, b""),            // base object: the root DSE
+        der(0x0a, &[0]),           // scope: base object
+        der(0x0a, &[0]),           // never dereference aliases
+        der(0x02, &[0]),           // no size limit
+        der(0x02, &[0]),           // no time limit
+        der(0x01, &[0]),           // not types only
+        der(0x87, b"objectClass"), // filter: (objectClass=*)
+        der(0x30, &attributes),
+    ]
+    .concat();
+
+    der(0x30, &[der(0x02, &[1]), der(0x63, &search)].concat())
+}
+
+/// Recognizes LDAP responses to [`ldap_request`], summarizing the root DSE
+/// if the server returned it.
+pub(super) fn ldap_service(response: &[u8]) -> Option<Service> {
+    let mut messages = response;
+    let mut is_ldap = false;
+    let mut root_dse = HashMap::new();
+
+    while let Some((0x30, message, rest)) = tlv(messages) {
+        let (0x02, _, message) = tlv(message)? else {
+            return None;
+        };
+
+        match tlv(message)? {
+            (0x64, entry, _) => {
+                is_ldap = true;
+                root_dse = attributes(entry).unwrap_or_default();
+            }
+            // search done, or a notice of disconnection
+            (0x65 | 0x78, _, _) => is_ldap = true,
+            _ => return None,
+        }
+
+        messages = rest;
+    }
+
+    if !is_ldap {
+        return None;
+    }
+
+    let first = |name: &str| root_dse.get(name).and_then(|values| values.first());
+    let mut details = vec![];
+    if first("domainfunctionality").is_some() || first("ldapservicename").is_some() {
+        details.push("Active Directory".to_string());
+    }
+
+    if first("isglobalcatalogready").is_some_and(|ready| ready == "TRUE") {
+        details.push("global catalog".to_string());
+    }
+
+    // e.g. corp.example.com:dc01$@CORP.EXAMPLE.COM
+    if let Some((_, realm)) = first("ldapservicename").and_then(|name| name.rsplit_once('@')) {
+        details.push(format!("realm {realm}"));
+    }
+
+    if let Some(context) = first("defaultnamingcontext").or_else(|| first("namingcontexts")) {
+        details.push(format!("naming context {context}"));
+    }
+
+    Some(Service {
+        name: "ldap".to_string(),
+        version: first("vendorversion").cloned(),
+        details: Some(details.join(", ")).filter(|details| !details.is_empty()),
+    })
+}
+
+/// The attributes of a search result entry, by lowercase name.
+fn attributes(entry: &[u8]) -> Option<HashMap<String, Vec<String>>> {
+    let (0x04, _, entry) = tlv(entry)? else {
+        return None;
+    };
+
+    let (0x30, mut list, _) = tlv(entry)? else {
+        return None;
+    };
+
+    let mut attributes = HashMap::new();
+    while let Some((0x30, attribute, rest)) = tlv(list) {
+        let (0x04, name, attribute) = tlv(attribute)? else {
+            return None;
+        };
+
+        let (0x31, mut set, _) = tlv(attribute)? else {
+            return None;
+        };
+
+        let mut values = vec![];
+        while let Some((0x04, value, rest)) = tlv(set) {
+            values.push(String::from_utf8_lossy(value).into_owned());
+            set = rest;
+        }
+
+        let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+        attributes.insert(name, values);
+        list = rest;
+    }
+
+    Some(attributes)
+}
+
+/// Encodes a DER value.
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    match contents.len() {
+        len @ 0..=0x7f => encoded.push(len as u8),
+        len @ 0x80..=0xff => encoded.extend_from_slice(&[0x81, len as u8]),
+        len => {
+            encoded.push(0x82);
+            encoded.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+
+    encoded.extend_from_slice(contents);
+    encoded
+}
+
+/// Splits the first BER value off `data`, returning its tag, contents and
+/// whatever follows.
+fn tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return None;
+        }
+
+        let len = rest
+            .get(..count)?
+            .iter()
+            .fold(0, |len, &byte| len << 8 | byte as usize);
+        (len, &rest[count..])
+    };
+
+    let contents = rest.get(..len)?;
+    Some((tag, contents, &rest[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A KRB-ERROR (principal unknown) for `realm`, framed for TCP.
+    fn krb_error(realm: &str) -> Vec<u8> {
+        let error = der(
+            0x7e,
+            &der(
+                0x30,
+                &[
+                    der(0xa0, &der(0x02, &[5])),  // pvno
+                    der(0xa1, &der(0x02, &[30])), // msg-type
+                    der(0xa6, &der(0x02, &[6])),  // error-code
+                    der(0xa9, &der(0x1b, realm.as_bytes())),
+                ]
+                .concat(),
+            ),
+        );
+
+        let mut framed = (error.len() as u32).to_be_bytes().to_vec();
+        framed.extend_from_slice(&error);
+        framed
+    }
+
+    /// An LDAP message with ID 1 and the given protocol operation.
+    fn ldap_message(tag: u8, contents: &[u8]) -> Vec<u8> {
+        der(0x30, &[der(0x02, &[1]), der(tag, contents)].concat())
+    }
+
+    /// A root DSE search result entry with the given attributes.
+    fn root_dse(attributes: &[(&str, &str)]) -> Vec<u8> {
+        let attributes: Vec<u8> = attributes
+            .iter()
+            .flat_map(|(name, value)| {
+                let values = der(0x31, &der(0x04, value.as_bytes()));
+                der(0x30, &[der(0x04, name.as_bytes()), values].concat())
+            })
+            .collect();
+        ldap_message(0x64, &[der(0x04, b""), der(0x30, &attributes)].concat())
+    }
+
+    fn search_done() -> Vec<u8> {
+        ldap_message(
+            0x65,
+            &[der(0x0a, &[0]), der(0x04, b""), der(0x04, b"")].concat(),
+        )
+    }
+
+    const DOMAIN_CONTROLLER: &[(&str, &str)] = &[
+        ("domainFunctionality", "7"),
+        ("defaultNamingContext", "DC=corp,DC=example,DC=com"),
+        ("isGlobalCatalogReady", "TRUE"),
+        ("ldapServiceName", "corp.example.com:dc01$@CORP.EXAMPLE.COM"),
+    ];
+
+    #[test]
+    fn reads_short_form_lengths() {
+        assert_eq!(
+            tlv(&[0x04, 2, b'h', b'i', 0xff]),
+            Some((0x04, &b"hi"[..], &[0xff][..]))
+        );
+        assert_eq!(tlv(&[0x05, 0]), Some((0x05, &[][..], &[][..])));
+    }
+
+    #[test]
+    fn reads_long_form_lengths() {
+        for len in [0x80, 0xff, 0x100, 0x1234] {
+            let encoded = der(0x04, &vec![0xaa; len]);
+            let (tag, contents, rest) = tlv(&encoded).unwrap();
+            assert_eq!((tag, contents.len(), rest.len()), (0x04, len, 0));
+        }
+
+        // BER allows more length octets than needed
+        assert_eq!(
+            tlv(&[0x04, 0x82, 0, 1, b'x']),
+            Some((0x04, &b"x"[..], &[][..]))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_values() {
+        assert_eq!(tlv(&[]), None);
+        assert_eq!(tlv(&[0x04]), None);
+        assert_eq!(tlv(&[0x04, 3, b'a', b'b']), None);
+        assert_eq!(tlv(&[0x04, 0x82, 0x01]), None);
+
+        let encoded = der(0x04, &[0xaa; 300]);
+        for len in 0..encoded.len() {
+            assert_eq!(tlv(&encoded[..len]), None, "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn rejects_over_long_lengths() {
+        // indefinite, then more length octets than we read
+        assert_eq!(tlv(&[0x30, 0x80, 0x04, 0, 0, 0]), None);
+        assert_eq!(tlv(&[0x04, 0x85, 0, 0, 0, 0, 1, b'x']), None);
+        assert_eq!(tlv(&[0x04, 0x84, 0xff, 0xff, 0xff, 0xff, b'x']), None);
+    }
+
+    #[test]
+    fn reads_kerberos_realms() {
+        let service = kerberos_service(&krb_error("CORP.EXAMPLE.COM")).unwrap();
+        assert_eq!(service.name, "kerberos");
+        assert_eq!(service.details.as_deref(), Some("realm CORP.EXAMPLE.COM"));
+
+        // the KDC only repeated the realm we asked for
+        let service = kerberos_service(&krb_error(REALM)).unwrap();
+        assert_eq!(service.details, None);
+    }
+
+    #[test]
+    fn rejects_truncated_kerberos_answers() {
+        let answer = krb_error("CORP.EXAMPLE.COM");
+        for len in 0..answer.len() {
+            assert_eq!(kerberos_service(&answer[..len]), None, "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn ignores_other_protocols() {
+        let http = b"HTTP/1.1 400 Bad Request\r\n\r\n";
+        assert_eq!(kerberos_service(http), None);
+        assert_eq!(ldap_service(http), None);
+        assert_eq!(kerberos_service(&[0, 0, 0, 2, 0x30, 0]), None);
+        // a message that isn't a search answer
+        assert_eq!(ldap_service(&ldap_message(0x61, &[])), None);
+    }
+
+    #[test]
+    fn spots_domain_controllers() {
+        let response = [root_dse(DOMAIN_CONTROLLER), search_done()].concat();
+        let service = ldap_service(&response).unwrap();
+        assert_eq!(service.name, "ldap");
+        assert_eq!(
+            service.details.as_deref(),
+            Some(
+                "Active Directory, global catalog, realm CORP.EXAMPLE.COM, \
+                naming context DC=corp,DC=example,DC=com"
+            )
+        );
+    }
+
+    #[test]
+    fn reads_other_directories() {
+        let entry = root_dse(&[
+            ("namingContexts", "dc=example,dc=org"),
+            ("vendorVersion", "389-Directory/2.4"),
+        ]);
+        let service = ldap_service(&[entry, search_done()].concat()).unwrap();
+        assert_eq!(service.version.as_deref(), Some("389-Directory/2.4"));
+        assert_eq!(
+            service.details.as_deref(),
+            Some("naming context dc=example,dc=org")
+        );
+
+        // servers hiding the root DSE still answer the search
+        let service = ldap_service(&search_done()).unwrap();
+        assert_eq!((service.version, service.details), (None, None));
+    }
+
+    #[test]
+    fn survives_truncated_ldap_answers() {
+        let entry = root_dse(DOMAIN_CONTROLLER);
+        let response = [entry.clone(), search_done()].concat();
+        for len in 0..response.len() {
+            let service = ldap_service(&response[..len]);
+            if len < entry.len() {
+                assert_eq!(service, None, "{len} bytes");
+            } else {
+                // cut off in the search done, after the whole entry
+                assert!(service.unwrap().details.is_some(), "{len} bytes");
+            }
+        }
+    }
+}
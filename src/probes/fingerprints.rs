@@ -0,0 +1,84 @@
+use std::sync::LazyLock;
+
+use regex::bytes::Regex;
+
+use super::Service;
+
+/// A pattern matched against what a service sent. The first capture group,
+/// if any, is the version.
+struct Fingerprint {
+    service: &'static str,
+    pattern: Regex,
+}
+
+/// Services which talk first, matched against their greeting. More specific
+/// patterns come before generic ones for the same service.
+static GREETINGS: LazyLock<Vec<Fingerprint>> = LazyLock::new(|| {
+    fingerprints(&[
+        ("ssh", r"^SSH-[\d.]+-(\S+)"),
+        (
+            "ftp",
+            r"^220[ -].*?((?:vsFTPd|ProFTPD|FileZilla Server|Pure-FTPd)[ /]?[\d.]*)",
+        ),
+        ("ftp", r"(?i)^220[ -].*ftp"),
+        (
+            "smtp",
+            r"^220[ -].*?((?:Postfix|Exim [\d.]+|Sendmail [\d./]+|Microsoft ESMTP MAIL Service))",
+        ),
+        ("smtp", r"^220[ -].*SMTP"),
+        ("pop3", r"^\+OK.*?(Dovecot|Cyrus|Courier)"),
+        ("pop3", r"^\+OK"),
+        ("imap", r"^\* OK.*?(Dovecot|Cyrus|Courier)"),
+        ("imap", r"^\* OK"),
+        ("mysql", r"(?s-u)^.{4}\x0a([\d.]+[^\x00]*)\x00"),
+        ("vnc", r"^RFB (\d{3}\.\d{3})"),
+        ("telnet", r"(?-u)^\xff[\xfb-\xfe]"),
+    ])
+});
+
+/// Matched against the `Server` header of HTTP responses.
+static HTTP_SERVERS: LazyLock<Vec<Fingerprint>> = LazyLock::new(|| {
+    fingerprints(&[
+        ("http", r"(?mi)^server:[ \t]*([^\r\n]+)"),
+        ("http", r"^HTTP/\d\.\d \d{3}"),
+    ])
+});
+
+fn fingerprints(patterns: &[(&'static str, &str)]) -> Vec<Fingerprint> {
+    patterns
+        .iter()
+        .map(|&(service, pattern)| Fingerprint {
+            service,
+            pattern: Regex::new(pattern).unwrap(),
+        })
+        .collect()
+}
+
+fn find(fingerprints: &[Fingerprint], response: &[u8]) -> Option<Service> {
+    fingerprints.iter().find_map(|fingerprint| {
+        let captures = fingerprint.pattern.captures(response)?;
+        let version = captures.get(1).map(|version| {
+            String::from_utf8_lossy(version.as_bytes())
+                .trim()
+                .to_string()
+        });
+
+        Some(Service {
+            name: fingerprint.service.to_string(),
+            version,
+            details: None,
+        })
+    })
+}
+
+pub(super) fn match_greeting(greeting: &[u8]) -> Option<Service> {
+    find(&GREETINGS, greeting)
+}
+
+pub(super) fn match_http(response: &[u8]) -> Option<Service> {
+    if !response.starts_with(b"HTTP/") {
+        return None;
+    }
+
+    find(&HTTP_SERVERS, response)
+}
@@ -0,0 +1,289 @@
+//! Query protocols game servers answer with their name, map and players:
+//! Steam's A2S, Minecraft Bedrock's unconnected ping and Quake 3's
+//! `getstatus`.
+
+use super::Service;
+
+/// Ports Source engine games usually answer A2S queries on.
+pub(super) const A2S_PORTS: std::ops::RangeInclusive<u16> = 27015..=27020;
+pub(super) const BEDROCK_PORT: u16 = 19132;
+pub(super) const QUAKE3_PORT: u16 = 27960;
+
+const HEADER: [u8; 4] = [0xff; 4];
+const BEDROCK_MAGIC: [u8; 16] = [
+    0x00, 0xff, 0xff, 0x00, 0xfe, 0xfe, 0xfe, 0xfe, 0xfd, 0xfd, 0xfd, 0xfd, 0x12, 0x34, 0x56, 0x78,
+];
+
+/// An A2S_INFO request, with the challenge from a previous answer if the
+/// server asked for one.
+pub(super) fn a2s_request(challenge: Option<&[u8]>) -> Vec<u8> {
+    let mut request = HEADER.to_vec();
+    request.push(b'T');
+    request.extend_from_slice(b"Source Engine Query\0");
+    request.extend_from_slice(challenge.unwrap_or_default());
+    request
+}
+
+/// The challenge in an answer to [`a2s_request`], if that's what it was.
+pub(super) fn a2s_challenge(response: &[u8]) -> Option<&[u8]> {
+    match response {
+        [0xff, 0xff, 0xff, 0xff, b'A', challenge @ ..] if challenge.len() == 4 => Some(challenge),
+        _ => None,
+    }
+}
+
+pub(super) fn bedrock_request() -> Vec<u8> {
+    let mut request = vec![0x01];
+    request.extend_from_slice(&[0; 8]); // time
+    request.extend_from_slice(&BEDROCK_MAGIC);
+    request.extend_from_slice(b"qapper\0\0"); // client GUID
+    request
+}
+
+pub(super) fn quake3_request() -> Vec<u8> {
+    [&HEADER[..], b"getstatus\n"].concat()
+}
+
+/// Recognizes the answer to any of the requests above.
+pub(super) fn service(response: &[u8]) -> Option<Service> {
+    a2s_service(response)
+        .or_else(|| bedrock_service(response))
+        .or_else(|| quake3_service(response))
+}
+
+/// An A2S_INFO answer: protocol, then name, map, folder and game, the app
+/// ID, players and maximum players, and later the version.
+fn a2s_service(response: &[u8]) -> Option<Service> {
+    let [0xff, 0xff, 0xff, 0xff, b'I', _protocol, rest @ ..] = response else {
+        return None;
+    };
+
+    let mut strings = rest.split(|&byte| byte == 0);
+    let mut next = || {
+        strings
+            .next()
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+    };
+    let (name, map, folder, game) = (next()?, next()?, next()?, next()?);
+
+    // name, map, folder and game, each with its terminator
+    let offset = [&name, &map, &folder, &game]
+        .iter()
+        .map(|s| s.len() + 1)
+        .sum::<usize>();
+    let [_, _, players, max_players, _bots, _kind, _environment, _visibility, _vac, rest @ ..] =
+        rest.get(offset..)?
+    else {
+        return None;
+    };
+
+    let version = rest
+        .split(|&byte| byte == 0)
+        .next()
+        .map(|version| String::from_utf8_lossy(version).into_owned());
+
+    Some(Service {
+        name: "a2s".to_string(),
+        version: version.filter(|version| !version.is_empty()),
+        details: Some(summary(&name, &game, &map, *players, *max_players)),
+    })
+}
+
+/// A pong, whose server ID is e.g.
+/// `MCPE;Dedicated Server;527;1.19.1;0;10;13253860892328930865;Bedrock level;Survival;...`.
+fn bedrock_service(response: &[u8]) -> Option<Service> {
+    let [0x1c, rest @ ..] = response else {
+        return None;
+    };
+
+    // time and server GUID
+    if rest.get(16..32)? != BEDROCK_MAGIC {
+        return None;
+    }
+
+    let id = String::from_utf8_lossy(rest.get(34..)?).into_owned();
+    let fields: Vec<&str> = id.split(';').collect();
+    let [_edition, name, _protocol, version, players, max_players, ..] = fields[..] else {
+        return None;
+    };
+
+    let map = fields.get(7).copied().unwrap_or_default();
+    Some(Service {
+        name: "minecraft-bedrock".to_string(),
+        version: Some(version.to_string()),
+        details: Some(summary(
+            name,
+            "Minecraft",
+            map,
+            players.parse().unwrap_or_default(),
+            max_players.parse().unwrap_or_default(),
+        )),
+    })
+}
+
+/// A status response: `\key\value` pairs describing the server, then a
+/// line for each player.
+fn quake3_service(response: &[u8]) -> Option<Service> {
+    let rest = response.strip_prefix(&HEADER)?;
+    let rest = rest.strip_prefix(b"statusResponse\n")?;
+    let text = String::from_utf8_lossy(rest);
+    let mut lines = text.lines();
+    let info = lines.next().unwrap_or_default();
+    let players = lines.filter(|line| !line.trim().is_empty()).count();
+
+    let fields: Vec<&str> = info.trim_start_matches('\\').split('\\').collect();
+    let get = |key: &str| {
+        fields
+            .chunks(2)
+            .find(|pair| pair[0].eq_ignore_ascii_case(key))
+            .and_then(|pair| pair.get(1).copied())
+            .unwrap_or_default()
+    };
+
+    Some(Service {
+        name: "quake3".to_string(),
+        version: Some(get("version").to_string()).filter(|version| !version.is_empty()),
+        details: Some(summary(
+            get("sv_hostname"),
+            get("gamename"),
+            get("mapname"),
+            players.min(u8::MAX as usize) as u8,
+            get("sv_maxclients").parse().unwrap_or_default(),
+        )),
+    })
+}
+
+/// E.g. `"My Server", Counter-Strike 2 on de_dust2, 5/10 players`.
+fn summary(name: &str, game: &str, map: &str, players: u8, max_players: u8) -> String {
+    let mut summary = format!("{name:?}");
+    if !game.is_empty() {
+        summary.push_str(&format!(", {game}"));
+    }
+
+    if !map.is_empty() {
+        summary.push_str(&format!(" on {map}"));
+    }
+
+    summary.push_str(&format!(", {players}/{max_players} players"));
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The A2S_INFO answer of a Counter-Strike: Source server, from the
+    /// example on the Valve developer wiki.
+    const A2S_INFO: &[u8] = b"\xff\xff\xff\xffI\x02game2xs.com Counter-Strike Source #1\0\
+        de_dust\0cstrike\0Counter-Strike: Source\0\xf0\x00\x05\x10\x04dl\x00\x001.0.0.22\0";
+
+    const BEDROCK_ID: &str = "MCPE;Dedicated Server;527;1.19.1;0;10;13253860892328930865;\
+        Bedrock level;Survival;1;19132;19133;";
+
+    /// What ioquake3 answers `getstatus` with, with two players on.
+    const QUAKE3_STATUS: &[u8] = b"\xff\xff\xff\xffstatusResponse\n\
+        \\sv_hostname\\noname\\mapname\\q3dm17\\gamename\\baseq3\\sv_maxclients\\8\
+        \\version\\ioq3 1.36 linux-x86_64 Apr 25 2024\n\
+        0 50 \"Sarge\"\n3 70 \"Visor\"\n";
+
+    fn bedrock_pong(id: &str) -> Vec<u8> {
+        let mut pong = vec![0x1c];
+        pong.extend_from_slice(&[0; 8]); // time
+        pong.extend_from_slice(&0xb7ed_2f1e_53a0_6b31_u64.to_be_bytes()); // server GUID
+        pong.extend_from_slice(&BEDROCK_MAGIC);
+        pong.extend_from_slice(&(id.len() as u16).to_be_bytes());
+        pong.extend_from_slice(id.as_bytes());
+        pong
+    }
+
+    #[test]
+    fn reads_a2s_info() {
+        assert_eq!(
+            service(A2S_INFO),
+            Some(Service {
+                name: "a2s".to_string(),
+                version: Some("1.0.0.22".to_string()),
+                details: Some(
+                    "\"game2xs.com Counter-Strike Source #1\", Counter-Strike: Source \
+                    on de_dust, 5/16 players"
+                        .to_string()
+                ),
+            })
+        );
+    }
+
+    #[test]
+    fn answers_a2s_challenges() {
+        let response = b"\xff\xff\xff\xffA\x4b\xa1\x19\x5c";
+        let challenge = a2s_challenge(response).unwrap();
+        assert_eq!(challenge, [0x4b, 0xa1, 0x19, 0x5c]);
+        assert_eq!(service(response), None);
+
+        let request = a2s_request(Some(challenge));
+        assert_eq!(request[..request.len() - 4], a2s_request(None));
+        assert!(request.ends_with(challenge));
+
+        assert_eq!(a2s_challenge(&response[..8]), None);
+        assert_eq!(
+            a2s_challenge(b"\xff\xff\xff\xffA\x4b\xa1\x19\x5c\x00"),
+            None
+        );
+        assert_eq!(a2s_challenge(A2S_INFO), None);
+    }
+
+    #[test]
+    fn reads_bedrock_pongs() {
+        assert_eq!(
+            service(&bedrock_pong(BEDROCK_ID)),
+            Some(Service {
+                name: "minecraft-bedrock".to_string(),
+                version: Some("1.19.1".to_string()),
+                details: Some(
+                    "\"Dedicated Server\", Minecraft on Bedrock level, 0/10 players".to_string()
+                ),
+            })
+        );
+    }
+
+    #[test]
+    fn reads_quake3_status() {
+        assert_eq!(
+            service(QUAKE3_STATUS),
+            Some(Service {
+                name: "quake3".to_string(),
+                version: Some("ioq3 1.36 linux-x86_64 Apr 25 2024".to_string()),
+                details: Some("\"noname\", baseq3 on q3dm17, 2/8 players".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_answers() {
+        // cut off before the player counts, after which only the version
+        // would be missing
+        for len in 0..A2S_INFO.len() - 9 {
+            assert_eq!(service(&A2S_INFO[..len]), None, "A2S, {len} bytes");
+        }
+
+        let pong = bedrock_pong(BEDROCK_ID);
+        let counts = pong.len() - BEDROCK_ID.len() + "MCPE;Dedicated Server;527;1.19.1;0;".len();
+        for len in 0..counts {
+            assert_eq!(service(&pong[..len]), None, "Bedrock, {len} bytes");
+        }
+
+        for len in 0..b"\xff\xff\xff\xffstatusResponse\n".len() {
+            assert_eq!(service(&QUAKE3_STATUS[..len]), None, "Quake 3, {len} bytes");
+        }
+    }
+
+    #[test]
+    fn ignores_other_protocols() {
+        assert_eq!(service(b""), None);
+        assert_eq!(service(b"\xff\xff\xff\xffprint\nbad rcon\n"), None);
+        assert_eq!(service(b"HTTP/1.1 400 Bad Request\r\n\r\n"), None);
+        // a pong without the magic
+        let mut pong = bedrock_pong(BEDROCK_ID);
+        pong[17] ^= 0xff;
+        assert_eq!(service(&pong), None);
+    }
+}
@@ -0,0 +1,327 @@
+//! A full TLS handshake with rustls, to summarize the certificate a port
+//! presents. Nothing is verified: self-signed and expired certificates are
+//! exactly the kind of thing worth finding.
+
+use std::{
+    fmt::Display,
+    io,
+    net::IpAddr,
+    sync::{Arc, LazyLock},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::{timeout_at, Instant},
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{self, CryptoProvider},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        ClientConfig, DigitallySignedStruct, ProtocolVersion, SignatureScheme,
+    },
+    TlsConnector,
+};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use super::{tls, Bind, Transcript};
+
+/// What a TLS handshake with a port revealed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsInfo {
+    /// The server name (SNI) sent, if a virtual host was given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    /// Negotiated protocol version, e.g. `TLSv1.3`.
+    pub version: String,
+    /// Negotiated application protocol, e.g. `h2`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alpn: Option<String>,
+    /// Every application protocol the server accepts out of `h2` and
+    /// `http/1.1`, plus `h3` if it advertises HTTP/3 to the HTTP probe.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protocols: Vec<String>,
+    /// Common name of the certificate's subject.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub common_name: Option<String>,
+    /// DNS names and IP addresses the certificate is issued for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alt_names: Vec<String>,
+    /// When the certificate expires.
+    #[serde(
+        default,
+        with = "crate::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expires: Option<SystemTime>,
+}
+
+/// Formats as e.g. `TLSv1.3, ALPN h2 of h2 http/1.1, CN example.com, SANs
+/// example.com www.example.com, expires 2025-01-01T00:00:00Z`.
+impl Display for TlsInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.version)?;
+        if let Some(alpn) = &self.alpn {
+            write!(f, ", ALPN {alpn}")?;
+            if !self.protocols.is_empty() {
+                write!(f, " of {}", self.protocols.join(" "))?;
+            }
+        }
+
+        if let Some(common_name) = &self.common_name {
+            write!(f, ", CN {common_name}")?;
+        }
+
+        if !self.alt_names.is_empty() {
+            write!(f, ", SANs {}", self.alt_names.join(" "))?;
+        }
+
+        if let Some(expires) = self.expires {
+            write!(
+                f,
+                ", expires {}",
+                humantime::format_rfc3339_seconds(expires)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Application protocols offered, in order of preference.
+const PROTOCOLS: &[&str] = &["h2", "http/1.1"];
+
+static CONFIG: LazyLock<ClientConfig> = LazyLock::new(|| {
+    let provider = Arc::new(crypto::ring::default_provider());
+    ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+        .with_no_client_auth()
+});
+
+/// Whether `name` can be sent as the server name in TLS handshakes.
+pub(crate) fn is_valid_server_name(name: &str) -> bool {
+    ServerName::try_from(name).is_ok()
+}
+
+/// Connects to `ip:port` and completes a TLS handshake for `server_name`, or
+/// the address if not given, offering `alpn` and giving up at `deadline`.
+pub(crate) async fn connect(
+    bind: &Bind,
+    ip: IpAddr,
+    port: u16,
+    server_name: Option<&str>,
+    alpn: &[&str],
+    deadline: Instant,
+) -> io::Result<TlsStream<TcpStream>> {
+    let server_name = match server_name {
+        Some(name) => ServerName::try_from(name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => ServerName::IpAddress(ip.into()),
+    };
+
+    let stream = timeout_at(deadline, bind.connect(ip, port)).await??;
+    handshake_on(stream, server_name, alpn, deadline).await
+}
+
+/// Completes a TLS handshake on an existing connection to `ip`, offering
+/// `alpn` and giving up at `deadline`.
+pub(crate) async fn wrap<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    ip: IpAddr,
+    alpn: &[&str],
+    deadline: Instant,
+) -> io::Result<TlsStream<S>> {
+    handshake_on(stream, ServerName::IpAddress(ip.into()), alpn, deadline).await
+}
+
+async fn handshake_on<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    server_name: ServerName<'static>,
+    alpn: &[&str],
+    deadline: Instant,
+) -> io::Result<TlsStream<S>> {
+    let mut config = CONFIG.clone();
+    config.alpn_protocols = alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+    let connector = TlsConnector::from(Arc::new(config));
+    timeout_at(deadline, connector.connect(server_name, stream)).await?
+}
+
+/// Completes a TLS handshake with `ip:port` for `server_name`, if given, and
+/// summarizes it, giving up at `deadline`. Protocols the server didn't pick
+/// are each offered on their own afterwards to find out whether it accepts
+/// them too. Servers rustls can't complete a handshake with, e.g. as they
+/// only speak TLS 1.0 or 1.1, are summarized by the version they pick alone.
+pub(crate) async fn handshake(
+    bind: &Bind,
+    ip: IpAddr,
+    port: u16,
+    server_name: Option<&str>,
+    deadline: Instant,
+) -> io::Result<TlsInfo> {
+    let stream = match connect(bind, ip, port, server_name, PROTOCOLS, deadline).await {
+        Ok(stream) => stream,
+        // rustls fails handshakes it can't complete with InvalidData
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            let Some(version) = legacy_version(bind, ip, port, deadline).await? else {
+                return Err(e);
+            };
+
+            return Ok(TlsInfo {
+                server_name: server_name.map(str::to_string),
+                version: version.to_string(),
+                alpn: None,
+                protocols: vec![],
+                common_name: None,
+                alt_names: vec![],
+                expires: None,
+            });
+        }
+        Err(e) => return Err(e),
+    };
+    let (_, conn) = stream.get_ref();
+    let version = match conn.protocol_version() {
+        Some(ProtocolVersion::TLSv1_3) => "TLSv1.3".to_string(),
+        Some(ProtocolVersion::TLSv1_2) => "TLSv1.2".to_string(),
+        Some(version) => format!("{version:?}"),
+        None => "unknown".to_string(),
+    };
+
+    let mut info = TlsInfo {
+        server_name: server_name.map(str::to_string),
+        version,
+        alpn: conn
+            .alpn_protocol()
+            .map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+        protocols: vec![],
+        common_name: None,
+        alt_names: vec![],
+        expires: None,
+    };
+
+    if let Some(cert) = conn.peer_certificates().and_then(|certs| certs.first()) {
+        summarize(cert, &mut info);
+    }
+
+    // without ALPN there's no telling what else it would accept
+    if let Some(alpn) = info.alpn.clone() {
+        for &protocol in PROTOCOLS {
+            let accepted = protocol == alpn
+                || match connect(bind, ip, port, server_name, &[protocol], deadline).await {
+                    Ok(stream) => stream.get_ref().1.alpn_protocol() == Some(protocol.as_bytes()),
+                    Err(_) => false,
+                };
+
+            if accepted {
+                info.protocols.push(protocol.to_string());
+            }
+        }
+    }
+
+    Ok(info)
+}
+
+/// The version `ip:port` picks when offered TLS 1.0 through 1.3 with the
+/// ClientHello service detection sends, which unlike rustls also offers
+/// TLS 1.0 and 1.1 and RSA key exchange.
+async fn legacy_version(
+    bind: &Bind,
+    ip: IpAddr,
+    port: u16,
+    deadline: Instant,
+) -> io::Result<Option<&'static str>> {
+    let mut stream = timeout_at(deadline, bind.connect(ip, port)).await??;
+    let hello = tls::client_hello();
+    let response = super::ask(&mut stream, &hello, deadline, &mut Transcript::default()).await?;
+    Ok(tls::server_version(&response).flatten())
+}
+
+/// Fills in what `info` says about the certificate, if it parses.
+fn summarize(der: &[u8], info: &mut TlsInfo) {
+    let Ok((_, cert)) = X509Certificate::from_der(der) else {
+        return;
+    };
+
+    info.common_name = cert
+        .subject()
+        .iter_common_name()
+        .find_map(|cn| cn.as_str().ok())
+        .map(str::to_string);
+
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(name) => info.alt_names.push(name.to_string()),
+                GeneralName::IPAddress(&[a, b, c, d]) => {
+                    info.alt_names.push(IpAddr::from([a, b, c, d]).to_string())
+                }
+                GeneralName::IPAddress(bytes) => {
+                    if let Ok(bytes) = <[u8; 16]>::try_from(*bytes) {
+                        info.alt_names.push(IpAddr::from(bytes).to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    info.expires = u64::try_from(cert.validity().not_after.timestamp())
+        .ok()
+        .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+}
+
+/// Accepts whatever certificate the server presents, while still checking
+/// the handshake signatures so the connection completes properly.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
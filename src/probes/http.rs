@@ -0,0 +1,502 @@
+//! A lightweight GET to see what a web server says about itself, plus a few
+//! requests to tell which kind of API it serves.
+
+use std::{fmt::Display, io, net::IpAddr, sync::LazyLock, time::Duration};
+
+use bytes::Bytes;
+use http::Method;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    time::{timeout_at, Instant},
+};
+
+use super::{handshake, Bind, TlsInfo};
+
+/// How much of the response to read looking for the title.
+const MAX_RESPONSE: usize = 16 * 1024;
+
+/// Paths APIs commonly serve for health checks and monitoring.
+const ENDPOINTS: &[&str] = &["/healthz", "/metrics"];
+
+/// The standard gRPC health check. gRPC servers answer it in gRPC even if
+/// they don't implement it.
+const GRPC_HEALTH_CHECK: &str = "/grpc.health.v1.Health/Check";
+
+static STATUS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(HTTP/\d(?:\.\d)?) (\d{3})").unwrap());
+static TITLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+static PROMETHEUS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^# (?:HELP|TYPE) [a-zA-Z_:][a-zA-Z0-9_:]* ").unwrap());
+
+/// What a web server answered to `GET /`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpInfo {
+    /// The virtual host requested, if one was given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Protocol of the response, e.g. `HTTP/1.1` or `HTTP/2`.
+    pub protocol: String,
+    pub status: u16,
+    /// The `Server` header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// The `Alt-Svc` header, advertising e.g. HTTP/3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_svc: Option<String>,
+    /// The page's `<title>`, with whitespace collapsed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Which of `/healthz` and `/metrics` answered 200 OK.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<String>,
+    /// The kind of API served, if recognized: `grpc` or `prometheus`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<String>,
+}
+
+impl HttpInfo {
+    /// Whether the server advertises HTTP/3 in `Alt-Svc`.
+    pub fn offers_h3(&self) -> bool {
+        self.alt_svc.as_ref().is_some_and(|alt_svc| {
+            alt_svc
+                .split(',')
+                .any(|service| service.trim_start().starts_with("h3"))
+        })
+    }
+}
+
+/// Formats as e.g. `HTTP/1.1 200 nginx/1.24.0 "Welcome to nginx!"`, followed
+/// by the kind of API in brackets if recognized.
+impl Display for HttpInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.protocol, self.status)?;
+        if let Some(server) = &self.server {
+            write!(f, " {server}")?;
+        }
+
+        if let Some(title) = &self.title {
+            write!(f, " {title:?}")?;
+        }
+
+        if let Some(api) = &self.api {
+            write!(f, " [{api}]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The request sent to web servers, both here and for service detection.
+pub(super) fn request(host: &str, path: &str, authorization: Option<&str>) -> String {
+    let mut request =
+        format!("GET {path} HTTP/1.0\r\nHost: {host}\r\nUser-Agent: qapper\r\nAccept: */*\r\n");
+
+    if let Some(authorization) = authorization {
+        request.push_str(&format!("Authorization: {authorization}\r\n"));
+    }
+
+    request.push_str("\r\n");
+    request
+}
+
+/// A web server to probe. Each request gets a connection of its own and at
+/// most `wait` to complete.
+pub(crate) struct Site<'a> {
+    pub ip: IpAddr,
+    pub port: u16,
+    /// Virtual host to request instead of the address.
+    pub vhost: Option<&'a str>,
+    /// How the TLS probe went. Requests go over TLS if given, in HTTP/2 if
+    /// that's what the server negotiated.
+    pub tls: Option<&'a TlsInfo>,
+    /// Sent as the `Authorization` header.
+    pub authorization: Option<&'a str>,
+    pub wait: Duration,
+    /// Where to connect from.
+    pub bind: &'a Bind,
+}
+
+/// A response, with header names in lowercase.
+struct Response {
+    protocol: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// The value of header `name`, with whitespace collapsed.
+    fn header(&self, name: &str) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| collapse(value))
+            .filter(|value| !value.is_empty())
+    }
+}
+
+impl Site<'_> {
+    /// Requests `/` and checks for gRPC, then requests the usual API endpoints
+    /// of servers that aren't gRPC. Returns `None` if the server doesn't speak
+    /// HTTP.
+    pub(crate) async fn probe(&self) -> io::Result<Option<HttpInfo>> {
+        let root = self.get("/").await;
+        let grpc = self.grpc().await.unwrap_or_default();
+        let is_grpc = grpc.is_some();
+
+        let response = match (root, grpc) {
+            (Ok(Some(root)), _) => root,
+            // gRPC servers often refuse anything but gRPC
+            (_, Some(grpc)) => grpc,
+            (root, None) => return root.map(|_| None),
+        };
+
+        let mut info = HttpInfo {
+            host: self.vhost.map(str::to_string),
+            protocol: response.protocol.clone(),
+            status: response.status,
+            server: response.header("server"),
+            alt_svc: response.header("alt-svc"),
+            title: title(&response.body),
+            endpoints: vec![],
+            api: is_grpc.then(|| "grpc".to_string()),
+        };
+
+        if !is_grpc {
+            for &path in ENDPOINTS {
+                let Ok(Some(response)) = self.get(path).await else {
+                    continue;
+                };
+
+                if response.status == 200 {
+                    info.endpoints.push(path.to_string());
+                    if path == "/metrics" && PROMETHEUS.is_match(&response.body) {
+                        info.api = Some("prometheus".to_string());
+                    }
+                }
+            }
+        }
+
+        Ok(Some(info))
+    }
+
+    /// Requests `path`. Returns `None` if the response isn't HTTP.
+    async fn get(&self, path: &str) -> io::Result<Option<Response>> {
+        let deadline = Instant::now() + self.wait;
+        let (ip, port, vhost) = (self.ip, self.port, self.vhost);
+        match self.tls {
+            Some(tls) if tls.alpn.as_deref() == Some("h2") => {
+                let stream =
+                    handshake::connect(self.bind, ip, port, vhost, &["h2"], deadline).await?;
+                let request = self.h2_request(Method::GET, path, &[("accept", "*/*")])?;
+                send_h2(stream, request, None, deadline).await.map(Some)
+            }
+            Some(tls) => {
+                let offered = tls.protocols.iter().any(|p| p == "http/1.1");
+                let alpn: &[&str] = if offered { &["http/1.1"] } else { &[] };
+                let mut stream =
+                    handshake::connect(self.bind, ip, port, vhost, alpn, deadline).await?;
+                self.get_http1(&mut stream, path, deadline).await
+            }
+            None => {
+                let mut stream = timeout_at(deadline, self.bind.connect(ip, port)).await??;
+                self.get_http1(&mut stream, path, deadline).await
+            }
+        }
+    }
+
+    /// Calls the gRPC health check over HTTP/2, in cleartext with prior
+    /// knowledge unless TLS negotiated it. Returns the response if it came
+    /// from a gRPC server.
+    async fn grpc(&self) -> io::Result<Option<Response>> {
+        let deadline = Instant::now() + self.wait;
+        let headers = [("content-type", "application/grpc"), ("te", "trailers")];
+        let request = self.h2_request(Method::POST, GRPC_HEALTH_CHECK, &headers)?;
+        // an empty HealthCheckRequest: uncompressed and 0 bytes long
+        let body = Some(Bytes::from_static(&[0; 5]));
+
+        let (ip, port, vhost) = (self.ip, self.port, self.vhost);
+        let response = match self.tls {
+            Some(tls) if tls.alpn.as_deref() == Some("h2") => {
+                let stream =
+                    handshake::connect(self.bind, ip, port, vhost, &["h2"], deadline).await?;
+                send_h2(stream, request, body, deadline).await?
+            }
+            Some(_) => return Ok(None),
+            None => {
+                let stream = timeout_at(deadline, self.bind.connect(ip, port)).await??;
+                send_h2(stream, request, body, deadline).await?
+            }
+        };
+
+        let content_type = response.header("content-type").unwrap_or_default();
+        Ok(Some(response).filter(|_| content_type.starts_with("application/grpc")))
+    }
+
+    async fn get_http1<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        path: &str,
+        deadline: Instant,
+    ) -> io::Result<Option<Response>> {
+        let request = request(&self.host(), path, self.authorization);
+        timeout_at(deadline, stream.write_all(request.as_bytes())).await??;
+        let response = super::read(stream, MAX_RESPONSE, deadline).await?;
+        Ok(parse_http1(&response))
+    }
+
+    fn h2_request(
+        &self,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> io::Result<http::Request<()>> {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        let mut request = http::Request::builder()
+            .method(method)
+            .uri(format!("{scheme}://{}{path}", self.host()))
+            .header("user-agent", "qapper");
+
+        for &(name, value) in headers {
+            request = request.header(name, value);
+        }
+
+        if let Some(authorization) = self.authorization {
+            request = request.header("authorization", authorization);
+        }
+
+        request.body(()).map_err(io::Error::other)
+    }
+
+    fn host(&self) -> String {
+        self.vhost
+            .map_or_else(|| host_header(self.ip), str::to_string)
+    }
+}
+
+fn parse_http1(response: &[u8]) -> Option<Response> {
+    let status = STATUS.captures(response)?;
+    let head_len = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap_or(response.len());
+
+    let head = String::from_utf8_lossy(&response[..head_len]);
+    let headers = head
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.to_string()))
+        .collect();
+
+    Some(Response {
+        protocol: String::from_utf8_lossy(&status[1]).into_owned(),
+        status: String::from_utf8_lossy(&status[2]).parse().ok()?,
+        headers,
+        body: response.get(head_len + 4..).unwrap_or_default().to_vec(),
+    })
+}
+
+/// Sends `request` over HTTP/2 on `stream`, followed by `body` if given, and
+/// reads the response, giving up at `deadline`.
+async fn send_h2<S>(
+    stream: S,
+    request: http::Request<()>,
+    body: Option<Bytes>,
+    deadline: Instant,
+) -> io::Result<Response>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (client, connection) = timeout_at(deadline, h2::client::handshake(stream))
+        .await?
+        .map_err(io::Error::other)?;
+
+    // drives the connection, which ends once the client below is dropped
+    tokio::spawn(connection);
+
+    let mut client = timeout_at(deadline, client.ready())
+        .await?
+        .map_err(io::Error::other)?;
+
+    let (response, mut send) = client
+        .send_request(request, body.is_none())
+        .map_err(io::Error::other)?;
+
+    if let Some(body) = body {
+        send.send_data(body, true).map_err(io::Error::other)?;
+    }
+
+    let (head, mut body) = timeout_at(deadline, response)
+        .await?
+        .map_err(io::Error::other)?
+        .into_parts();
+
+    let mut page = vec![];
+    while page.len() < MAX_RESPONSE {
+        match timeout_at(deadline, body.data()).await {
+            Ok(Some(Ok(chunk))) => {
+                let _ = body.flow_control().release_capacity(chunk.len());
+                page.extend_from_slice(&chunk);
+            }
+            _ => break,
+        }
+    }
+
+    let headers = head
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (name.to_string(), value)
+        })
+        .collect();
+
+    Ok(Response {
+        protocol: "HTTP/2".to_string(),
+        status: head.status.as_u16(),
+        headers,
+        body: page,
+    })
+}
+
+/// The page's `<title>`, with whitespace collapsed.
+fn title(body: &[u8]) -> Option<String> {
+    let captures = TITLE.captures(body)?;
+    let title = collapse(&String::from_utf8_lossy(&captures[1]));
+    Some(title).filter(|title| !title.is_empty())
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub(super) fn host_header(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{ip}]"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NGINX: &[u8] = b"HTTP/1.1 200 OK\r\n\
+        Server: nginx/1.24.0\r\n\
+        Content-Type: text/html\r\n\
+        alt-svc:   h3=\":443\";  ma=86400\r\n\
+        \r\n\
+        <html><head><title>\n  Welcome to\n nginx!\n</title></head></html>";
+
+    #[test]
+    fn parses_responses() {
+        let response = parse_http1(NGINX).unwrap();
+        assert_eq!(response.protocol, "HTTP/1.1");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("server").as_deref(), Some("nginx/1.24.0"));
+        assert_eq!(
+            response.header("alt-svc").as_deref(),
+            Some("h3=\":443\"; ma=86400")
+        );
+        assert_eq!(response.header("x-missing"), None);
+        assert_eq!(title(&response.body).as_deref(), Some("Welcome to nginx!"));
+    }
+
+    #[test]
+    fn parses_responses_without_a_body() {
+        let response = parse_http1(b"HTTP/1.0 404 Not Found\r\nServer: lighttpd").unwrap();
+        assert_eq!(
+            (response.protocol.as_str(), response.status),
+            ("HTTP/1.0", 404)
+        );
+        assert_eq!(response.header("server").as_deref(), Some("lighttpd"));
+        assert!(response.body.is_empty());
+        assert_eq!(title(&response.body), None);
+    }
+
+    #[test]
+    fn rejects_responses_without_a_status_line() {
+        assert!(parse_http1(b"").is_none());
+        assert!(parse_http1(b"SSH-2.0-OpenSSH_9.6\r\n").is_none());
+        assert!(parse_http1(b"Server: nginx\r\n\r\n").is_none());
+        assert!(parse_http1(b"HTTP/1.1 OK\r\n\r\n").is_none());
+        // the status line has to come first
+        assert!(parse_http1(b"\r\nHTTP/1.1 200 OK\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn survives_headers_that_arent_utf8() {
+        let response =
+            parse_http1(b"HTTP/1.1 200 OK\r\nServer: caf\xe9\r\nX-Other: \xff\xfe\r\n\r\n")
+                .unwrap();
+        assert_eq!(response.header("server").as_deref(), Some("caf\u{fffd}"));
+        assert_eq!(
+            response.header("x-other").as_deref(),
+            Some("\u{fffd}\u{fffd}")
+        );
+    }
+
+    #[test]
+    fn ignores_blank_headers_and_lines_without_a_colon() {
+        let response =
+            parse_http1(b"HTTP/1.1 200 OK\r\nServer:   \r\ngarbage\r\nVia: 1.1 proxy\r\n\r\n")
+                .unwrap();
+        assert_eq!(response.header("server"), None);
+        assert_eq!(response.headers.len(), 2);
+        assert_eq!(response.header("via").as_deref(), Some("1.1 proxy"));
+    }
+
+    #[test]
+    fn finds_http3() {
+        let mut info = HttpInfo {
+            host: None,
+            protocol: "HTTP/2".to_string(),
+            status: 200,
+            server: None,
+            alt_svc: None,
+            title: None,
+            endpoints: vec![],
+            api: None,
+        };
+        assert!(!info.offers_h3());
+
+        info.alt_svc = Some("h2=\":443\", h3-29=\":443\"".to_string());
+        assert!(info.offers_h3());
+
+        info.alt_svc = Some("h2=\":443\"".to_string());
+        assert!(!info.offers_h3());
+    }
+
+    #[test]
+    fn formats_info() {
+        let info = HttpInfo {
+            host: Some("example.com".to_string()),
+            protocol: "HTTP/1.1".to_string(),
+            status: 200,
+            server: Some("nginx/1.24.0".to_string()),
+            alt_svc: None,
+            title: Some("Welcome to nginx!".to_string()),
+            endpoints: vec!["/metrics".to_string()],
+            api: Some("prometheus".to_string()),
+        };
+        assert_eq!(
+            info.to_string(),
+            "HTTP/1.1 200 nginx/1.24.0 \"Welcome to nginx!\" [prometheus]"
+        );
+    }
+
+    #[test]
+    fn builds_requests() {
+        assert_eq!(
+            request("example.com", "/", Some("Basic dXNlcjpwYXNz")),
+            "GET / HTTP/1.0\r\nHost: example.com\r\nUser-Agent: qapper\r\nAccept: */*\r\n\
+            Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+        );
+        assert_eq!(host_header("192.0.2.1".parse().unwrap()), "192.0.2.1");
+        assert_eq!(host_header("2001:db8::1".parse().unwrap()), "[2001:db8::1]");
+    }
+}
@@ -0,0 +1,301 @@
+//! Follow-up probes run against open ports to find out what's listening.
+
+mod bind;
+mod capture;
+mod directory;
+mod fingerprints;
+mod game;
+mod handshake;
+mod http;
+mod proxy;
+mod stun;
+mod tls;
+mod traceroute;
+mod udp;
+
+use std::{fmt::Display, io, net::IpAddr};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time::{timeout_at, Instant},
+};
+
+pub(crate) use bind::Bind;
+pub(crate) use capture::Transcript;
+pub use handshake::TlsInfo;
+pub(crate) use handshake::{handshake, is_valid_server_name};
+pub use http::HttpInfo;
+pub(crate) use http::Site;
+pub use stun::SourceAddress;
+pub(crate) use traceroute::{format_hops, raw_socket, trace};
+pub(crate) use udp::{
+    can_retransmit as can_retransmit_udp, exchange as exchange_udp,
+    identify_silent as identify_silent_udp, source_address,
+};
+
+/// A service found listening on a port, e.g. `ssh` version `OpenSSH_9.6`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Service {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Whatever else the service disclosed, e.g. the realm and naming context
+    /// of a directory server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+impl Display for Service {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(version) = &self.version {
+            write!(f, " {version}")?;
+        }
+
+        if let Some(details) = &self.details {
+            write!(f, " [{details}]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What to probe a port with, based on the service usually found there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ProbeKind {
+    /// The service talks first, so just wait for its greeting.
+    Greeting,
+    Smtp,
+    Http,
+    Tls,
+    Kerberos,
+    Ldap,
+    /// LDAP over TLS.
+    Ldaps,
+    Stun,
+    /// STUN over TLS.
+    Stuns,
+    /// Nothing in particular is usually found there, so wait for a greeting,
+    /// then try HTTP and TLS.
+    Unknown,
+}
+
+impl ProbeKind {
+    pub(crate) fn for_port(port: u16) -> Option<Self> {
+        match port {
+            21 | 22 | 23 | 110 | 143 | 3306 | 5900 => Some(Self::Greeting),
+            25 | 587 => Some(Self::Smtp),
+            80 | 3000 | 5000 | 8000 | 8008 | 8080 | 8081 | 8888 | 9000 => Some(Self::Http),
+            443 | 465 | 990 | 992 | 993 | 995 | 5986 | 6443 | 8443 => Some(Self::Tls),
+            88 => Some(Self::Kerberos),
+            389 | 3268 => Some(Self::Ldap),
+            636 | 3269 => Some(Self::Ldaps),
+            3478 => Some(Self::Stun),
+            5349 => Some(Self::Stuns),
+            _ => None,
+        }
+    }
+
+    /// What to probe `port` with, trying whatever is common if nothing in
+    /// particular is usually found there.
+    pub(crate) fn for_any_port(port: u16) -> Self {
+        Self::for_port(port).unwrap_or(Self::Unknown)
+    }
+
+    /// Whether the service is expected to send something before we do.
+    pub(crate) fn talks_first(self) -> bool {
+        matches!(self, Self::Greeting | Self::Smtp | Self::Unknown)
+    }
+}
+
+/// Identifies the service on `stream`, given whatever it already sent after
+/// connecting. Waits at most until `deadline` for responses, recording what's
+/// exchanged in `transcript`.
+pub(crate) async fn detect(
+    bind: &Bind,
+    stream: &mut TcpStream,
+    ip: IpAddr,
+    kind: ProbeKind,
+    greeting: &[u8],
+    deadline: Instant,
+    transcript: &mut Transcript,
+) -> io::Result<Option<Service>> {
+    if !greeting.is_empty() {
+        let service = fingerprints::match_greeting(greeting);
+        if kind == ProbeKind::Smtp && service.as_ref().is_some_and(|s| s.name == "smtp") {
+            // make sure it actually speaks SMTP rather than just looking like it
+            let reply = ask(stream, b"EHLO qapper\r\n", deadline, transcript).await?;
+            if !reply.starts_with(b"250") {
+                return Ok(None);
+            }
+        }
+
+        return Ok(service);
+    }
+
+    match kind {
+        ProbeKind::Greeting | ProbeKind::Smtp => Ok(None),
+        ProbeKind::Http => detect_http(stream, ip, deadline, transcript).await,
+        ProbeKind::Tls => detect_tls(stream, deadline, transcript).await,
+        ProbeKind::Kerberos => {
+            let request = directory::kerberos_request();
+            let response = ask(stream, &request, deadline, transcript).await?;
+            Ok(directory::kerberos_service(&response))
+        }
+        ProbeKind::Ldap => {
+            let request = directory::ldap_request();
+            let response = ask(stream, &request, deadline, transcript).await?;
+            Ok(directory::ldap_service(&response))
+        }
+        ProbeKind::Ldaps => {
+            let mut stream = handshake::wrap(stream, ip, &[], deadline).await?;
+            let request = directory::ldap_request();
+            let response = ask(&mut stream, &request, deadline, transcript).await?;
+            Ok(directory::ldap_service(&response))
+        }
+        ProbeKind::Stun => {
+            let id = stun::transaction_id();
+            let request = stun::binding_request(&id);
+            let response = ask(stream, &request, deadline, transcript).await?;
+            Ok(stun::service(&response, &id))
+        }
+        ProbeKind::Stuns => {
+            let mut stream = handshake::wrap(stream, ip, &[], deadline).await?;
+            let id = stun::transaction_id();
+            let request = stun::binding_request(&id);
+            let response = ask(&mut stream, &request, deadline, transcript).await?;
+            Ok(stun::service(&response, &id))
+        }
+        ProbeKind::Unknown => {
+            // a silent port would use up all the time otherwise
+            let now = Instant::now();
+            let halfway = now + deadline.saturating_duration_since(now) / 2;
+            if let Some(service) = detect_http(stream, ip, halfway, transcript).await? {
+                return Ok(Some(service));
+            }
+
+            // the hello needs a connection of its own, after the request
+            let peer = stream.peer_addr()?;
+            let mut stream = timeout_at(deadline, bind.connect(peer.ip(), peer.port())).await??;
+            transcript.reconnected();
+            detect_tls(&mut stream, deadline, transcript).await
+        }
+    }
+}
+
+async fn detect_http(
+    stream: &mut TcpStream,
+    ip: IpAddr,
+    deadline: Instant,
+    transcript: &mut Transcript,
+) -> io::Result<Option<Service>> {
+    let request = http::request(&http::host_header(ip), "/", None);
+    let response = ask(stream, request.as_bytes(), deadline, transcript).await?;
+    Ok(fingerprints::match_http(&response))
+}
+
+async fn detect_tls(
+    stream: &mut TcpStream,
+    deadline: Instant,
+    transcript: &mut Transcript,
+) -> io::Result<Option<Service>> {
+    let response = ask(stream, &tls::client_hello(), deadline, transcript).await?;
+    Ok(tls::server_version(&response).map(|version| Service {
+        name: "tls".to_string(),
+        version: version.map(str::to_string),
+        details: None,
+    }))
+}
+
+/// Identifies the service on `ip:port` like [`detect`], but on a connection
+/// of its own starting with a PROXY protocol header. Services found this way
+/// are marked as expecting it.
+pub(crate) async fn detect_behind_proxy(
+    bind: &Bind,
+    ip: IpAddr,
+    port: u16,
+    kind: ProbeKind,
+    deadline: Instant,
+    transcript: &mut Transcript,
+) -> io::Result<Option<Service>> {
+    let mut stream = timeout_at(deadline, bind.connect(ip, port)).await??;
+    transcript.reconnected();
+
+    let header = proxy::header(stream.local_addr()?, stream.peer_addr()?);
+    stream.write_all(header.as_bytes()).await?;
+    transcript.sent(header.as_bytes());
+
+    let greeting = if kind.talks_first() {
+        let greeting = read_some(&mut stream, deadline).await?;
+        transcript.received(&greeting);
+        greeting
+    } else {
+        vec![]
+    };
+
+    let service = detect(bind, &mut stream, ip, kind, &greeting, deadline, transcript).await?;
+    Ok(service.map(|service| Service {
+        details: Some(match service.details {
+            Some(details) => format!("PROXY protocol, {details}"),
+            None => "PROXY protocol".to_string(),
+        }),
+        ..service
+    }))
+}
+
+/// Sends `request` and reads whatever arrives first in response, recording
+/// both in `transcript`.
+async fn ask<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    request: &[u8],
+    deadline: Instant,
+    transcript: &mut Transcript,
+) -> io::Result<Vec<u8>> {
+    stream.write_all(request).await?;
+    transcript.sent(request);
+
+    let response = read_some(stream, deadline).await?;
+    transcript.received(&response);
+    Ok(response)
+}
+
+/// Reads until `max_bytes` have been read, `deadline` has passed or the
+/// connection is closed.
+pub(crate) async fn read<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_bytes: usize,
+    deadline: Instant,
+) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; max_bytes];
+    let mut len = 0;
+
+    while len < buf.len() {
+        match timeout_at(deadline, stream.read(&mut buf[len..])).await {
+            Ok(Ok(0)) | Err(_) => break,
+            Ok(Ok(n)) => len += n,
+            Ok(Err(e)) if len == 0 => return Err(e),
+            Ok(Err(_)) => break,
+        }
+    }
+
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Reads whatever arrives first, or nothing if `deadline` passes before
+/// anything does.
+pub(crate) async fn read_some<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    deadline: Instant,
+) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; 4096];
+    match timeout_at(deadline, stream.read(&mut buf)).await {
+        Ok(Ok(n)) => buf.truncate(n),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => buf.clear(),
+    }
+
+    Ok(buf)
+}
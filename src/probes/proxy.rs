@@ -0,0 +1,17 @@
+//! The PROXY protocol, which load balancers like HAProxy use to pass on the
+//! client's address. Services expecting it drop connections that don't start
+//! with its header, so they look broken unless we send one.
+
+use std::net::SocketAddr;
+
+/// A version 1 header for a connection from `local` to `peer`.
+pub(super) fn header(local: SocketAddr, peer: SocketAddr) -> String {
+    let family = if local.is_ipv4() { "TCP4" } else { "TCP6" };
+    format!(
+        "PROXY {family} {} {} {} {}\r\n",
+        local.ip(),
+        peer.ip(),
+        local.port(),
+        peer.port()
+    )
+}
@@ -0,0 +1,252 @@
+//! Just enough of STUN to send a binding request and read the address the
+//! server saw it come from.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Display,
+    hash::{BuildHasher, Hasher},
+    net::{IpAddr, SocketAddr},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::Service;
+
+const MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xa4, 0x42];
+
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const SOFTWARE: u16 = 0x8022;
+
+/// The address a scan's traffic comes from, locally and as a STUN server saw
+/// it. Behind NAT, targets log and allowlist the public one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceAddress {
+    /// Address of the interface traffic leaves through.
+    pub local: IpAddr,
+    /// Address the STUN server saw traffic come from.
+    pub public: IpAddr,
+}
+
+impl SourceAddress {
+    /// Whether traffic goes through NAT on its way out.
+    pub fn is_nat(&self) -> bool {
+        self.local != self.public
+    }
+}
+
+/// Formats as e.g. `203.0.113.7 (NAT from 192.168.1.5)`.
+impl Display for SourceAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.public)?;
+        if self.is_nat() {
+            write!(f, " (NAT from {})", self.local)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Ties a response to the request it answers.
+pub(super) type TransactionId = [u8; 12];
+
+/// A new transaction ID, unguessable so that answers can't be spoofed
+/// without seeing the request.
+pub(super) fn transaction_id() -> TransactionId {
+    let mut id = [0; 12];
+    for chunk in id.chunks_mut(8) {
+        let random = RandomState::new().build_hasher().finish().to_be_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+
+    id
+}
+
+/// A binding request without attributes, the same over UDP, TCP and TLS.
+pub(super) fn binding_request(id: &TransactionId) -> Vec<u8> {
+    let mut request = vec![0x00, 0x01, 0x00, 0x00];
+    request.extend_from_slice(&MAGIC_COOKIE);
+    request.extend_from_slice(id);
+    request
+}
+
+/// Whether `response` is a STUN message, but not an answer to the
+/// [`binding_request`] with `id`, e.g. one meant for another client.
+pub(super) fn is_other_transaction(response: &[u8], id: &TransactionId) -> bool {
+    response.get(4..8) == Some(&MAGIC_COOKIE) && response.get(8..20) != Some(id)
+}
+
+/// Recognizes the answer to the [`binding_request`] with `id`, noting the
+/// server's software and the address it reflected, if it said.
+pub(super) fn service(response: &[u8], id: &TransactionId) -> Option<Service> {
+    let (software, reflected) = parse(response, id)?;
+    Some(Service {
+        name: "stun".to_string(),
+        version: software.filter(|software| !software.is_empty()),
+        details: reflected.map(|address| format!("reflected address {address}")),
+    })
+}
+
+/// The address the server saw the [`binding_request`] with `id` come from,
+/// if `response` answers it and says.
+pub(super) fn reflected(response: &[u8], id: &TransactionId) -> Option<SocketAddr> {
+    parse(response, id)?.1
+}
+
+/// Reads the software and reflected address out of an answer to the
+/// [`binding_request`] with `id`.
+fn parse(response: &[u8], id: &TransactionId) -> Option<(Option<String>, Option<SocketAddr>)> {
+    let [t0, t1, l0, l1, rest @ ..] = response else {
+        return None;
+    };
+
+    // a success or error response to a binding request
+    if ![[0x01, 0x01], [0x01, 0x11]].contains(&[*t0, *t1])
+        || rest.get(..4)? != MAGIC_COOKIE
+        || rest.get(4..16)? != id
+    {
+        return None;
+    }
+
+    let len = u16::from_be_bytes([*l0, *l1]) as usize;
+    let mut attributes = rest.get(16..16 + len).unwrap_or(&rest[16..]);
+    let (mut software, mut reflected) = (None, None);
+    while let [t0, t1, l0, l1, data @ ..] = attributes {
+        let len = u16::from_be_bytes([*l0, *l1]) as usize;
+        let Some(value) = data.get(..len) else {
+            break;
+        };
+
+        match u16::from_be_bytes([*t0, *t1]) {
+            SOFTWARE => {
+                let text = String::from_utf8_lossy(value);
+                software = Some(text.trim_end_matches('\0').trim().to_string());
+            }
+            XOR_MAPPED_ADDRESS => reflected = address(value, Some(id)).or(reflected),
+            MAPPED_ADDRESS if reflected.is_none() => reflected = address(value, None),
+            _ => {}
+        }
+
+        // values are padded to 4 bytes
+        attributes = data.get(len.next_multiple_of(4)..).unwrap_or_default();
+    }
+
+    Some((software, reflected))
+}
+
+/// Decodes a MAPPED-ADDRESS attribute, or an XOR-MAPPED-ADDRESS one given
+/// the `id` of the transaction it was sent in.
+fn address(value: &[u8], xor: Option<&TransactionId>) -> Option<SocketAddr> {
+    let mask: Vec<u8> = match xor {
+        Some(id) => MAGIC_COOKIE.iter().chain(id).copied().collect(),
+        None => vec![0; 16],
+    };
+
+    let unmask =
+        |bytes: &[u8]| -> Vec<u8> { bytes.iter().zip(&mask).map(|(b, m)| b ^ m).collect() };
+    let port = u16::from_be_bytes(unmask(value.get(2..4)?).try_into().ok()?);
+    let ip = match value.get(1)? {
+        0x01 => IpAddr::from(<[u8; 4]>::try_from(unmask(value.get(4..8)?)).ok()?),
+        0x02 => IpAddr::from(<[u8; 16]>::try_from(unmask(value.get(4..20)?)).ok()?),
+        _ => return None,
+    };
+
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The transaction of the sample responses in RFC 5769.
+    const ID: TransactionId = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    /// The IPv4 sample response of RFC 5769, 2.2, without its
+    /// MESSAGE-INTEGRITY and FINGERPRINT attributes.
+    const IPV4_RESPONSE: &[u8] = &[
+        0x01, 0x01, 0x00, 0x1c, // success, length
+        0x21, 0x12, 0xa4, 0x42, // magic cookie
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00,
+        0x0b, // SOFTWARE
+        0x74, 0x65, 0x73, 0x74, 0x20, 0x76, 0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00,
+        0x08, // XOR-MAPPED-ADDRESS
+        0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43,
+    ];
+
+    /// The IPv6 sample response of RFC 5769, 2.3, likewise.
+    const IPV6_RESPONSE: &[u8] = &[
+        0x01, 0x01, 0x00, 0x28, // success, length
+        0x21, 0x12, 0xa4, 0x42, // magic cookie
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00,
+        0x0b, // SOFTWARE
+        0x74, 0x65, 0x73, 0x74, 0x20, 0x76, 0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00,
+        0x14, // XOR-MAPPED-ADDRESS
+        0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79, 0xbc, 0x25, 0xf4,
+        0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+    ];
+
+    #[test]
+    fn reads_xor_mapped_ipv4_addresses() {
+        assert_eq!(
+            reflected(IPV4_RESPONSE, &ID),
+            Some("192.0.2.1:32853".parse().unwrap())
+        );
+
+        let service = service(IPV4_RESPONSE, &ID).unwrap();
+        assert_eq!(service.version.as_deref(), Some("test vector"));
+        assert_eq!(
+            service.details.as_deref(),
+            Some("reflected address 192.0.2.1:32853")
+        );
+    }
+
+    #[test]
+    fn reads_xor_mapped_ipv6_addresses() {
+        assert_eq!(
+            reflected(IPV6_RESPONSE, &ID),
+            Some(
+                "[2001:db8:1234:5678:11:2233:4455:6677]:32853"
+                    .parse()
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn reads_mapped_addresses() {
+        let mut response = IPV4_RESPONSE.to_vec();
+        // MAPPED-ADDRESS instead, in the clear
+        response[36..38].copy_from_slice(&MAPPED_ADDRESS.to_be_bytes());
+        response[42..].copy_from_slice(&[0x80, 0x55, 192, 0, 2, 1]);
+        assert_eq!(
+            reflected(&response, &ID),
+            Some("192.0.2.1:32853".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn ignores_other_transactions() {
+        let other = transaction_id();
+        assert!(is_other_transaction(IPV4_RESPONSE, &other));
+        assert!(!is_other_transaction(IPV4_RESPONSE, &ID));
+        assert_eq!(service(IPV4_RESPONSE, &other), None);
+        assert_eq!(reflected(IPV6_RESPONSE, &other), None);
+    }
+
+    #[test]
+    fn picks_new_transaction_ids() {
+        let (first, second) = (transaction_id(), transaction_id());
+        assert_ne!(first, second);
+        assert_eq!(binding_request(&first)[8..], first);
+    }
+
+    #[test]
+    fn survives_truncated_responses() {
+        for len in 0..IPV6_RESPONSE.len() {
+            // cut off attributes are left out
+            let _ = reflected(&IPV6_RESPONSE[..len], &ID);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Just enough of TLS to tell whether a port speaks it and which version it
+//! picks, without completing a handshake.
+
+const CIPHER_SUITES: &[u16] = &[
+    0x1301, 0x1302, 0x1303, // TLS 1.3
+    0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, // ECDHE
+    0x009c, 0x009d, 0x002f, 0x0035, // RSA
+];
+
+const SUPPORTED_GROUPS: &[u16] = &[0x001d, 0x0017, 0x0018];
+
+const SIGNATURE_ALGORITHMS: &[u16] = &[
+    0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601, 0x0201,
+];
+
+const SUPPORTED_VERSIONS: &[u16] = &[0x0304, 0x0303, 0x0302, 0x0301];
+
+/// Builds a ClientHello offering TLS 1.0 through 1.3. No key shares are sent,
+/// so TLS 1.3 servers answer with a HelloRetryRequest, which is all we need.
+pub(super) fn client_hello() -> Vec<u8> {
+    let mut extensions = vec![];
+    extension(&mut extensions, 0x000a, &list16(SUPPORTED_GROUPS));
+    extension(&mut extensions, 0x000b, &[1, 0]);
+    extension(&mut extensions, 0x000d, &list16(SIGNATURE_ALGORITHMS));
+    extension(&mut extensions, 0x002b, &versions());
+    extension(&mut extensions, 0x0033, &[0, 0]);
+
+    let mut hello = vec![0x03, 0x03];
+    hello.extend_from_slice(&[0x42; 32]); // random
+    hello.push(0); // session id
+    hello.extend_from_slice(&list16(CIPHER_SUITES));
+    hello.extend_from_slice(&[1, 0]); // null compression
+    hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    hello.extend_from_slice(&extensions);
+
+    let mut handshake = vec![0x01];
+    handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&hello);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
+}
+
+/// Returns the protocol version chosen in a ServerHello, e.g. `TLSv1.2`, or
+/// `None` if `response` isn't one. An alert still means the port speaks TLS,
+/// just not with anything we offered.
+pub(super) fn server_version(response: &[u8]) -> Option<Option<&'static str>> {
+    match response {
+        [0x15, 0x03, ..] => Some(None),
+        [0x16, 0x03, _, _, _, 0x02, _, _, _, rest @ ..] => Some(hello_version(rest)),
+        _ => None,
+    }
+}
+
+fn hello_version(hello: &[u8]) -> Option<&'static str> {
+    let mut version = u16::from_be_bytes([*hello.first()?, *hello.get(1)?]);
+
+    // TLS 1.3 keeps the legacy version at 1.2 and puts the real one in the
+    // supported_versions extension
+    let mut rest = extensions(hello).unwrap_or_default();
+    while let [t0, t1, l0, l1, data @ ..] = rest {
+        let len = u16::from_be_bytes([*l0, *l1]) as usize;
+        let Some(body) = data.get(..len) else {
+            break;
+        };
+
+        if [*t0, *t1] == [0x00, 0x2b] && len == 2 {
+            version = u16::from_be_bytes([body[0], body[1]]);
+        }
+
+        rest = &data[len..];
+    }
+
+    match version {
+        0x0304 => Some("TLSv1.3"),
+        0x0303 => Some("TLSv1.2"),
+        0x0302 => Some("TLSv1.1"),
+        0x0301 => Some("TLSv1.0"),
+        0x0300 => Some("SSLv3"),
+        _ => None,
+    }
+}
+
+fn extensions(hello: &[u8]) -> Option<&[u8]> {
+    // version (2), random (32), session id
+    let session_len = *hello.get(34)? as usize;
+    // cipher suite (2), compression (1), then the extensions' length
+    let start = 35 + session_len + 3;
+    let len = u16::from_be_bytes([*hello.get(start)?, *hello.get(start + 1)?]) as usize;
+    hello.get(start + 2..start + 2 + len)
+}
+
+fn versions() -> Vec<u8> {
+    let mut list = vec![(SUPPORTED_VERSIONS.len() * 2) as u8];
+    for version in SUPPORTED_VERSIONS {
+        list.extend_from_slice(&version.to_be_bytes());
+    }
+
+    list
+}
+
+fn list16(items: &[u16]) -> Vec<u8> {
+    let mut list = ((items.len() * 2) as u16).to_be_bytes().to_vec();
+    for item in items {
+        list.extend_from_slice(&item.to_be_bytes());
+    }
+
+    list
+}
+
+fn extension(extensions: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    extensions.extend_from_slice(&kind.to_be_bytes());
+    extensions.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    extensions.extend_from_slice(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `openssl s_server -tls1_2` answered [`client_hello`] with.
+    const TLS12_HELLO: &str =
+        "160303005402000050030373df1ce62ea608ccd7a6bfbd6d35d0db6e06b3a76dadc4f4\
+        e6882f5a5283b08120cb8a0a1611d8f628bf5be24af992d242964910aad4dcbafe2559aff3892442fcc02b00\
+        0008000b000403000102";
+
+    /// What `openssl s_server -tls1_3` answered [`client_hello`] with, a
+    /// HelloRetryRequest asking for an x25519 key share.
+    const TLS13_HELLO: &str =
+        "1603030038020000340303cf21ad74e59a6111be1d8c021e65b891c2a211167abb8c5e\
+        079e09e2c8a8339c00130100000c002b0002030400330002001d";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn builds_consistent_client_hello() {
+        let hello = client_hello();
+        assert_eq!(hello[..3], [0x16, 0x03, 0x01]);
+        assert_eq!(
+            u16::from_be_bytes([hello[3], hello[4]]) as usize,
+            hello.len() - 5
+        );
+        assert_eq!(hello[5], 0x01);
+        assert_eq!(
+            u32::from_be_bytes([0, hello[6], hello[7], hello[8]]) as usize,
+            hello.len() - 9
+        );
+    }
+
+    #[test]
+    fn reads_tls12_server_hello() {
+        assert_eq!(server_version(&hex(TLS12_HELLO)), Some(Some("TLSv1.2")));
+    }
+
+    #[test]
+    fn reads_tls13_hello_retry_request() {
+        assert_eq!(server_version(&hex(TLS13_HELLO)), Some(Some("TLSv1.3")));
+    }
+
+    #[test]
+    fn alerts_speak_tls_without_a_version() {
+        // handshake_failure
+        assert_eq!(server_version(&[0x15, 0x03, 0x03, 0, 2, 2, 40]), Some(None));
+    }
+
+    #[test]
+    fn ignores_other_protocols() {
+        assert_eq!(server_version(b"SSH-2.0-OpenSSH_9.6\r\n"), None);
+        assert_eq!(server_version(b"HTTP/1.1 400 Bad Request\r\n"), None);
+        assert_eq!(server_version(&[]), None);
+    }
+
+    #[test]
+    fn survives_truncated_server_hellos() {
+        let hello = hex(TLS13_HELLO);
+        for len in 0..hello.len() {
+            let expected = match len {
+                0..=8 => None,
+                9..=10 => Some(None),
+                // the extensions are cut off, leaving only the legacy version
+                _ => Some(Some("TLSv1.2")),
+            };
+            assert_eq!(server_version(&hello[..len]), expected, "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn survives_malformed_extensions() {
+        let hello = hex(TLS13_HELLO);
+        // where the extensions' length, and supported_versions' length, are
+        let (extensions_len, versions_len) = (47, 51);
+
+        let mut overlong = hello.clone();
+        overlong[extensions_len..extensions_len + 2].copy_from_slice(&[0xff, 0xff]);
+        assert_eq!(server_version(&overlong), Some(Some("TLSv1.2")));
+
+        let mut overlong = hello.clone();
+        overlong[versions_len..versions_len + 2].copy_from_slice(&[0xff, 0xff]);
+        assert_eq!(server_version(&overlong), Some(Some("TLSv1.2")));
+
+        let mut session = hello.clone();
+        session[43] = 0xff;
+        assert_eq!(server_version(&session), Some(Some("TLSv1.2")));
+
+        let mut unknown = hello;
+        unknown[versions_len + 2..versions_len + 4].copy_from_slice(&[0x7f, 0x1c]);
+        assert_eq!(server_version(&unknown), Some(None));
+    }
+}
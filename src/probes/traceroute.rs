@@ -0,0 +1,315 @@
+//! Where packets to a host stop: pings are sent with every TTL up to
+//! [`MAX_HOPS`] at once, and the routers they expire at report back. Replies
+//! about expired packets only reach raw sockets, so this needs root or
+//! `CAP_NET_RAW`.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use tokio::{
+    net::UdpSocket,
+    time::{timeout_at, Instant},
+};
+
+use super::Bind;
+
+/// Most hops traced.
+const MAX_HOPS: u8 = 30;
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const UNREACHABLE_V4: u8 = 3;
+const TIME_EXCEEDED_V4: u8 = 11;
+
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+const UNREACHABLE_V6: u8 = 1;
+const TIME_EXCEEDED_V6: u8 = 3;
+
+/// Length of an IPv6 header, which raw ICMPv6 sockets leave out of what
+/// they receive, unlike IPv4 ones.
+const IPV6_HEADER_LEN: usize = 40;
+
+/// A raw ICMP socket for pinging `ip`, failing with
+/// [`io::ErrorKind::PermissionDenied`] without the privileges to open one.
+pub(crate) fn raw_socket(bind: &Bind, ip: IpAddr) -> io::Result<UdpSocket> {
+    let socket = match ip {
+        IpAddr::V4(_) => Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?,
+        IpAddr::V6(_) => Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?,
+    };
+
+    if let Some(source) = bind.ip {
+        socket.bind(&SocketAddr::new(source, 0).into())?;
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    socket.bind_device(bind.interface.as_deref().map(str::as_bytes))?;
+
+    if let IpAddr::V6(ip) = ip {
+        bind.set_ipv6_options(&socket, ip)?;
+    }
+
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// The routers on the way to `ip`, hop by hop, with `None` for hops which
+/// didn't answer within `wait`. Ends at the last hop that answered, which is
+/// `ip` itself if the pings got through.
+pub(crate) async fn trace(
+    bind: &Bind,
+    ip: IpAddr,
+    id: u16,
+    wait: Duration,
+) -> io::Result<Vec<Option<IpAddr>>> {
+    let socket = raw_socket(bind, ip)?;
+    let target = bind.addr(ip, 0);
+    for ttl in 1..=MAX_HOPS {
+        let sock = SockRef::from(&socket);
+        match ip {
+            IpAddr::V4(_) => sock.set_ttl_v4(ttl.into())?,
+            IpAddr::V6(_) => sock.set_unicast_hops_v6(ttl.into())?,
+        }
+
+        socket.send_to(&echo_request(ip, id, ttl), target).await?;
+    }
+
+    let mut hops = vec![None; MAX_HOPS as usize];
+    let mut reached = None;
+    let deadline = Instant::now() + wait;
+    let mut buf = [0; 1500];
+    while reached.is_none_or(|hop| hops[..hop].iter().any(Option::is_none)) {
+        let Ok(received) = timeout_at(deadline, socket.recv_from(&mut buf)).await else {
+            break;
+        };
+
+        let (len, from) = received?;
+        let Some((hop, done)) = answer(&buf[..len], ip, id) else {
+            continue;
+        };
+
+        let from = from.ip();
+        hops[hop - 1] = Some(from);
+
+        // the target answers pings that reach it, whatever their TTL
+        if done || from == ip {
+            reached = Some(reached.map_or(hop, |reached: usize| reached.min(hop)));
+        }
+    }
+
+    let end = match reached {
+        Some(hop) => hop,
+        None => hops
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(0, |idx| idx + 1),
+    };
+
+    hops.truncate(end);
+    Ok(hops)
+}
+
+/// An ICMP echo request with sequence number `seq`. ICMPv6 checksums are
+/// filled in by the kernel.
+fn echo_request(ip: IpAddr, id: u16, seq: u8) -> Vec<u8> {
+    let kind = match ip {
+        IpAddr::V4(_) => ECHO_REQUEST_V4,
+        IpAddr::V6(_) => ECHO_REQUEST_V6,
+    };
+
+    let mut packet = vec![kind, 0, 0, 0];
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&u16::from(seq).to_be_bytes());
+    packet.extend_from_slice(b"qapper traceroute");
+
+    if ip.is_ipv4() {
+        let checksum = checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    packet
+}
+
+/// The hop one of our pings to `ip` was answered from, as its sequence
+/// number, and whether the answer came from the end of the route, i.e. it's
+/// a reply or the destination is unreachable.
+fn answer(packet: &[u8], ip: IpAddr, id: u16) -> Option<(usize, bool)> {
+    let (icmp, echo_reply, unreachable, time_exceeded, inner_icmp) = match ip {
+        IpAddr::V4(_) => {
+            let header_len = usize::from(packet.first()? & 0x0f) * 4;
+            let icmp = packet.get(header_len..)?;
+            let inner_header_len = usize::from(icmp.get(8)? & 0x0f) * 4;
+            (
+                icmp,
+                ECHO_REPLY_V4,
+                UNREACHABLE_V4,
+                TIME_EXCEEDED_V4,
+                8 + inner_header_len,
+            )
+        }
+        IpAddr::V6(_) => (
+            packet,
+            ECHO_REPLY_V6,
+            UNREACHABLE_V6,
+            TIME_EXCEEDED_V6,
+            8 + IPV6_HEADER_LEN,
+        ),
+    };
+
+    let echo = |at: usize| {
+        let echo = icmp.get(at..at + 8)?;
+        let echo_id = u16::from_be_bytes([echo[4], echo[5]]);
+        let seq = usize::from(u16::from_be_bytes([echo[6], echo[7]]));
+        (echo_id == id && (1..=usize::from(MAX_HOPS)).contains(&seq)).then_some(seq)
+    };
+
+    match *icmp.first()? {
+        kind if kind == echo_reply => Some((echo(0)?, true)),
+        kind if kind == time_exceeded => Some((echo(inner_icmp)?, false)),
+        kind if kind == unreachable => Some((echo(inner_icmp)?, true)),
+        _ => None,
+    }
+}
+
+/// The internet checksum of `data`.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// Writes `hops` like traceroute, with `*` for those that didn't answer.
+pub(crate) fn format_hops(hops: &[Option<IpAddr>]) -> String {
+    hops.iter()
+        .map(|hop| hop.map_or("*".to_string(), |ip| ip.to_string()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    const V4: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const V6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+    const ID: u16 = 0x4242;
+
+    /// An IPv4 header without options, as raw sockets receive them.
+    const IPV4_HEADER: [u8; 20] = [
+        0x45, 0, 0, 0, 0, 0, 0, 0, 64, 1, 0, 0, 192, 0, 2, 1, 192, 0, 2, 99,
+    ];
+
+    /// `ip` answering our ping with `seq` with an ICMP message of `kind`:
+    /// either a reply, or an error quoting the ping.
+    fn answer_to(ip: IpAddr, kind: u8, id: u16, seq: u8) -> Vec<u8> {
+        let mut ping = echo_request(ip, id, seq);
+        let reply = match ip {
+            IpAddr::V4(_) => kind == ECHO_REPLY_V4,
+            IpAddr::V6(_) => kind == ECHO_REPLY_V6,
+        };
+        let icmp = if reply {
+            ping[0] = kind;
+            ping
+        } else {
+            let inner_header: &[u8] = if ip.is_ipv4() {
+                &IPV4_HEADER
+            } else {
+                &[0; IPV6_HEADER_LEN]
+            };
+            [&[kind, 0, 0, 0, 0, 0, 0, 0], inner_header, &ping].concat()
+        };
+
+        match ip {
+            IpAddr::V4(_) => [&IPV4_HEADER[..], &icmp].concat(),
+            IpAddr::V6(_) => icmp,
+        }
+    }
+
+    #[test]
+    fn computes_internet_checksums() {
+        for (data, expected) in [
+            // RFC 1071, 3
+            (
+                &[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7][..],
+                0x220d,
+            ),
+            (&[][..], 0xffff),
+            (&[0xff, 0xff][..], 0x0000),
+            // odd lengths are padded with a zero
+            (&[0x01][..], 0xfeff),
+        ] {
+            assert_eq!(checksum(data), expected, "{data:02x?}");
+        }
+
+        // a packet with its checksum filled in sums to zero
+        assert_eq!(checksum(&echo_request(V4, ID, 7)), 0);
+    }
+
+    #[test]
+    fn reads_answers() {
+        for (ip, kind, seq, expected) in [
+            (V4, ECHO_REPLY_V4, 5, Some((5, true))),
+            (V4, TIME_EXCEEDED_V4, 3, Some((3, false))),
+            (V4, UNREACHABLE_V4, 30, Some((30, true))),
+            (V6, ECHO_REPLY_V6, 5, Some((5, true))),
+            (V6, TIME_EXCEEDED_V6, 1, Some((1, false))),
+            (V6, UNREACHABLE_V6, 9, Some((9, true))),
+            // not a hop we sent
+            (V4, ECHO_REPLY_V4, 0, None),
+            (V4, TIME_EXCEEDED_V4, 31, None),
+            // e.g. a redirect
+            (V4, 5, 3, None),
+            (V6, ECHO_REQUEST_V6, 3, None),
+        ] {
+            assert_eq!(
+                answer(&answer_to(ip, kind, ID, seq), ip, ID),
+                expected,
+                "{ip} {kind} {seq}"
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_other_pings() {
+        let reply = answer_to(V4, TIME_EXCEEDED_V4, ID + 1, 3);
+        assert_eq!(answer(&reply, V4, ID), None);
+        let reply = answer_to(V6, ECHO_REPLY_V6, ID + 1, 3);
+        assert_eq!(answer(&reply, V6, ID), None);
+    }
+
+    #[test]
+    fn survives_truncated_answers() {
+        for (ip, kind) in [(V4, TIME_EXCEEDED_V4), (V6, TIME_EXCEEDED_V6)] {
+            let packet = answer_to(ip, kind, ID, 3);
+            // the quoted ping's ID and sequence number end 8 bytes in
+            let quoted = packet.len() - echo_request(ip, ID, 3).len() + 8;
+            for len in 0..quoted {
+                assert_eq!(answer(&packet[..len], ip, ID), None, "{ip}, {len} bytes");
+            }
+        }
+    }
+
+    #[test]
+    fn formats_hops() {
+        for (hops, expected) in [
+            (vec![], ""),
+            (vec![Some(V4)], "192.0.2.1"),
+            (vec![None, Some(V6), None], "*, 2001:db8::1, *"),
+        ] {
+            assert_eq!(format_hops(&hops), expected);
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! Probing UDP ports, which only answer requests they understand, if at all.
+//!
+//! Each protocol is spoken by a small state machine: it says what to send
+//! first, and for each datagram that comes back whether to answer it, keep
+//! waiting or stop. Requests which go unanswered are sent again a few times
+//! before the deadline, since a single lost datagram would otherwise make an
+//! open port look closed.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{
+    net::UdpSocket,
+    time::{timeout_at, Instant},
+};
+
+use super::{game, stun, Bind, Service, SourceAddress, Transcript};
+
+const TFTP: u16 = 69;
+const SYSLOG: u16 = 514;
+
+/// A port almost never in use, whose answer shows whether a host reports
+/// closed UDP ports at all.
+const CONTROL_PORT: u16 = 33434;
+
+/// How many times an unanswered request is sent again.
+const RETRANSMISSIONS: u32 = 2;
+
+/// How many follow-up requests a service may ask for, e.g. by answering
+/// with a challenge, before its answer is taken as is. Keeps a service
+/// which challenges every request from holding the probe until the
+/// deadline.
+const MAX_FOLLOW_UPS: u32 = 2;
+
+/// The protocol a UDP port is probed with, based on the service usually
+/// found there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Dialect {
+    Tftp,
+    Syslog,
+    /// Binding requests, with the ID of the transaction they're sent in.
+    Stun(stun::TransactionId),
+    A2s,
+    Bedrock,
+    Quake3,
+    /// Nothing in particular: an empty datagram, which is still enough to
+    /// find out whether the port is closed.
+    Empty,
+}
+
+/// What to do after a datagram came back.
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    /// Send this and wait for the answer to it instead.
+    Send(Vec<u8>),
+    /// That was the answer. Send this, if anything, to wrap up.
+    Done(Option<Vec<u8>>),
+    /// That wasn't an answer to what was sent, so keep waiting.
+    Ignore,
+}
+
+impl Dialect {
+    fn for_port(port: u16) -> Self {
+        match port {
+            TFTP => Self::Tftp,
+            SYSLOG => Self::Syslog,
+            3478 | 19302 => Self::Stun(stun::transaction_id()),
+            port if game::A2S_PORTS.contains(&port) => Self::A2s,
+            game::BEDROCK_PORT => Self::Bedrock,
+            game::QUAKE3_PORT => Self::Quake3,
+            _ => Self::Empty,
+        }
+    }
+
+    fn request(self) -> Vec<u8> {
+        match self {
+            Self::Tftp => tftp_read_request(),
+            Self::Syslog => b"<14>qapper: port scan probe".to_vec(),
+            Self::Stun(id) => stun::binding_request(&id),
+            Self::A2s => game::a2s_request(None),
+            Self::Bedrock => game::bedrock_request(),
+            Self::Quake3 => game::quake3_request(),
+            Self::Empty => vec![],
+        }
+    }
+
+    fn step(self, response: &[u8]) -> Step {
+        match self {
+            // A2S servers may want their challenge back before answering
+            Self::A2s => match game::a2s_challenge(response) {
+                Some(challenge) => Step::Send(game::a2s_request(Some(challenge))),
+                None => Step::Done(None),
+            },
+            // stop the server from sending the rest of the file, if it exists
+            Self::Tftp if response.starts_with(&[0, 3]) => {
+                Step::Done(Some(b"\0\x05\0\0qapper\0".to_vec()))
+            }
+            Self::Stun(id) if stun::is_other_transaction(response, &id) => Step::Ignore,
+            // anything else coming back from the port still shows it's open
+            _ => Step::Done(None),
+        }
+    }
+
+    fn identify(self, response: &[u8]) -> Option<Service> {
+        match self {
+            Self::Tftp => tftp_service(response),
+            Self::Stun(id) => stun::service(response, &id),
+            // e.g. a game server on a port of its own
+            Self::A2s | Self::Bedrock | Self::Quake3 | Self::Syslog | Self::Empty => {
+                game::service(response)
+            }
+        }
+    }
+
+    /// Whether sending the request again is harmless. Every copy of a syslog
+    /// message would be logged.
+    fn can_retransmit(self) -> bool {
+        self != Self::Syslog
+    }
+
+    /// TFTP servers answer from a port of their own, so they can't be probed
+    /// with a connected socket like other services, nor found to be closed.
+    fn answers_from_own_port(self) -> bool {
+        self == Self::Tftp
+    }
+}
+
+/// What a UDP port answered, and what it was asked.
+#[derive(Debug)]
+pub(crate) struct Answer {
+    dialect: Dialect,
+    response: Vec<u8>,
+}
+
+impl Answer {
+    /// Identifies the service that answered.
+    pub(crate) fn service(&self) -> Option<Service> {
+        self.dialect.identify(&self.response)
+    }
+}
+
+/// Sends a request to `ip:port` and waits until `deadline` for an answer,
+/// recording what's exchanged in `transcript`. Returns `None` if nothing came
+/// back, and a `ConnectionRefused` error if the host reported the port
+/// closed.
+pub(crate) async fn exchange(
+    bind: &Bind,
+    ip: IpAddr,
+    port: u16,
+    deadline: Instant,
+    transcript: &mut Transcript,
+) -> io::Result<Option<Answer>> {
+    let dialect = Dialect::for_port(port);
+    let socket = bind.udp(ip).await?;
+    let target = bind.addr(ip, port);
+    let connected = !dialect.answers_from_own_port();
+    if connected {
+        // connecting makes ICMP port unreachable messages show up as errors
+        socket.connect(target).await?;
+    }
+
+    let response = converse(&socket, connected, dialect, target, deadline, transcript).await?;
+    Ok(response.map(|response| Answer { dialect, response }))
+}
+
+/// Asks the STUN server at `server` which address traffic comes from, giving
+/// up at `deadline`. Returns `None` if it didn't say.
+pub(crate) async fn source_address(
+    bind: &Bind,
+    server: SocketAddr,
+    deadline: Instant,
+) -> io::Result<Option<SourceAddress>> {
+    let socket = bind.udp(server.ip()).await?;
+    socket.connect(server).await?;
+    let local = socket.local_addr()?.ip();
+
+    let id = stun::transaction_id();
+    let response = converse(
+        &socket,
+        true,
+        Dialect::Stun(id),
+        server,
+        deadline,
+        &mut Transcript::default(),
+    )
+    .await?;
+
+    Ok(response
+        .as_deref()
+        .and_then(|response| stun::reflected(response, &id))
+        .map(|public| SourceAddress {
+            local,
+            public: public.ip(),
+        }))
+}
+
+/// Speaks `dialect` with `target` over `socket` until `deadline`, as
+/// described for [`exchange`].
+async fn converse(
+    socket: &UdpSocket,
+    connected: bool,
+    dialect: Dialect,
+    target: SocketAddr,
+    deadline: Instant,
+    transcript: &mut Transcript,
+) -> io::Result<Option<Vec<u8>>> {
+    let retransmissions = if dialect.can_retransmit() {
+        RETRANSMISSIONS
+    } else {
+        0
+    };
+
+    let mut request = dialect.request();
+    let mut follow_ups = 0;
+    let mut buf = vec![0; 2048];
+    'requests: loop {
+        // spread the attempts evenly over the time left
+        let interval = deadline.saturating_duration_since(Instant::now()) / (retransmissions + 1);
+        let interval = interval.max(Duration::from_millis(1));
+
+        for _ in 0..=retransmissions {
+            if connected {
+                socket.send(&request).await?;
+            } else {
+                socket.send_to(&request, target).await?;
+            }
+
+            transcript.sent(&request);
+            let retransmit_at = deadline.min(Instant::now() + interval);
+            loop {
+                let (len, from) = match timeout_at(retransmit_at, socket.recv_from(&mut buf)).await
+                {
+                    Ok(received) => received?,
+                    Err(_) => break,
+                };
+
+                if from.ip() != target.ip() {
+                    continue;
+                }
+
+                let response = &buf[..len];
+                transcript.received(response);
+                match dialect.step(response) {
+                    // something answered, which is all the port needs
+                    Step::Send(_) if follow_ups == MAX_FOLLOW_UPS => {
+                        return Ok(Some(response.to_vec()));
+                    }
+                    Step::Send(next) => {
+                        follow_ups += 1;
+                        request = next;
+                        continue 'requests;
+                    }
+                    Step::Done(reply) => {
+                        if let Some(reply) = reply {
+                            let _ = socket.send_to(&reply, from).await;
+                            transcript.sent(&reply);
+                        }
+
+                        return Ok(Some(response.to_vec()));
+                    }
+                    Step::Ignore => {}
+                }
+            }
+        }
+
+        return Ok(None);
+    }
+}
+
+/// Whether the request sent to `port` can be sent again without harm.
+pub(crate) fn can_retransmit(port: u16) -> bool {
+    Dialect::for_port(port).can_retransmit()
+}
+
+/// Identifies services which never answer on `ip:port`, which stayed silent.
+/// They're only taken to be listening if the host reports closed ports, so
+/// the silence isn't just a firewall.
+pub(crate) async fn identify_silent(
+    bind: &Bind,
+    ip: IpAddr,
+    port: u16,
+    deadline: Instant,
+) -> io::Result<Option<Service>> {
+    if Dialect::for_port(port) != Dialect::Syslog {
+        return Ok(None);
+    }
+
+    match exchange(bind, ip, CONTROL_PORT, deadline, &mut Transcript::default()).await {
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(Some(Service {
+            name: "syslog".to_string(),
+            version: None,
+            details: Some("accepts messages".to_string()),
+        })),
+        Err(e) => Err(e),
+        Ok(_) => Ok(None),
+    }
+}
+
+/// A read request for a file which surely doesn't exist.
+fn tftp_read_request() -> Vec<u8> {
+    let mut request = vec![0, 1];
+    request.extend_from_slice(b"qapper-probe\0octet\0");
+    request
+}
+
+fn tftp_service(response: &[u8]) -> Option<Service> {
+    let details = match response {
+        [0, 3, ..] => "serves reads".to_string(),
+        [0, 5, code0, code1, message @ ..] => {
+            let code = u16::from_be_bytes([*code0, *code1]);
+            let message = String::from_utf8_lossy(message);
+            let message = message.trim_end_matches('\0');
+            format!("read refused: error {code} {message}")
+        }
+        _ => return None,
+    };
+
+    Some(Service {
+        name: "tftp".to_string(),
+        version: None,
+        details: Some(details.trim_end().to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A binding success response without attributes, in transaction `id`.
+    fn stun_response(id: &stun::TransactionId) -> Vec<u8> {
+        let mut response = stun::binding_request(id);
+        response[..2].copy_from_slice(&[0x01, 0x01]);
+        response
+    }
+
+    #[test]
+    fn picks_dialects_by_port() {
+        assert_eq!(Dialect::for_port(69), Dialect::Tftp);
+        assert_eq!(Dialect::for_port(514), Dialect::Syslog);
+        assert!(matches!(Dialect::for_port(3478), Dialect::Stun(_)));
+        assert_eq!(Dialect::for_port(27016), Dialect::A2s);
+        assert_eq!(Dialect::for_port(19132), Dialect::Bedrock);
+        assert_eq!(Dialect::for_port(27960), Dialect::Quake3);
+        assert_eq!(Dialect::for_port(53), Dialect::Empty);
+    }
+
+    #[test]
+    fn answers_a2s_challenges() {
+        let challenge = b"\xff\xff\xff\xffA\x4b\xa1\x19\x5c";
+        assert_eq!(
+            Dialect::A2s.step(challenge),
+            Step::Send(game::a2s_request(Some(&challenge[5..])))
+        );
+        assert_eq!(
+            Dialect::A2s.step(b"\xff\xff\xff\xffI\x11"),
+            Step::Done(None)
+        );
+    }
+
+    #[test]
+    fn ignores_other_stun_transactions() {
+        let id = stun::transaction_id();
+        let dialect = Dialect::Stun(id);
+        assert_eq!(
+            dialect.step(&stun_response(&stun::transaction_id())),
+            Step::Ignore
+        );
+        assert_eq!(dialect.step(&stun_response(&id)), Step::Done(None));
+        // not STUN at all, but the port still answered
+        assert_eq!(dialect.step(b"hello"), Step::Done(None));
+    }
+
+    #[test]
+    fn stops_tftp_transfers() {
+        let data = b"\0\x03\0\x01file contents";
+        assert_eq!(
+            Dialect::Tftp.step(data),
+            Step::Done(Some(b"\0\x05\0\0qapper\0".to_vec()))
+        );
+        assert_eq!(
+            Dialect::Tftp.step(b"\0\x05\0\x01File not found\0"),
+            Step::Done(None)
+        );
+    }
+
+    #[test]
+    fn takes_anything_else_as_the_answer() {
+        for dialect in [
+            Dialect::Syslog,
+            Dialect::Bedrock,
+            Dialect::Quake3,
+            Dialect::Empty,
+        ] {
+            assert_eq!(dialect.step(b""), Step::Done(None), "{dialect:?}");
+            assert_eq!(dialect.step(b"\x01\x02"), Step::Done(None), "{dialect:?}");
+        }
+    }
+
+    #[test]
+    fn identifies_tftp_servers() {
+        assert_eq!(
+            tftp_service(b"\0\x03\0\x01file contents")
+                .unwrap()
+                .details
+                .as_deref(),
+            Some("serves reads")
+        );
+        assert_eq!(
+            tftp_service(b"\0\x05\0\x01File not found\0")
+                .unwrap()
+                .details
+                .as_deref(),
+            Some("read refused: error 1 File not found")
+        );
+        assert_eq!(
+            tftp_service(b"\0\x05\0\x02\0").unwrap().details.as_deref(),
+            Some("read refused: error 2")
+        );
+        assert_eq!(tftp_service(b"\0\x04\0\x01"), None);
+        assert_eq!(tftp_service(b"\0"), None);
+    }
+
+    #[test]
+    fn identifies_by_dialect() {
+        let id = stun::transaction_id();
+        let stun = Dialect::Stun(id).identify(&stun_response(&id)).unwrap();
+        assert_eq!(stun.name, "stun");
+        assert_eq!(Dialect::Tftp.identify(&stun_response(&id)), None);
+
+        let tftp = Dialect::Tftp.identify(b"\0\x03\0\x01").unwrap();
+        assert_eq!(tftp.name, "tftp");
+        assert_eq!(Dialect::Empty.identify(b"\0\x03\0\x01"), None);
+
+        // game servers are recognized whichever port they're found on
+        let quake3 = b"\xff\xff\xff\xffstatusResponse\n\\sv_hostname\\noname\n";
+        for dialect in [Dialect::Quake3, Dialect::A2s, Dialect::Empty] {
+            assert_eq!(
+                dialect.identify(quake3).map(|service| service.name),
+                Some("quake3".to_string()),
+                "{dialect:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! A status line on stderr every so often while a scan runs, for
+//! --stats-every, so scans left unattended, e.g. in CI, still show they're
+//! alive.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use qapper::{group_digits, ProbeSlots, SlotStats};
+
+#[derive(Debug)]
+pub(crate) struct Progress {
+    /// Ports to be checked, at most, as hosts which don't answer pings
+    /// aren't checked.
+    total: usize,
+    checked: AtomicUsize,
+    /// Whether to separate thousands and show how long is left in minutes
+    /// and hours, rather than in plain numbers.
+    human: bool,
+}
+
+impl Progress {
+    pub(crate) fn new(total: usize, human: bool) -> Self {
+        Self {
+            total,
+            checked: AtomicUsize::new(0),
+            human,
+        }
+    }
+
+    /// Counts one more port checked.
+    pub(crate) fn checked(&self) {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Prints how far along the scan is every `interval`, and how its
+    /// `slots` are shared out, if limited, until aborted.
+    pub(crate) async fn report(
+        self: Arc<Self>,
+        interval: Duration,
+        slots: Option<Arc<ProbeSlots>>,
+    ) {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        let mut last = 0;
+        loop {
+            ticks.tick().await;
+            let checked = self.checked.load(Ordering::Relaxed);
+            let slots = slots.as_ref().map(|slots| slots.stats());
+            eprintln!("{}", self.status(checked, checked - last, interval, slots));
+            last = checked;
+        }
+    }
+
+    /// E.g. `42.0% done, 4200 of 10000 ports, 512 ports/s, about 11s left`,
+    /// or `about 1h 2m 3s left` rather than `about 3723s left` for people,
+    /// going by the rate over the last `interval`, in which `recent` ports
+    /// were checked. With `slots`, followed by e.g. `, 100 of 100 slots in
+    /// use by 12 hosts, 30 hosts waiting`.
+    fn status(
+        &self,
+        checked: usize,
+        recent: usize,
+        interval: Duration,
+        slots: Option<SlotStats>,
+    ) -> String {
+        let total = self.total.max(checked);
+        let percent = match total {
+            0 => 100.0,
+            total => checked as f64 * 100.0 / total as f64,
+        };
+        let rate = recent as f64 / interval.as_secs_f64();
+        let secs = ((total - checked) as f64 / rate).ceil() as u64;
+        let left = if rate <= 0.0 {
+            "stalled".to_string()
+        } else if self.human {
+            format!(
+                "about {} left",
+                humantime::format_duration(Duration::from_secs(secs))
+            )
+        } else {
+            format!("about {secs}s left")
+        };
+        let count = |n: usize| {
+            if self.human {
+                group_digits(n as u64)
+            } else {
+                n.to_string()
+            }
+        };
+
+        let rate = format!("{rate:.0}");
+        let rate = if self.human {
+            group_digits(rate.parse().unwrap_or_default())
+        } else {
+            rate
+        };
+
+        let mut status = format!(
+            "{percent:.1}% done, {} of {} ports, {rate} ports/s, {left}",
+            count(checked),
+            count(total)
+        );
+        if let Some(slots) = slots {
+            status += &format!(
+                ", {} of {} slots in use by {} hosts, {} hosts waiting",
+                count(slots.in_use),
+                count(slots.total),
+                count(slots.hosts_holding),
+                count(slots.hosts_waiting)
+            );
+        }
+
+        status
+    }
+}
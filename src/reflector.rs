@@ -0,0 +1,148 @@
+//! Scans whoever asks, so machines can find out which of their ports are
+//! reachable from outside their network. `GET /scan?ports=22,443&udp=53` is
+//! answered with the results of scanning the address it came from, as a
+//! JSON list of events like `--simulate` reads. No other address is ever
+//! scanned, so the reflector can't be used against anyone else.
+
+use std::{
+    collections::HashSet,
+    io,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::trace;
+use qapper::{PortScanner, Ports, ScanEvent};
+use tokio::{net::TcpListener, time::timeout};
+use tokio_stream::StreamExt;
+
+use crate::web::{self, HttpUrl, Request, Response};
+
+/// Ports checked at once for each scan.
+const CONCURRENCY: usize = 100;
+
+/// How long a reflector may take to scan us, plenty for the 1000 ports it
+/// scans at most by default.
+const SELF_SCAN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Answers scan requests on `listener`, scanning at most `max_ports` ports
+/// per request with `timeout` (ms) for each.
+pub(crate) async fn serve(listener: TcpListener, max_ports: usize, timeout: u64) {
+    // one scan per address at a time
+    let scanning = Arc::new(Mutex::new(HashSet::new()));
+    web::serve(listener, "scan", move |request: Request| {
+        let scanning = Arc::clone(&scanning);
+        async move {
+            let ip = request.peer.ip().to_canonical();
+            let query = match (request.method.as_str(), request.target.split_once('?')) {
+                ("GET", Some(("/scan", query))) => query.to_string(),
+                ("GET", None) if request.target == "/scan" => String::new(),
+                ("GET", _) => return Response::error("404 Not Found"),
+                _ => return Response::error("405 Method Not Allowed"),
+            };
+
+            let Some(_scanning) = Scanning::start(&scanning, ip) else {
+                return Response::error("429 Too Many Requests");
+            };
+
+            match scan(ip, &query, max_ports, timeout).await {
+                Ok(events) => Response::new(
+                    "200 OK",
+                    "application/json",
+                    serde_json::to_string(&events).expect("events serialize"),
+                ),
+                Err(e) => Response::new("400 Bad Request", "text/plain", format!("{e}\n")),
+            }
+        }
+    })
+    .await
+}
+
+/// Marks an address as being scanned until dropped, however the scan ends,
+/// even if its task panics.
+struct Scanning<'a> {
+    scanning: &'a Mutex<HashSet<IpAddr>>,
+    ip: IpAddr,
+}
+
+impl<'a> Scanning<'a> {
+    /// Marks `ip` as being scanned, unless it already is.
+    fn start(scanning: &'a Mutex<HashSet<IpAddr>>, ip: IpAddr) -> Option<Self> {
+        scanning
+            .lock()
+            .unwrap()
+            .insert(ip)
+            .then_some(Self { scanning, ip })
+    }
+}
+
+impl Drop for Scanning<'_> {
+    fn drop(&mut self) {
+        // still release the address if another scan poisoned the lock
+        let mut scanning = self.scanning.lock().unwrap_or_else(|e| e.into_inner());
+        scanning.remove(&self.ip);
+    }
+}
+
+/// Scans `ip` for the ports asked for in `query`.
+async fn scan(
+    ip: IpAddr,
+    query: &str,
+    max_ports: usize,
+    timeout: u64,
+) -> io::Result<Vec<ScanEvent>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let (mut ports, mut udp_ports) = (Ports::default(), Ports::default());
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        let value = value.replace("%2C", ",").replace("%2c", ",");
+        let parsed: Ports = value
+            .parse()
+            .map_err(|e| invalid(format!("invalid {name}: {e}")))?;
+        match name {
+            "ports" => ports = parsed,
+            "udp" => udp_ports = parsed,
+            _ => return Err(invalid(format!("unknown parameter {name}"))),
+        }
+    }
+
+    if ports.len() + udp_ports.len() > max_ports {
+        return Err(invalid(format!("at most {max_ports} ports can be scanned")));
+    }
+
+    trace!("Scanning {ip} for {ports} and UDP {udp_ports}, as asked");
+    let scanner = PortScanner::builder()
+        .ports(ports)
+        .udp_ports(udp_ports)
+        .targets(vec![ip])
+        .timeout(timeout)
+        .discovery(false)
+        .concurrency(CONCURRENCY)
+        .build()?;
+
+    Ok(scanner.scan_stream().collect().await)
+}
+
+/// Asks the reflector at `url` to scan this machine for `ports` and
+/// `udp_ports`, returning what it found.
+pub(crate) async fn self_scan(
+    url: &HttpUrl,
+    ports: &Ports,
+    udp_ports: &Ports,
+) -> io::Result<Vec<ScanEvent>> {
+    let mut params = vec![];
+    if !ports.is_empty() {
+        params.push(format!("ports={ports}"));
+    }
+
+    if !udp_ports.is_empty() {
+        params.push(format!("udp={udp_ports}"));
+    }
+
+    let url = url.with_query(&params.join("&"));
+    let body = timeout(SELF_SCAN_TIMEOUT, url.request("GET", None))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no answer"))??;
+    serde_json::from_slice(&body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,169 @@
+//! Scanning a random share of each subnet first, to estimate how many of its
+//! hosts are up before committing to a sweep of the whole range.
+
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::{fake::Rng, scanner::ScanReport};
+
+/// The most hosts a subnet may have to be scanned.
+pub const MAX_SUBNET_HOSTS: u64 = 1 << 24;
+
+/// How a sample of a subnet's hosts went, and what it suggests about the
+/// rest.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SampleStatus {
+    /// Hosts in the subnet.
+    pub hosts: u64,
+    /// Hosts scanned.
+    pub sampled: u64,
+    /// Hosts among those scanned which responded.
+    pub up: u64,
+    /// Share of the sampled hosts which responded, from 0 to 1.
+    pub density: f64,
+    /// Hosts likely to respond in the whole subnet.
+    pub estimated_up: u64,
+}
+
+/// How many host addresses `subnet` has, leaving out the network and
+/// broadcast addresses of IPv4 subnets like [`IpNet::hosts`].
+pub fn subnet_hosts(subnet: &IpNet) -> u128 {
+    let bits = subnet.max_prefix_len() - subnet.prefix_len();
+    let all = 1u128.checked_shl(bits.into()).unwrap_or(u128::MAX);
+    match subnet {
+        IpNet::V4(_) if bits > 1 => all - 2,
+        _ => all,
+    }
+}
+
+/// Picks `share` (from 0 to 1) of the hosts in `subnet` at random, at least
+/// one, in order. The same seed always picks the same hosts.
+///
+/// # Panics
+///
+/// If the subnet has more than [`MAX_SUBNET_HOSTS`].
+pub fn sample_subnet(subnet: &IpNet, share: f64, seed: u64) -> Vec<IpAddr> {
+    let hosts = subnet_hosts(subnet);
+    assert!(hosts <= MAX_SUBNET_HOSTS.into(), "{subnet} is too large");
+    let hosts = hosts as u64;
+    let wanted = ((hosts as f64 * share).ceil() as u64).clamp(1, hosts);
+
+    // Floyd's algorithm, which takes as many draws as hosts wanted
+    let mut rng = Rng::new(seed);
+    let mut picked = HashSet::new();
+    for n in hosts - wanted..hosts {
+        let offset = rng.below(n + 1);
+        if !picked.insert(offset) {
+            picked.insert(n);
+        }
+    }
+
+    let first = subnet.hosts().next().expect("subnets have hosts");
+    let mut addrs: Vec<IpAddr> = picked
+        .into_iter()
+        .map(|offset| match first {
+            IpAddr::V4(ip) => Ipv4Addr::from(u32::from(ip) + offset as u32).into(),
+            IpAddr::V6(ip) => Ipv6Addr::from(u128::from(ip) + u128::from(offset)).into(),
+        })
+        .collect();
+    addrs.sort();
+    addrs
+}
+
+impl ScanReport {
+    /// Estimates how many hosts of `subnet` are up from how many of `addrs`,
+    /// a sample of them, were.
+    pub(crate) fn estimate(&mut self, subnet: IpNet, addrs: &[IpAddr]) {
+        let hosts = subnet_hosts(&subnet).min(u64::MAX.into()) as u64;
+        let sampled = addrs.len() as u64;
+        let up = addrs
+            .iter()
+            .filter(|ip| self.hosts.contains_key(ip))
+            .count() as u64;
+        let density = if sampled == 0 {
+            0.0
+        } else {
+            up as f64 / sampled as f64
+        };
+
+        let status = SampleStatus {
+            hosts,
+            sampled,
+            up,
+            density,
+            estimated_up: (hosts as f64 * density).round() as u64,
+        };
+
+        self.samples.insert(subnet, status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subnet(cidr: &str) -> IpNet {
+        cidr.parse().unwrap()
+    }
+
+    #[test]
+    fn counts_hosts_without_network_and_broadcast() {
+        assert_eq!(subnet_hosts(&subnet("10.0.0.0/24")), 254);
+        assert_eq!(subnet_hosts(&subnet("10.0.0.0/31")), 2);
+        assert_eq!(subnet_hosts(&subnet("10.0.0.1/32")), 1);
+        assert_eq!(subnet_hosts(&subnet("fd00::/120")), 256);
+        assert_eq!(subnet_hosts(&subnet("::/0")), u128::MAX);
+    }
+
+    #[test]
+    fn samples_whole_subnets() {
+        let subnet = subnet("10.0.0.0/28");
+        let all: Vec<IpAddr> = subnet.hosts().collect();
+        assert_eq!(sample_subnet(&subnet, 1.0, 7), all);
+    }
+
+    #[test]
+    fn samples_at_least_one_host() {
+        let sample = sample_subnet(&subnet("10.0.0.0/24"), 0.0, 7);
+        assert_eq!(sample.len(), 1);
+        assert!(subnet("10.0.0.0/24").contains(&sample[0]));
+    }
+
+    #[test]
+    fn samples_the_same_hosts_for_the_same_seed() {
+        let subnet = subnet("fd00::/112");
+        let sample = sample_subnet(&subnet, 0.01, 7);
+        assert_eq!(sample.len(), 656);
+        assert!(sample.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(sample.iter().all(|ip| subnet.contains(ip)));
+        assert_eq!(sample_subnet(&subnet, 0.01, 7), sample);
+        assert_ne!(sample_subnet(&subnet, 0.01, 8), sample);
+    }
+
+    #[test]
+    fn samples_subnets_of_up_to_max_hosts() {
+        assert_eq!(subnet_hosts(&subnet("fd00::/104")), MAX_SUBNET_HOSTS.into());
+        assert_eq!(sample_subnet(&subnet("fd00::/104"), 0.0, 7).len(), 1);
+        assert_eq!(sample_subnet(&subnet("10.0.0.0/8"), 0.0, 7).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "too large")]
+    fn refuses_subnets_over_max_hosts() {
+        sample_subnet(&subnet("fd00::/103"), 0.0, 7);
+    }
+
+    #[test]
+    fn estimates_nothing_up_from_no_samples() {
+        let mut report = ScanReport::default();
+        report.estimate(subnet("10.0.0.0/24"), &[]);
+        let status = &report.samples[&subnet("10.0.0.0/24")];
+        assert_eq!((status.hosts, status.sampled, status.up), (254, 0, 0));
+        assert_eq!((status.density, status.estimated_up), (0.0, 0));
+    }
+}
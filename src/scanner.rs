@@ -1,175 +1,1970 @@
-use std::{collections::HashMap, io, net::IpAddr, sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    collections::{HashMap, HashSet},
+    fs,
+    future::Future,
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    pin::{pin, Pin},
+    sync::{Arc, Mutex, Once},
+    time::{Duration, SystemTime},
+};
 
+use ipnet::IpNet;
 use log::{error, trace};
-use surge_ping::{Client as PingClient, Config as PingConfig, PingIdentifier, PingSequence, ICMP};
-use tokio::{net::TcpStream, sync::mpsc, time::timeout};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use socket2::Type;
+use surge_ping::{Client as PingClient, IcmpPacket, PingIdentifier, PingSequence, ICMP};
+use tokio::{
+    io::AsyncReadExt,
+    net::TcpStream,
+    sync::mpsc,
+    task::{JoinHandle, JoinSet},
+    time::{timeout, timeout_at, Instant},
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
 
-use crate::ports::{Ports, PortsStatus};
+use crate::{
+    credentials::Credentials,
+    enrich::{self, Enricher},
+    exhaustion::{self, SocketExhaustion, SocketLimit},
+    fake, mac,
+    names::NameStatus,
+    os::{self, Fingerprint},
+    pacing::IcmpPacer,
+    policy::{Policy, Protocol, RateLimiter},
+    port_rules::PortRules,
+    ports::{Ports, PortsStatus},
+    probes::{self, Bind, HttpInfo, ProbeKind, Service, SourceAddress, TlsInfo, Transcript},
+    sample::SampleStatus,
+    sink::ResultSink,
+    slots::ProbeSlots,
+    stats::ScanStats,
+};
 
+/// Pings each address and checks which of the given ports accept TCP
+/// connections. `on_checked` is called as each port is checked.
+///
+/// Created with [`PortScanner::builder`].
 pub struct PortScanner<Callback>
 where
-    Callback: FnMut(&'static IpAddr, u16, bool) -> (),
+    Callback: FnMut(IpAddr, u16, bool),
 {
-    inner: Arc<ScannerInner<'static>>,
-    channel: (PortSender<'static>, PortReceiver<'static>),
+    inner: Arc<ScannerInner>,
+    channel: (PortSender, PortReceiver),
+    simulated: Option<Vec<ScanEvent>>,
+    resumed: Vec<ScanEvent>,
+    names: BTreeMap<String, Vec<IpAddr>>,
+    samples: BTreeMap<IpNet, Vec<IpAddr>>,
+    unresolved: Vec<String>,
+    sinks: Vec<Box<dyn ResultSink>>,
+    enrichers: Vec<Arc<dyn Enricher>>,
     on_checked: Callback,
+    on_checked_async: Option<AsyncCallback>,
+}
+
+impl PortScanner<NoopCallback> {
+    pub fn builder() -> PortScannerBuilder {
+        PortScannerBuilder::default()
+    }
 }
 
 impl<Callback> PortScanner<Callback>
 where
-    Callback: FnMut(&'static IpAddr, u16, bool) -> (),
+    Callback: FnMut(IpAddr, u16, bool),
 {
-    pub fn new(
-        ports: Ports,
-        addrs: &'static [IpAddr],
-        timeout: u64,
-        on_checked: Callback,
-    ) -> io::Result<Self> {
-        let inner = ScannerInner::new(ports, addrs, timeout).map(Arc::new)?;
-
-        Ok(Self {
-            inner,
-            channel: mpsc::channel(100),
-            on_checked,
-        })
+    /// Returns a token which stops the scan when cancelled. Ports that haven't
+    /// been checked by then are left out of the results, and the report is
+    /// marked [`partial`](ScanReport::partial).
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.inner.cancel.clone()
     }
 
-    pub async fn scan(mut self) -> HashMap<IpAddr, PortsStatus> {
-        let (tx, mut rx) = self.channel;
+    /// The slots ports are checked in, to see how they're shared out between
+    /// hosts as the scan runs, unless any number of ports may be checked at
+    /// once.
+    pub fn slots(&self) -> Option<Arc<ProbeSlots>> {
+        self.inner.slots.clone()
+    }
 
-        for (idx, ip) in self.inner.addrs.iter().enumerate() {
-            let inner = Arc::clone(&self.inner);
-            let tx = tx.clone();
+    /// Scans all ports on all responding hosts, returning the status of
+    /// each host once every port has been checked. Results are passed to the
+    /// sinks as they come in, failing if any of them does.
+    pub async fn scan(mut self) -> io::Result<ScanReport> {
+        let num_ports = self.inner.ports.len();
+        let source = match self.inner.stun_server {
+            Some(server) if self.simulated.is_none() => self.inner.source_address(server).await,
+            _ => None,
+        };
+
+        let mut sinks = std::mem::take(&mut self.sinks);
+        let enrichers = std::mem::take(&mut self.enrichers);
+        let names = std::mem::take(&mut self.names);
+        let samples = std::mem::take(&mut self.samples);
+        let unresolved = std::mem::take(&mut self.unresolved);
+        let inner = Arc::clone(&self.inner);
+        let mac_lookup = self.inner.mac_lookup && self.simulated.is_none();
+        let started = Instant::now();
+        let mut events = pin!(self.scan_stream());
+
+        let mut stats = ScanStats::default();
+        let mut report = ScanReport {
+            source,
+            unresolved,
+            ..Default::default()
+        };
+        while let Some(event) = events.next().await {
+            for sink in &mut sinks {
+                sink.record(&event)?;
+            }
+
+            stats.record(&event);
+            report
+                .hosts
+                .entry(event.ip)
+                .or_insert(PortsStatus::new(num_ports))
+                .record(&event);
+        }
 
-            // TODO: avoid collisions
-            let id = (idx % (u16::MAX as usize)) as u16;
+        for status in report.hosts.values_mut() {
+            status.sort();
+            status.filter_middlebox();
+        }
 
-            tokio::spawn(async move { inner.scan_ip(ip, tx, id).await });
+        report.routes = std::mem::take(&mut inner.routes.lock().unwrap());
+        report.exhaustion = inner.sockets.report();
+        report.partial = inner.cancel.is_cancelled();
+        stats.finish(inner.addrs.len(), report.hosts.len(), started.elapsed());
+        report.stats = stats;
+        for (ip, fingerprint) in inner.fingerprints.lock().unwrap().iter() {
+            if let Some(status) = report.hosts.get_mut(ip) {
+                status.guess_os(fingerprint);
+            }
         }
 
-        // if we don't do this, the loop below will never end going loopy loopy...
-        drop(tx);
+        if mac_lookup {
+            let mut neighbors = tokio::task::spawn_blocking(mac::neighbors).await.unwrap();
+            for (ip, status) in &mut report.hosts {
+                if let Some(mac) = neighbors.remove(ip) {
+                    status.set_mac(mac);
+                }
+            }
+        }
 
-        let mut map = HashMap::new();
-        while let Some((ip, port, open)) = rx.recv().await {
-            (self.on_checked)(ip, port, open);
+        // whoever cancelled the scan wants the results now
+        if !report.partial {
+            enrich::run(&enrichers, &mut report.hosts).await;
+        }
 
-            map.entry(*ip)
-                .or_insert(PortsStatus::new(self.inner.ports.len()))
-                .record(port, open);
+        for (name, addrs) in names {
+            report.group(name, addrs);
         }
 
-        for status in map.values_mut() {
-            status.sort();
+        for (subnet, addrs) in samples {
+            report.estimate(subnet, &addrs);
         }
 
-        map
+        for sink in &mut sinks {
+            sink.finish(&report)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Scans all ports on all responding hosts, yielding a [`ScanEvent`] as
+    /// each port is checked. The stream ends once every port has been checked.
+    pub fn scan_stream(self) -> impl Stream<Item = ScanEvent> {
+        let Self {
+            inner,
+            channel: (tx, rx),
+            simulated,
+            resumed,
+            mut on_checked,
+            mut on_checked_async,
+            ..
+        } = self;
+
+        if !resumed.is_empty() {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                for event in resumed {
+                    if tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        if let Some(events) = simulated {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                for event in events {
+                    if tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        } else {
+            if inner.slots.is_some() {
+                let inner = Arc::clone(&inner);
+                let tx = tx.downgrade();
+                tokio::spawn(async move {
+                    let mut ticks = tokio::time::interval(SLOT_STATS_INTERVAL);
+                    ticks.tick().await;
+                    loop {
+                        ticks.tick().await;
+                        // every host is done once all their senders are gone
+                        if tx.upgrade().is_none() || inner.cancel.is_cancelled() {
+                            break;
+                        }
+
+                        if let Some(slots) = &inner.slots {
+                            trace!("stats: {}", slots.stats());
+                        }
+                    }
+                });
+            }
+
+            for (idx, &ip) in inner.addrs.iter().enumerate() {
+                let inner = Arc::clone(&inner);
+                let tx = tx.clone();
+
+                // TODO: avoid collisions
+                let id = (idx % (u16::MAX as usize)) as u16;
+
+                tokio::spawn(async move { inner.scan_ip(ip, tx, id).await });
+            }
+        }
+
+        // if we don't do this, the stream below will never end going loopy loopy...
+        drop(tx);
+
+        ReceiverStream::new(rx).then(move |event| {
+            on_checked(event.ip, event.port, event.open);
+            let handled = on_checked_async
+                .as_mut()
+                .map(|f| f(event.ip, event.port, event.open));
+
+            async move {
+                if let Some(handled) = handled {
+                    handled.await;
+                }
+
+                event
+            }
+        })
     }
 }
 
-struct ScannerInner<'a> {
-    pinger4: Option<PingClient>,
-    pinger6: Option<PingClient>,
-    ports: Ports,
-    addrs: &'a [IpAddr],
+/// Results of a whole scan, by host.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ScanReport {
+    /// The address the scan came from, if a STUN server was asked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceAddress>,
+    pub hosts: BTreeMap<IpAddr, PortsStatus>,
+    /// Host names given with the targets they resolved to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub names: BTreeMap<String, NameStatus>,
+    /// Subnets only a sample of was scanned, with how many of their hosts are
+    /// likely up.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub samples: BTreeMap<IpNet, SampleStatus>,
+    /// Host names given as targets which couldn't be resolved, and so weren't
+    /// scanned.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<String>,
+    /// Routes traced to hosts which didn't respond or had no open ports, if
+    /// tracing is enabled, hop by hop with `None` for hops that didn't
+    /// answer.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub routes: BTreeMap<IpAddr, Vec<Option<IpAddr>>>,
+    /// How often the scan ran out of sockets and backed off, if it ever did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exhaustion: Option<SocketExhaustion>,
+    /// How many hosts and ports were scanned, what was found and how long it
+    /// took.
+    #[serde(default)]
+    pub stats: ScanStats,
+    /// Whether the scan was cancelled before every port was checked, leaving
+    /// only the results so far.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+}
+
+/// The result of checking a single port.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanEvent {
+    pub ip: IpAddr,
+    pub port: u16,
+    /// Whether the port was checked over TCP or UDP.
+    #[serde(default, skip_serializing_if = "Protocol::is_tcp")]
+    pub protocol: Protocol,
+    pub open: bool,
+    /// Whether a closed TCP port didn't answer within the timeout, rather
+    /// than refusing the connection, e.g. as a firewall dropped it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub filtered: bool,
+    /// When the port was checked, i.e. when the connection attempt started.
+    #[serde(with = "crate::rfc3339", default = "SystemTime::now")]
+    pub time: SystemTime,
+    /// What the service sent right after connecting, if banner grabbing is
+    /// enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    /// The service identified on the port, if service detection is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<Service>,
+    /// What a TLS handshake with the port revealed, if TLS probing is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsInfo>,
+    /// What the port answered to `GET /`, if HTTP probing is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpInfo>,
+    /// How many milliseconds after connecting the port hung up without a
+    /// word, if connections are held and it didn't last.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dropped: Option<u64>,
+    /// How many microseconds connecting to the port took, if it's an open
+    /// TCP port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<u64>,
+    /// How many microseconds the host took to answer a ping, if it was
+    /// pinged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt: Option<u64>,
+}
+
+type NoopCallback = fn(IpAddr, u16, bool);
+type AsyncCallback =
+    Box<dyn FnMut(IpAddr, u16, bool) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// Builder for [`PortScanner`]. Ports and targets must be given, everything
+/// else has a default.
+pub struct PortScannerBuilder<Callback = NoopCallback> {
+    options: BuilderOptions,
+    on_checked: Callback,
+}
+
+/// Everything a [`PortScannerBuilder`] is given but its callback, which sets
+/// its type.
+struct BuilderOptions {
+    ports: Option<Ports>,
+    udp_ports: Ports,
+    targets: Arc<[IpAddr]>,
+    target_ports: HashMap<IpAddr, (Ports, Ports)>,
+    names: BTreeMap<String, Vec<IpAddr>>,
+    samples: BTreeMap<IpNet, Vec<IpAddr>>,
+    unresolved: Vec<String>,
     timeout: u64,
+    ping_timeout: Option<u64>,
+    retries: u32,
+    banner: Option<BannerGrab>,
+    service_detection: Option<Duration>,
+    tls_probe: Option<Duration>,
+    http_probe: Option<Duration>,
+    hold: Option<Duration>,
+    vhost: Option<String>,
+    vhosts: HashMap<IpAddr, String>,
+    redactions: Vec<String>,
+    credentials: Credentials,
+    capture_dir: Option<PathBuf>,
+    stun_server: Option<SocketAddr>,
+    bind: Bind,
+    concurrency: Option<usize>,
+    batch_size: usize,
+    max_rate: Option<u32>,
+    discovery: bool,
+    traceroute: bool,
+    os_guess: bool,
+    mac_lookup: bool,
+    policy: Policy,
+    port_rules: PortRules,
+    simulated: Option<Vec<ScanEvent>>,
+    fake: Option<u64>,
+    resumed: Vec<ScanEvent>,
+    sinks: Vec<Box<dyn ResultSink>>,
+    enrichers: Vec<Arc<dyn Enricher>>,
+    on_checked_async: Option<AsyncCallback>,
+}
+
+impl Default for BuilderOptions {
+    fn default() -> Self {
+        Self {
+            ports: None,
+            udp_ports: Ports::default(),
+            targets: Arc::new([]),
+            target_ports: HashMap::new(),
+            names: BTreeMap::new(),
+            samples: BTreeMap::new(),
+            unresolved: vec![],
+            timeout: 1000,
+            ping_timeout: None,
+            retries: 0,
+            banner: None,
+            service_detection: None,
+            tls_probe: None,
+            http_probe: None,
+            hold: None,
+            vhost: None,
+            vhosts: HashMap::new(),
+            redactions: vec![],
+            credentials: Credentials::default(),
+            capture_dir: None,
+            stun_server: None,
+            bind: Bind::default(),
+            concurrency: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_rate: None,
+            discovery: true,
+            traceroute: false,
+            os_guess: false,
+            mac_lookup: false,
+            policy: Policy::default(),
+            port_rules: PortRules::default(),
+            simulated: None,
+            fake: None,
+            resumed: vec![],
+            sinks: vec![],
+            enrichers: vec![],
+            on_checked_async: None,
+        }
+    }
+}
+
+impl Default for PortScannerBuilder {
+    fn default() -> Self {
+        Self {
+            options: BuilderOptions::default(),
+            on_checked: |_, _, _| {},
+        }
+    }
+}
+
+impl<Callback> PortScannerBuilder<Callback>
+where
+    Callback: FnMut(IpAddr, u16, bool),
+{
+    /// Ports to check on each target.
+    pub fn ports(mut self, ports: Ports) -> Self {
+        self.options.ports = Some(ports);
+        self
+    }
+
+    /// UDP ports to probe on each target. Each is sent a request for the
+    /// service usually found there, e.g. a TFTP read request on 69, a STUN
+    /// binding request on 3478 or a Steam A2S_INFO query on 27015-27020, or an
+    /// empty datagram otherwise. Game servers are reported with their name,
+    /// map and players. Unanswered requests are sent again a couple of times
+    /// within the timeout, in case they were lost, and once more after
+    /// slowing down if the host reports other ports closed, in case it
+    /// rate-limits doing so. Ports that answer are open, anything else is
+    /// reported closed, since silent ports can't be told apart from filtered
+    /// ones. The exception is syslog on 514, which never answers: it's open
+    /// if the host reports other ports closed but not that one. Disabled by
+    /// default.
+    pub fn udp_ports(mut self, ports: Ports) -> Self {
+        self.options.udp_ports = ports;
+        self
+    }
+
+    /// IP addresses to scan.
+    pub fn targets(mut self, targets: impl Into<Arc<[IpAddr]>>) -> Self {
+        self.options.targets = targets.into();
+        self
+    }
+
+    /// Checks `ports` over `protocol` on `ip` on top of the ports every target
+    /// gets, e.g. the port a DNS SRV record publishes a service on. `ip`
+    /// needn't be among the targets, in which case only these ports are
+    /// checked on it.
+    pub fn target_ports(mut self, ip: IpAddr, protocol: Protocol, ports: Ports) -> Self {
+        let (tcp, udp) = self.options.target_ports.entry(ip).or_default();
+        match protocol {
+            Protocol::Udp => udp.extend(ports.iter()),
+            _ => tcp.extend(ports.iter()),
+        }
+
+        self
+    }
+
+    /// Groups `addrs`, the targets host `name` resolved to, under it in the
+    /// report, flagging where their results differ. Backends behind
+    /// round-robin DNS are meant to be alike, so differences are worth a
+    /// look.
+    pub fn target_name(
+        mut self,
+        name: impl Into<String>,
+        addrs: impl IntoIterator<Item = IpAddr>,
+    ) -> Self {
+        self.options
+            .names
+            .insert(name.into(), addrs.into_iter().collect());
+        self
+    }
+
+    /// Notes that `addrs`, among the targets, are a sample of `subnet`, e.g.
+    /// picked with [`sample_subnet`], and estimates in the report how many of
+    /// its hosts are up from how many of them are.
+    ///
+    /// [`sample_subnet`]: crate::sample_subnet
+    pub fn target_sample(mut self, subnet: IpNet, addrs: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.options
+            .samples
+            .insert(subnet, addrs.into_iter().collect());
+        self
+    }
+
+    /// Notes in the report that host `name` was to be scanned but couldn't be
+    /// resolved.
+    pub fn unresolved(mut self, name: impl Into<String>) -> Self {
+        self.options.unresolved.push(name.into());
+        self
+    }
+
+    /// Timeout (ms) when trying to connect to a port. Defaults to 1000.
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.options.timeout = timeout;
+        self
+    }
+
+    /// Timeout (ms) when pinging each host to see if it's up, with ICMP or,
+    /// without the rights to, by connecting to common ports. Defaults to 2000
+    /// for ICMP, and to the connect timeout for connecting.
+    pub fn ping_timeout(mut self, timeout: u64) -> Self {
+        self.options.ping_timeout = Some(timeout);
+        self
+    }
+
+    /// How many times to try connecting again to TCP ports which didn't
+    /// answer within the timeout, in case the packets were lost. Defaults to
+    /// 0.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.options.retries = retries;
+        self
+    }
+
+    /// Read up to `max_bytes` of whatever open ports send right after
+    /// connecting, waiting at most `wait_ms` for it. Disabled by default.
+    pub fn banner(mut self, max_bytes: usize, wait_ms: u64) -> Self {
+        self.options.banner = Some(BannerGrab {
+            max_bytes,
+            wait: Duration::from_millis(wait_ms),
+        });
+        self
+    }
+
+    /// Probe open ports of well-known services (HTTP, TLS, SSH, SMTP, ...) to
+    /// identify the service and its version, waiting at most `wait_ms` for
+    /// each response. Other open ports are identified by their greeting, or
+    /// failing that their answer to an HTTP request or TLS hello. Kerberos
+    /// and LDAP servers are also asked for the realm and naming context they
+    /// serve, to spot Active Directory domain controllers. Ports that can't
+    /// be identified are tried again with a PROXY protocol header. Disabled
+    /// by default.
+    pub fn service_detection(mut self, wait_ms: u64) -> Self {
+        self.options.service_detection = Some(Duration::from_millis(wait_ms));
+        self
+    }
+
+    /// Attempts a TLS handshake with every open port, whatever is usually
+    /// found there, recording the negotiated version and ALPN protocol and a
+    /// summary of the certificate. Waits at most `wait_ms` for each.
+    /// Handshakes are only completed in TLS 1.2 or 1.3, so servers which
+    /// only speak TLS 1.0 or 1.1 are recorded with their version alone.
+    pub fn tls_probe(mut self, wait_ms: u64) -> Self {
+        self.options.tls_probe = Some(Duration::from_millis(wait_ms));
+        self
+    }
+
+    /// Sends `GET /` to every open port, recording the status code, `Server`
+    /// header and page title of those that answer HTTP. Also checks for gRPC
+    /// and for `/healthz` and `/metrics` endpoints, labelling the service of
+    /// gRPC servers and Prometheus exporters. Waits at most `wait_ms` for each
+    /// request. Ports which completed a TLS probe get the request
+    /// over TLS, in HTTP/2 if that was negotiated.
+    pub fn http_probe(mut self, wait_ms: u64) -> Self {
+        self.options.http_probe = Some(Duration::from_millis(wait_ms));
+        self
+    }
+
+    /// Connects to every open port once more after probing it and holds the
+    /// connection for `hold_ms`, recording when ports hang up without
+    /// sending anything. Middleboxes which accept connections on behalf of
+    /// hosts, e.g. SYN proxies, drop them once the host doesn't answer,
+    /// whereas services keep them open or at least say something first.
+    /// Each port takes that much longer. Disabled by default.
+    pub fn hold(mut self, hold_ms: u64) -> Self {
+        self.options.hold = Some(Duration::from_millis(hold_ms));
+        self
+    }
+
+    /// Virtual host name TLS and HTTP probes ask for, as the server name (SNI)
+    /// and `Host` header, on targets without one of their own. By default
+    /// they use the address.
+    pub fn vhost(mut self, name: impl Into<String>) -> Self {
+        self.options.vhost = Some(name.into());
+        self
+    }
+
+    /// Same as [`vhost`](Self::vhost), only for `ip`.
+    pub fn target_vhost(mut self, ip: IpAddr, name: impl Into<String>) -> Self {
+        self.options.vhosts.insert(ip, name.into());
+        self
+    }
+
+    /// Reaches link-local IPv6 address `ip` through the interface with index
+    /// `scope_id`, as in `fe80::1%2`. Only one scope is kept per address.
+    pub fn target_scope(mut self, ip: Ipv6Addr, scope_id: u32) -> Self {
+        self.options.bind.scopes.insert(ip, scope_id);
+        self
+    }
+
+    /// Replaces matches of the regex `pattern` in banners, service versions,
+    /// certificate names and HTTP headers and titles with `[REDACTED]` before
+    /// they're passed on to anything, e.g. to keep internal host names out of
+    /// reports. Can be given multiple times.
+    pub fn redact(mut self, pattern: impl Into<String>) -> Self {
+        self.options.redactions.push(pattern.into());
+        self
+    }
+
+    /// Secrets probes authenticate with where they can. The secrets are also
+    /// redacted like with [`redact`](Self::redact).
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.options.credentials = credentials;
+        self
+    }
+
+    /// Writes a hexdump of the bytes banner grabbing, service detection and
+    /// UDP probes exchange with each port to a file of its own in `dir`, e.g.
+    /// `10.0.0.1-tcp-22.hexdump`, to troubleshoot misdetections or write new
+    /// fingerprints. The directory is created if needed.
+    pub fn capture_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.options.capture_dir = Some(dir.into());
+        self
+    }
+
+    /// Asks the STUN server at `addr` which address the scan comes from
+    /// before scanning, and records it in [`ScanReport::source`]. Behind NAT,
+    /// that's the address targets log and allowlists need to let through,
+    /// rather than the local one. Not asked by default.
+    pub fn stun_server(mut self, addr: SocketAddr) -> Self {
+        self.options.stun_server = Some(addr);
+        self
+    }
+
+    /// Connects, and pings, from `ip` rather than whichever address the
+    /// routing table picks, e.g. on hosts with several. Targets must all be of
+    /// the same IP version.
+    pub fn source_ip(mut self, ip: IpAddr) -> Self {
+        self.options.bind.ip = Some(ip);
+        self
+    }
+
+    /// Connects, and pings, through network interface `name`, e.g. `eth1`,
+    /// regardless of the routing table. Only supported on Linux.
+    pub fn interface(mut self, name: impl Into<String>) -> Self {
+        self.options.bind.interface = Some(name.into());
+        self
+    }
+
+    /// Checks TCP ports from source `port`, e.g. 53 or 20, to test firewall
+    /// rules letting traffic through by source port. Ports below 1024 need
+    /// privileges. The probes run on open ports connect from any port.
+    pub fn source_port(mut self, port: u16) -> Self {
+        self.options.bind.port = Some(port);
+        self
+    }
+
+    /// Sends IPv6 packets with flow label `label`, at most 20 bits, to test
+    /// how firewalls and load balancers treat it. Pings are sent without it.
+    /// Only supported on Linux.
+    pub fn flow_label(mut self, label: u32) -> Self {
+        self.options.bind.flow_label = Some(label);
+        self
+    }
+
+    /// Whether IPv6 packets carry an empty hop-by-hop options header, which
+    /// every router on the way has to look at and many firewalls drop. Pings
+    /// are sent without it. Needs `CAP_NET_RAW` and is only supported on
+    /// Linux. Disabled by default.
+    pub fn hop_by_hop_options(mut self, hop_by_hop: bool) -> Self {
+        self.options.bind.hop_by_hop = hop_by_hop;
+        self
+    }
+
+    /// Whether IPv6 packets carry an empty destination options header, which
+    /// only the target looks at. Pings are sent without it. Needs
+    /// `CAP_NET_RAW` and is only supported on Linux. Disabled by default.
+    pub fn destination_options(mut self, destination_options: bool) -> Self {
+        self.options.bind.destination_options = destination_options;
+        self
+    }
+
+    /// Maximum number of ports checked at once across all targets, shared
+    /// out fairly between hosts, so hosts with few ports to check aren't kept
+    /// waiting by ones with many. Unlimited by default, though never more
+    /// than the limit on open files allows.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.options.concurrency = Some(concurrency);
+        self
+    }
+
+    /// How many ports of each host to start checking at once, waiting for
+    /// them all before the next batch, so scanning every port doesn't take
+    /// a task for each up front. Defaults to 1024.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.options.batch_size = batch_size;
+        self
+    }
+
+    /// Maximum number of ports checked and hosts pinged per second across
+    /// all targets, on top of any rate the policy sets. Unlimited by default.
+    pub fn max_rate(mut self, rate: u32) -> Self {
+        self.options.max_rate = Some(rate);
+        self
+    }
+
+    /// Whether to ping targets first and skip those that don't respond.
+    /// Enabled by default. When disabled, all targets are assumed to be up.
+    /// Without the privileges to ping, a few common TCP ports are connected
+    /// to instead.
+    pub fn discovery(mut self, discovery: bool) -> Self {
+        self.options.discovery = discovery;
+        self
+    }
+
+    /// Whether to trace the route to hosts which don't respond or have no
+    /// open ports, recording in [`ScanReport::routes`] which routers the
+    /// packets to them got as far as. Needs the privileges to open raw ICMP
+    /// sockets. Disabled by default.
+    pub fn traceroute(mut self, traceroute: bool) -> Self {
+        self.options.traceroute = traceroute;
+        self
+    }
+
+    /// Whether to guess which OS family each host runs, from the TTL of its
+    /// ping replies and the SYN-ACK of the first open port connected to,
+    /// recording it in [`PortsStatus::os`]. Pings go over raw sockets where
+    /// allowed, as only they see the TTL. Reading SYN-ACKs needs Linux 6.2 or
+    /// later. Disabled by default.
+    pub fn os_guess(mut self, os_guess: bool) -> Self {
+        self.options.os_guess = os_guess;
+        self
+    }
+
+    /// Whether to look up the MAC address of hosts on the local network once
+    /// the scan is done, along with the vendor it was assigned to, recording
+    /// it in [`PortsStatus::mac`]. Pinging and connecting to hosts has the
+    /// kernel resolve them, so this only reads what it found. Vendors come
+    /// from the OUI database of nmap or Wireshark if either is installed, or
+    /// a list of common ones. Only supported on Linux. Disabled by default.
+    pub fn mac_lookup(mut self, mac_lookup: bool) -> Self {
+        self.options.mac_lookup = mac_lookup;
+        self
+    }
+
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.options.policy = policy;
+        self
+    }
+
+    /// Checks more TCP ports on hosts where `rules` match what's found open,
+    /// after the ports given.
+    pub fn port_rules(mut self, rules: PortRules) -> Self {
+        self.options.port_rules = rules;
+        self
+    }
+
+    /// Replays `events` instead of scanning, without touching the network.
+    /// Ports and targets aren't needed when simulating.
+    pub fn simulate(mut self, events: impl IntoIterator<Item = ScanEvent>) -> Self {
+        self.options.simulated = Some(events.into_iter().collect());
+        self
+    }
+
+    /// Carries on from an interrupted scan which already got `events`, e.g.
+    /// from [`CheckpointSink::open`]. Ports they cover aren't checked again,
+    /// and they're passed along with the new results, so the report covers
+    /// the whole scan.
+    ///
+    /// [`CheckpointSink::open`]: crate::CheckpointSink::open
+    pub fn resume(mut self, events: impl IntoIterator<Item = ScanEvent>) -> Self {
+        self.options.resumed.extend(events);
+        self
+    }
+
+    /// Generates plausible results for the ports and targets from `seed`
+    /// instead of scanning, without touching the network. The same seed always
+    /// gives the same results.
+    pub fn fake(mut self, seed: u64) -> Self {
+        self.options.fake = Some(seed);
+        self
+    }
+
+    /// Adds a sink which [`PortScanner::scan`] passes results to. Any number of
+    /// sinks can be added. They aren't used by [`PortScanner::scan_stream`].
+    pub fn sink(mut self, sink: impl ResultSink + 'static) -> Self {
+        self.options.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Adds an enricher which [`PortScanner::scan`] runs on every host that
+    /// responded once all ports have been checked, in the order added. They
+    /// aren't used by [`PortScanner::scan_stream`].
+    pub fn enricher(mut self, enricher: impl Enricher + 'static) -> Self {
+        self.options.enrichers.push(Arc::new(enricher));
+        self
+    }
+
+    /// Called as each port is checked.
+    pub fn on_checked<C>(self, on_checked: C) -> PortScannerBuilder<C>
+    where
+        C: FnMut(IpAddr, u16, bool),
+    {
+        PortScannerBuilder {
+            options: self.options,
+            on_checked,
+        }
+    }
+
+    /// Awaited as each port is checked, after `on_checked`, e.g. to store
+    /// results in a database. The next result isn't handled until the
+    /// returned future completes.
+    pub fn on_checked_async<F, Fut>(mut self, mut on_checked: F) -> Self
+    where
+        F: FnMut(IpAddr, u16, bool) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.options.on_checked_async = Some(Box::new(move |ip, port, open| {
+            Box::pin(on_checked(ip, port, open))
+        }));
+        self
+    }
+
+    pub fn build(self) -> io::Result<PortScanner<Callback>> {
+        self.options.build(self.on_checked)
+    }
 }
 
-impl<'a> ScannerInner<'a> {
-    fn new(ports: Ports, addrs: &'a [IpAddr], timeout: u64) -> io::Result<Self> {
-        let (pinger4, pinger6) = Self::create_pingers(addrs)?;
+impl BuilderOptions {
+    fn build<Callback>(self, on_checked: Callback) -> io::Result<PortScanner<Callback>>
+    where
+        Callback: FnMut(IpAddr, u16, bool),
+    {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let simulating = self.simulated.is_some();
+
+        let ports = self.ports.unwrap_or_default();
+        if ports.is_empty()
+            && self.udp_ports.is_empty()
+            && self.target_ports.is_empty()
+            && !simulating
+        {
+            return Err(invalid("no ports to scan"));
+        }
+
+        // a report of nothing but unresolved names is still worth having
+        if self.targets.is_empty()
+            && self.target_ports.is_empty()
+            && self.unresolved.is_empty()
+            && !simulating
+        {
+            return Err(invalid("no targets to scan"));
+        }
+
+        // addresses only given with their own ports are scanned for those alone
+        let extra_targets: HashSet<IpAddr> = self
+            .target_ports
+            .keys()
+            .filter(|ip| !self.targets.contains(ip))
+            .copied()
+            .collect();
+        let addrs: Arc<[IpAddr]> = self.targets.iter().chain(&extra_targets).copied().collect();
+
+        if let Some(source) = self.bind.ip {
+            if let Some(ip) = addrs.iter().find(|ip| ip.is_ipv4() != source.is_ipv4()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{ip} can't be reached from source address {source}"),
+                ));
+            }
+
+            // fails unless the address is one of ours
+            std::net::UdpSocket::bind((source, 0)).map_err(|e| {
+                io::Error::new(e.kind(), format!("invalid source address {source}: {e}"))
+            })?;
+        }
+
+        if let (Some(port), Some(&ip)) = (self.bind.port, addrs.first()) {
+            self.bind.tcp_socket(ip, port).map_err(|e| {
+                io::Error::new(e.kind(), format!("can't connect from port {port}: {e}"))
+            })?;
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        if self.bind.interface.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "binding to an interface is only supported on Linux",
+            ));
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        if self.bind.has_ipv6_options() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "IPv6 flow labels and extension headers are only supported on Linux",
+            ));
+        }
+
+        if self.bind.flow_label.is_some_and(|label| label > 0xfffff) {
+            return Err(invalid("flow label must be at most 20 bits"));
+        }
+
+        if self.bind.has_ipv6_options() {
+            if let Some(&ip) = addrs.iter().find(|ip| ip.is_ipv6()) {
+                self.bind.tcp_socket(ip, 0).map_err(|e| {
+                    io::Error::new(e.kind(), format!("can't set IPv6 options: {e}"))
+                })?;
+            }
+        }
+
+        if self.concurrency == Some(0) {
+            return Err(invalid("concurrency must be at least 1"));
+        }
+
+        if self.batch_size == 0 {
+            return Err(invalid("batch size must be at least 1"));
+        }
+
+        if self.max_rate == Some(0) {
+            return Err(invalid("rate must be at least 1"));
+        }
+
+        let simulated = match self.fake {
+            Some(seed) => Some(fake::events(seed, &ports, &self.targets)),
+            None => self.simulated,
+        };
+
+        let mut concurrency = self.concurrency;
+        let checks = addrs.len() * (ports.len() + self.udp_ports.len())
+            + self
+                .target_ports
+                .values()
+                .map(|(tcp, udp)| tcp.len() + udp.len())
+                .sum::<usize>();
+        if let (None, Some(max)) = (&simulated, exhaustion::max_concurrency()) {
+            // unlimited scans only need limiting if they'd go over
+            if concurrency.unwrap_or(checks) > max {
+                // once is enough when scanning again and again
+                static WARNED: Once = Once::new();
+                WARNED.call_once(|| {
+                    error!(
+                        "Checking at most {max} ports at once to stay within the limit on open \
+                         files. Raise it with ulimit -n to check more"
+                    )
+                });
+                concurrency = Some(max);
+            }
+        }
+
+        if let Some(&ip) = addrs
+            .first()
+            .filter(|_| self.traceroute && simulated.is_none())
+        {
+            probes::raw_socket(&self.bind, ip)
+                .map_err(|e| io::Error::new(e.kind(), format!("can't trace routes: {e}")))?;
+        }
+
+        let vhosts = self.vhost.iter().chain(self.vhosts.values());
+        if let Some(name) = vhosts
+            .into_iter()
+            .find(|name| !probes::is_valid_server_name(name))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid virtual host name: {name}"),
+            ));
+        }
+
+        if let Some(dir) = &self.capture_dir {
+            fs::create_dir_all(dir)?;
+        }
+
+        let secrets = self.credentials.secrets().map(regex::escape);
+        let redactions = self
+            .redactions
+            .iter()
+            .cloned()
+            .chain(secrets)
+            .map(|pattern| Regex::new(&pattern))
+            .collect::<Result<_, _>>()
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid redaction pattern: {e}"),
+                )
+            })?;
+
+        let (pinger4, pinger6) = if self.discovery && simulated.is_none() && !addrs.is_empty() {
+            // datagram "ping sockets" need no privileges where the system
+            // allows them (on Linux, for groups in net.ipv4.ping_group_range),
+            // and raw sockets are only tried if it doesn't, unless the TTLs
+            // only raw sockets see are wanted
+            let sock_type = if self.os_guess {
+                Type::RAW
+            } else {
+                Type::DGRAM
+            };
+            match ScannerInner::create_pingers(&addrs, &self.bind, sock_type) {
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    // once is enough when scanning again and again
+                    static WARNED: Once = Once::new();
+                    WARNED.call_once(|| {
+                        error!(
+                            "Not allowed to ping ({e}), connecting to ports {TCP_PING_PORTS:?} \
+                             instead to see which hosts are up. Pinging needs CAP_NET_RAW, or \
+                             unprivileged ICMP sockets allowed by net.ipv4.ping_group_range"
+                        )
+                    });
+                    (None, None)
+                }
+                pingers => pingers?,
+            }
+        } else {
+            (None, None)
+        };
 
-        Ok(Self {
+        let inner = ScannerInner {
             pinger4,
             pinger6,
             ports,
+            udp_ports: self.udp_ports,
+            target_ports: self.target_ports,
+            extra_targets,
             addrs,
-            timeout,
+            timeout: self.timeout,
+            ping_timeout: self.ping_timeout.map(Duration::from_millis),
+            retries: self.retries,
+            banner: self.banner,
+            service_detection: self.service_detection,
+            tls_probe: self.tls_probe,
+            http_probe: self.http_probe,
+            hold: self.hold,
+            vhost: self.vhost,
+            vhosts: self.vhosts,
+            redactions,
+            credentials: self.credentials,
+            capture_dir: self.capture_dir,
+            stun_server: self.stun_server,
+            bind: self.bind,
+            slots: concurrency.map(|total| Arc::new(ProbeSlots::new(total))),
+            sockets: SocketLimit::default(),
+            batch_size: self.batch_size,
+            max_rate: self.max_rate,
+            limiter: RateLimiter::default(),
+            discovery: self.discovery,
+            traceroute: self.traceroute,
+            os_guess: self.os_guess,
+            mac_lookup: self.mac_lookup,
+            policy: self.policy,
+            port_rules: self.port_rules,
+            routes: Mutex::default(),
+            fingerprints: Mutex::default(),
+            checked: self
+                .resumed
+                .iter()
+                .map(|event| (event.ip, event.protocol, event.port))
+                .collect(),
+            cancel: CancellationToken::new(),
+        };
+
+        Ok(PortScanner {
+            inner: Arc::new(inner),
+            channel: mpsc::channel(100),
+            simulated,
+            resumed: self.resumed,
+            names: self.names,
+            samples: self.samples,
+            unresolved: self.unresolved,
+            sinks: self.sinks,
+            enrichers: self.enrichers,
+            on_checked,
+            on_checked_async: self.on_checked_async,
         })
     }
+}
 
-    fn create_pingers(addrs: &'a [IpAddr]) -> io::Result<(Option<PingClient>, Option<PingClient>)> {
+struct ScannerInner {
+    pinger4: Option<PingClient>,
+    pinger6: Option<PingClient>,
+    ports: Ports,
+    udp_ports: Ports,
+    /// TCP and UDP ports checked on particular addresses.
+    target_ports: HashMap<IpAddr, (Ports, Ports)>,
+    /// Addresses only checked for their own ports.
+    extra_targets: HashSet<IpAddr>,
+    addrs: Arc<[IpAddr]>,
+    timeout: u64,
+    ping_timeout: Option<Duration>,
+    retries: u32,
+    banner: Option<BannerGrab>,
+    service_detection: Option<Duration>,
+    tls_probe: Option<Duration>,
+    http_probe: Option<Duration>,
+    hold: Option<Duration>,
+    vhost: Option<String>,
+    vhosts: HashMap<IpAddr, String>,
+    redactions: Vec<Regex>,
+    credentials: Credentials,
+    capture_dir: Option<PathBuf>,
+    stun_server: Option<SocketAddr>,
+    bind: Bind,
+    /// Ports checked at once, shared out between hosts.
+    slots: Option<Arc<ProbeSlots>>,
+    /// Ports being checked, limited once sockets run out.
+    sockets: SocketLimit,
+    /// Ports of each host started checking at once.
+    batch_size: usize,
+    /// Probes per second across all targets.
+    max_rate: Option<u32>,
+    limiter: RateLimiter,
+    discovery: bool,
+    traceroute: bool,
+    os_guess: bool,
+    mac_lookup: bool,
+    policy: Policy,
+    port_rules: PortRules,
+    /// Routes traced to hosts that didn't respond or had no open ports.
+    routes: Mutex<BTreeMap<IpAddr, Vec<Option<IpAddr>>>>,
+    /// What hosts' network stacks gave away, if guessing their OS.
+    fingerprints: Mutex<HashMap<IpAddr, Fingerprint>>,
+    /// Ports checked before the scan was resumed.
+    checked: HashSet<(IpAddr, Protocol, u16)>,
+    cancel: CancellationToken,
+}
+
+/// Ports of each host started checking at once, unless told otherwise.
+const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// How often to log how probe slots are shared out between hosts.
+const SLOT_STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long ICMP pings wait for a reply unless told otherwise.
+const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Ports connected to instead of pinging hosts when that isn't allowed. Any
+/// answer, even refusing the connection, shows the host is up.
+const TCP_PING_PORTS: [u16; 5] = [80, 443, 22, 445, 3389];
+
+/// How much of a service's banner to read after connecting.
+#[derive(Clone, Copy, Debug)]
+struct BannerGrab {
+    max_bytes: usize,
+    wait: Duration,
+}
+
+impl ScannerInner {
+    /// Asks the STUN server at `server` which address the scan comes from,
+    /// logging rather than failing if it doesn't say.
+    async fn source_address(&self, server: SocketAddr) -> Option<SourceAddress> {
+        let deadline = Instant::now() + Duration::from_millis(self.timeout);
+        match probes::source_address(&self.bind, server, deadline).await {
+            Ok(Some(source)) => {
+                trace!("Scanning from {source}");
+                Some(source)
+            }
+            Ok(None) => {
+                error!("STUN server {server} didn't say which address the scan comes from");
+                None
+            }
+            Err(e) => {
+                error!("Failed to ask STUN server {server} for the scan's address: {e}");
+                None
+            }
+        }
+    }
+
+    fn create_pingers(
+        addrs: &[IpAddr],
+        bind: &Bind,
+        sock_type: Type,
+    ) -> io::Result<(Option<PingClient>, Option<PingClient>)> {
+        // only for the versions needed, as a source address is only good for one
         let pinger4 = addrs
             .iter()
             .any(IpAddr::is_ipv4)
-            .then_some(Self::create_pinger(ICMP::V4)?);
+            .then(|| PingClient::new(&bind.ping_config(ICMP::V4, sock_type)))
+            .transpose()?;
 
         let pinger6 = addrs
             .iter()
             .any(IpAddr::is_ipv6)
-            .then_some(Self::create_pinger(ICMP::V6)?);
+            .then(|| PingClient::new(&bind.ping_config(ICMP::V6, sock_type)))
+            .transpose()?;
 
-        if pinger4.is_none() && pinger6.is_none() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+        let Some(client) = pinger4.as_ref().or(pinger6.as_ref()) else {
+            return Err(io::Error::other(
                 "tried to create port scanner with no supported IP versions",
             ));
+        };
+
+        match client.get_socket().get_type() {
+            Type::DGRAM => trace!("Pinging with unprivileged ICMP sockets"),
+            _ => trace!("Pinging with raw sockets"),
         }
 
         Ok((pinger4, pinger6))
     }
 
-    fn create_pinger(version: ICMP) -> io::Result<PingClient> {
-        let config = PingConfig::builder().kind(version).build();
-        PingClient::new(&config)
+    /// The TCP and UDP ports to check on `ip`.
+    fn ports_for(&self, ip: IpAddr) -> (Ports, Ports) {
+        let (mut ports, mut udp_ports) = if self.extra_targets.contains(&ip) {
+            (Ports::default(), Ports::default())
+        } else {
+            (self.ports.clone(), self.udp_ports.clone())
+        };
+
+        if let Some((tcp, udp)) = self.target_ports.get(&ip) {
+            ports.extend(tcp.iter());
+            udp_ports.extend(udp.iter());
+        }
+
+        (ports, udp_ports)
     }
 
-    async fn scan_ip(&self, ip: &'static IpAddr, tx: PortSender<'a>, id: u16) {
-        let Some(rtt) = self.ping(ip, id).await else {
-            trace!("{ip} isn't responding");
-            return;
+    async fn scan_ip(self: Arc<Self>, ip: IpAddr, tx: PortSender, id: u16) {
+        let (ports, udp_ports) = self.ports_for(ip);
+        let any_unchecked = |protocol, ports: &Ports| {
+            ports
+                .iter()
+                .any(|port| !self.checked.contains(&(ip, protocol, port)))
         };
 
-        trace!("{ip} is responding, pinged in {}ms", rtt.as_millis());
-        trace!("Checking {} ports on {ip}...", self.ports.len());
+        if !any_unchecked(Protocol::Tcp, &ports) && !any_unchecked(Protocol::Udp, &udp_ports) {
+            trace!("Every port on {ip} was checked before resuming");
+            return;
+        }
+
+        let mut rtt = None;
+        if !self.discovery {
+            trace!("Not pinging {ip}, assuming it's up");
+        } else if self.policy.allows_protocol(&ip, Protocol::Icmp) {
+            let ping = tokio::select! {
+                _ = self.cancel.cancelled() => return,
+                ping = async {
+                    self.throttle(&ip).await;
+                    self.ping(&ip, id).await
+                } => ping,
+            };
+
+            let Some(ping) = ping else {
+                trace!("{ip} isn't responding");
+                self.trace_route(ip, id).await;
+                return;
+            };
+
+            trace!("{ip} is responding, pinged in {}ms", ping.as_millis());
+            rtt = Some(ping.as_micros() as u64);
+        } else {
+            trace!("Policy forbids pinging {ip}, assuming it's up");
+        }
+
+        let pacer = Arc::new(IcmpPacer::new(ip));
+        let mut pending = vec![];
+        for (protocol, ports) in [(Protocol::Tcp, &ports), (Protocol::Udp, &udp_ports)] {
+            if !ports.is_empty() {
+                pending.extend(self.ports_to_check(ip, protocol, ports));
+            }
+        }
+
+        // rules can add ports after each round, but never ones already checked
+        let mut scanned = ports;
+        let mut any_open = false;
+        while !pending.is_empty() {
+            let mut triggered = Ports::default();
+            let host_ports = pending.len();
+            // spawning a task for every port at once would take a lot of
+            // memory on large port ranges for little gain
+            for batch in pending.chunks(self.batch_size) {
+                for h in self.check_ports(ip, batch, host_ports, &pacer) {
+                    let mut event = match h.await {
+                        Ok(Some(event)) => event,
+                        Ok(None) => continue,
+                        // the probe panicked, which leaves that port out
+                        Err(e) => {
+                            error!("Checking a port on {ip} failed: {e}");
+                            continue;
+                        }
+                    };
+
+                    event.rtt = rtt;
+
+                    any_open |= event.open;
+
+                    for ports in self.port_rules.triggered_by(&event) {
+                        triggered.extend(ports.iter());
+                    }
+
+                    // the receiver is gone if whoever was consuming the results stopped
+                    if tx.send(event).await.is_err() {
+                        self.cancel.cancel();
+                        return;
+                    }
+                }
+            }
+
+            pending = vec![];
+            triggered.exclude(&scanned);
+            if !triggered.is_empty() {
+                trace!(
+                    "Rules matched on {ip}, checking {} more ports",
+                    triggered.len()
+                );
+                scanned.extend(triggered.iter());
+                pending = self.ports_to_check(ip, Protocol::Tcp, &triggered);
+            }
+        }
+
+        if !any_open && !self.cancel.is_cancelled() {
+            self.trace_route(ip, id).await;
+        }
+    }
+
+    /// Records how the host at the other end of `stream` answered the
+    /// handshake, if guessing OSes and it's the first port connected to.
+    fn fingerprint_handshake(&self, ip: IpAddr, stream: &TcpStream) {
+        if !self.os_guess {
+            return;
+        }
+
+        let mut fingerprints = self.fingerprints.lock().unwrap();
+        let fingerprint = fingerprints.entry(ip).or_default();
+        if fingerprint.tcp.is_none() {
+            fingerprint.tcp = os::handshake(stream);
+        }
+    }
+
+    /// Waits until a probe to `ip` is allowed by the overall rate limit and
+    /// the policy.
+    async fn throttle(&self, ip: &IpAddr) {
+        if let Some(rate) = self.max_rate {
+            self.limiter.wait(rate).await;
+        }
 
-        let mut handles = Vec::with_capacity(self.ports.len());
-        for &port in &*self.ports {
-            let timeout = self.timeout;
+        self.policy.throttle(ip).await;
+    }
+
+    /// Traces the route to `ip` if enabled and the policy allows pinging it,
+    /// to show where packets to it are lost.
+    async fn trace_route(&self, ip: IpAddr, id: u16) {
+        if !self.traceroute || !self.policy.allows_protocol(&ip, Protocol::Icmp) {
+            return;
+        }
+
+        let wait = Duration::from_millis(self.timeout);
+        match probes::trace(&self.bind, ip, id, wait).await {
+            Ok(hops) => {
+                trace!("Route to {ip}: {}", probes::format_hops(&hops));
+                self.routes.lock().unwrap().insert(ip, hops);
+            }
+            Err(e) => error!("Failed to trace the route to {ip}: {e}"),
+        }
+    }
+
+    /// Which of `ports` to check over `protocol` on `ip`, leaving out any the
+    /// policy forbids or that were checked before resuming.
+    fn ports_to_check(
+        &self,
+        ip: IpAddr,
+        protocol: Protocol,
+        ports: &Ports,
+    ) -> Vec<(Protocol, u16)> {
+        if !self.policy.allows_protocol(&ip, protocol) {
+            trace!("Policy forbids {protocol} on {ip}, skipping its {protocol} ports");
+            return vec![];
+        }
+
+        let allowed = |port: &u16| self.policy.allows_port(&ip, *port);
+        let num_ports = ports.iter().filter(allowed).count();
+
+        if num_ports < ports.len() {
+            trace!(
+                "Policy forbids {} {protocol} ports on {ip}, skipping them",
+                ports.len() - num_ports
+            );
+        }
+
+        let unchecked = |port: &u16| !self.checked.contains(&(ip, protocol, *port));
+        let checks: Vec<_> = ports
+            .iter()
+            .filter(allowed)
+            .filter(unchecked)
+            .map(|port| (protocol, port))
+            .collect();
+        trace!("Checking {} {protocol} ports on {ip}...", checks.len());
+        checks
+    }
+
+    /// Starts checking `batch` of the `host_ports` ports being checked on
+    /// `ip`.
+    fn check_ports(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        batch: &[(Protocol, u16)],
+        host_ports: usize,
+        pacer: &Arc<IcmpPacer>,
+    ) -> Vec<JoinHandle<Option<ScanEvent>>> {
+        let mut handles = Vec::with_capacity(batch.len());
+        for &(protocol, port) in batch {
+            let inner = Arc::clone(self);
+            let pacer = Arc::clone(pacer);
             handles.push(tokio::spawn(async move {
-                Self::check_port(ip, port, timeout).await
+                tokio::select! {
+                    _ = inner.cancel.cancelled() => None,
+                    res = async {
+                        let _slot = match &inner.slots {
+                            Some(slots) => Some(slots.acquire(ip, host_ports).await),
+                            None => None,
+                        };
+
+                        let _permit = inner.sockets.acquire().await;
+                        inner.throttle(&ip).await;
+                        match protocol {
+                            Protocol::Udp => inner.check_udp_port(ip, port, &pacer).await,
+                            _ => inner.check_port(ip, port).await,
+                        }
+                    } => res,
+                }
             }));
         }
 
-        for h in handles {
-            let (port, open) = h.await.unwrap();
-            tx.send((ip, port, open)).await.unwrap();
+        handles
+    }
+
+    /// Connects to a TCP port, probing it if it's open. Ports that don't
+    /// answer are tried again as many times as retries allow. Ports that
+    /// can't be checked for lack of sockets are retried after backing off,
+    /// and left out if they never can be.
+    async fn check_port(&self, ip: IpAddr, port: u16) -> Option<ScanEvent> {
+        let mut attempt = 0;
+        let mut retried = 0;
+        let (time, started, res) = loop {
+            let time = SystemTime::now();
+            let started = Instant::now();
+            let res = timeout(
+                Duration::from_millis(self.timeout),
+                self.bind.check(ip, port),
+            )
+            .await;
+
+            match &res {
+                Ok(Err(e)) if exhaustion::is_exhaustion(e) => {
+                    let backoff = self.exhausted(ip, port, attempt, e)?;
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                    continue;
+                }
+                Err(_) if retried < self.retries => {
+                    trace!("{ip}:{port} didn't answer, trying again");
+                    retried += 1;
+                    self.throttle(&ip).await;
+                    continue;
+                }
+                _ => {}
+            }
+
+            break (time, started, res);
+        };
+
+        let mut event = ScanEvent {
+            ip,
+            port,
+            protocol: Protocol::Tcp,
+            open: false,
+            filtered: false,
+            time,
+            banner: None,
+            service: None,
+            tls: None,
+            http: None,
+            dropped: None,
+            latency: None,
+            rtt: None,
+        };
+
+        match res {
+            Ok(Ok(mut stream)) => {
+                let latency = started.elapsed();
+                trace!("{ip}:{port} is open, connected in {latency:?}");
+                event.open = true;
+                event.latency = Some(latency.as_micros() as u64);
+                self.fingerprint_handshake(ip, &stream);
+                let mut transcript = Transcript::default();
+                self.probe_open_port(&mut stream, &mut event, &mut transcript)
+                    .await;
+
+                // the probes below need connections of their own, and some
+                // servers only handle one at a time
+                drop(stream);
+                self.probe_proxy_protocol(&mut event, &mut transcript).await;
+                self.save_transcript(&event, &transcript);
+                self.probe_tls(&mut event).await;
+                self.probe_http(&mut event).await;
+                self.hold_connection(&mut event).await;
+            }
+            Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                trace!(
+                    "{ip}:{port} refused the connection in {:?}",
+                    started.elapsed()
+                );
+            }
+            Ok(Err(e)) => error!("Unexpected error: {e:#?}"),
+            Err(_) => {
+                trace!("{ip}:{port} didn't answer within {}ms", self.timeout);
+                event.filtered = true;
+            }
+        }
+
+        Some(event)
+    }
+
+    /// Notes that checking `port` on `ip` failed for lack of sockets,
+    /// returning how long to back off before retrying it, if at all.
+    fn exhausted(&self, ip: IpAddr, port: u16, attempt: u32, e: &io::Error) -> Option<Duration> {
+        let backoff = self.sockets.exhausted(attempt, e);
+        match backoff {
+            Some(backoff) => trace!(
+                "Out of sockets checking {ip}:{port}, retrying in {}ms",
+                backoff.as_millis()
+            ),
+            None => error!("Out of sockets checking {ip}:{port} after every retry, leaving it out"),
+        }
+
+        backoff
+    }
+
+    /// Sends a request to a UDP port, identifying the service if it answers.
+    /// Silent ports are probed again more slowly if the host seems to
+    /// rate-limit reporting closed ones.
+    async fn check_udp_port(&self, ip: IpAddr, port: u16, pacer: &IcmpPacer) -> Option<ScanEvent> {
+        let time = SystemTime::now();
+        let mut event = ScanEvent {
+            ip,
+            port,
+            protocol: Protocol::Udp,
+            open: false,
+            filtered: false,
+            time,
+            banner: None,
+            service: None,
+            tls: None,
+            http: None,
+            dropped: None,
+            latency: None,
+            rtt: None,
+        };
+
+        let mut transcript = Transcript::default();
+        let mut retried = false;
+        let mut attempt = 0;
+        let exchanged = loop {
+            pacer.wait().await;
+            let deadline = Instant::now() + Duration::from_millis(self.timeout);
+            match probes::exchange_udp(&self.bind, ip, port, deadline, &mut transcript).await {
+                Err(e) if exhaustion::is_exhaustion(&e) => {
+                    let backoff = self.exhausted(ip, port, attempt, &e)?;
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    pacer.refused();
+                    if retried {
+                        pacer.missed();
+                    }
+
+                    break Err(e);
+                }
+                Ok(None) if !retried && probes::can_retransmit_udp(port) && pacer.silent() => {
+                    trace!("UDP {ip}:{port} was silent, probing it again more slowly");
+                    retried = true;
+                }
+                exchanged => break exchanged,
+            }
+        };
+
+        self.save_transcript(&event, &transcript);
+        let service = match exchanged {
+            Ok(Some(answer)) => {
+                event.open = true;
+                answer.service()
+            }
+            Ok(None) => {
+                pacer.wait().await;
+                let deadline = Instant::now() + Duration::from_millis(self.timeout);
+                match probes::identify_silent_udp(&self.bind, ip, port, deadline).await {
+                    Ok(service) => {
+                        event.open = service.is_some();
+                        service
+                    }
+                    Err(e) => {
+                        trace!("Probing UDP {ip}:{port} failed: {e}");
+                        None
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => None,
+            Err(e) => {
+                trace!("Probing UDP {ip}:{port} failed: {e}");
+                None
+            }
+        };
+
+        if let Some(service) = service {
+            self.identified(&mut event, service);
+        }
+
+        Some(event)
+    }
+
+    /// Runs the enabled follow-up probes against a port that accepted a
+    /// connection, recording what they find in `event`.
+    async fn probe_open_port(
+        &self,
+        stream: &mut TcpStream,
+        event: &mut ScanEvent,
+        transcript: &mut Transcript,
+    ) {
+        let (ip, port) = (event.ip, event.port);
+        let kind = self
+            .service_detection
+            .map(|_| ProbeKind::for_any_port(port));
+
+        let greeting = match (self.banner, kind) {
+            (Some(grab), _) => {
+                probes::read(stream, grab.max_bytes, Instant::now() + grab.wait).await
+            }
+            (None, Some(kind)) if kind.talks_first() => {
+                let wait = self.service_detection.unwrap();
+                probes::read_some(stream, Instant::now() + wait).await
+            }
+            _ => Ok(vec![]),
+        }
+        .unwrap_or_default();
+        transcript.received(&greeting);
+
+        if self.banner.is_some() {
+            let banner = self.redact(String::from_utf8_lossy(&greeting).trim_end());
+            if !banner.is_empty() {
+                trace!("{ip}:{port} banner: {}", banner.escape_debug());
+                event.banner = Some(banner);
+            }
+        }
+
+        if let (Some(wait), Some(kind)) = (self.service_detection, kind) {
+            let deadline = Instant::now() + wait;
+            match probes::detect(
+                &self.bind, stream, ip, kind, &greeting, deadline, transcript,
+            )
+            .await
+            {
+                Ok(Some(service)) => self.identified(event, service),
+                Ok(None) => trace!("Couldn't identify the service on {ip}:{port}"),
+                Err(e) => trace!("Service detection on {ip}:{port} failed: {e}"),
+            }
+        }
+    }
+
+    /// Retries service detection with a PROXY protocol header on ports it
+    /// couldn't identify, since services expecting one drop connections
+    /// without it.
+    async fn probe_proxy_protocol(&self, event: &mut ScanEvent, transcript: &mut Transcript) {
+        let (ip, port) = (event.ip, event.port);
+        let (Some(wait), Some(kind)) = (self.service_detection, ProbeKind::for_port(port)) else {
+            return;
+        };
+
+        if event.service.is_some() {
+            return;
+        }
+
+        let deadline = Instant::now() + wait;
+        match probes::detect_behind_proxy(&self.bind, ip, port, kind, deadline, transcript).await {
+            Ok(Some(service)) => self.identified(event, service),
+            Ok(None) => {}
+            Err(e) => trace!("Service detection with PROXY protocol on {ip}:{port} failed: {e}"),
+        }
+    }
+
+    async fn probe_tls(&self, event: &mut ScanEvent) {
+        let (ip, port) = (event.ip, event.port);
+        if let Some(wait) = self.tls_probe {
+            let vhost = self.vhost_for(&ip);
+            match probes::handshake(&self.bind, ip, port, vhost, Instant::now() + wait).await {
+                Ok(mut tls) => {
+                    tls.common_name = tls.common_name.map(|name| self.redact(&name));
+                    for name in &mut tls.alt_names {
+                        *name = self.redact(name);
+                    }
+
+                    trace!("{ip}:{port} speaks {tls}");
+                    event.tls = Some(tls);
+                }
+                Err(e) => trace!("TLS handshake with {ip}:{port} failed: {e}"),
+            }
+        }
+    }
+
+    /// Connects to an open port and waits until the hold time is up for it
+    /// to hang up, recording when it did unless it sent something first.
+    async fn hold_connection(&self, event: &mut ScanEvent) {
+        let (ip, port) = (event.ip, event.port);
+        let Some(hold) = self.hold else {
+            return;
+        };
+
+        let start = Instant::now();
+        let deadline = start + hold;
+        let mut stream = match timeout_at(deadline, self.bind.connect(ip, port)).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => {
+                trace!("Connecting to {ip}:{port} again to hold it failed: {e}");
+                return;
+            }
+            Err(_) => return,
+        };
+
+        let mut buf = [0; 1024];
+        match timeout_at(deadline, stream.read(&mut buf)).await {
+            // something's there, whether or not it hangs up after
+            Ok(Ok(len)) if len > 0 => return,
+            Ok(_) => {}
+            Err(_) => return,
+        }
+
+        let millis = start.elapsed().as_millis() as u64;
+        trace!("{ip}:{port} hung up after {millis}ms without a word");
+        event.dropped = Some(millis);
+    }
+
+    async fn probe_http(&self, event: &mut ScanEvent) {
+        let (ip, port) = (event.ip, event.port);
+        if let Some(wait) = self.http_probe {
+            let authorization = self.credentials.http_authorization(&ip, port);
+            let site = probes::Site {
+                ip,
+                port,
+                vhost: self.vhost_for(&ip),
+                tls: event.tls.as_ref(),
+                authorization: authorization.as_deref(),
+                wait,
+                bind: &self.bind,
+            };
+
+            match site.probe().await {
+                Ok(Some(mut http)) => {
+                    if let Some(tls) = event.tls.as_mut().filter(|_| http.offers_h3()) {
+                        tls.protocols.push("h3".to_string());
+                    }
+
+                    if let Some(api) = &http.api {
+                        event.service = Some(Service {
+                            name: api.clone(),
+                            version: None,
+                            details: None,
+                        });
+                    }
+
+                    http.server = http.server.map(|server| self.redact(&server));
+                    http.title = http.title.map(|title| self.redact(&title));
+                    http.alt_svc = http.alt_svc.map(|alt_svc| self.redact(&alt_svc));
+                    trace!("{ip}:{port} answered {http}");
+                    event.http = Some(http);
+                }
+                Ok(None) => trace!("{ip}:{port} didn't answer HTTP"),
+                Err(e) => trace!("HTTP probe of {ip}:{port} failed: {e}"),
+            }
+        }
+    }
+
+    /// Writes `transcript` to the capture directory, if there is one.
+    fn save_transcript(&self, event: &ScanEvent, transcript: &Transcript) {
+        let Some(dir) = &self.capture_dir else {
+            return;
+        };
+
+        if transcript.is_empty() {
+            return;
+        }
+
+        // colons in IPv6 addresses aren't allowed in file names everywhere
+        let ip = event.ip.to_string().replace(':', "_");
+        let path = dir.join(format!("{ip}-{}-{}.hexdump", event.protocol, event.port));
+        if let Err(e) = fs::write(&path, transcript.to_string()) {
+            error!("Failed to write capture to {}: {e}", path.display());
         }
     }
 
-    async fn check_port(ip: &'static IpAddr, port: u16, timeout_ms: u64) -> (u16, bool) {
-        let res = timeout(
-            Duration::from_millis(timeout_ms),
-            TcpStream::connect((*ip, port)),
-        )
-        .await;
+    fn vhost_for(&self, ip: &IpAddr) -> Option<&str> {
+        self.vhosts
+            .get(ip)
+            .or(self.vhost.as_ref())
+            .map(String::as_str)
+    }
 
-        if let Ok(Err(e)) = &res {
-            error!("Unexpected error: {e:#?}");
+    /// Records `service` as running on `event`'s port, redacting its version
+    /// and details.
+    fn identified(&self, event: &mut ScanEvent, mut service: Service) {
+        service.version = service.version.map(|version| self.redact(&version));
+        service.details = service.details.map(|details| self.redact(&details));
+        let (ip, port) = (event.ip, event.port);
+        match event.protocol {
+            Protocol::Udp => trace!("UDP {ip}:{port} is running {service}"),
+            _ => trace!("{ip}:{port} is running {service}"),
         }
+        event.service = Some(service);
+    }
 
-        (port, res.is_ok())
+    fn redact(&self, text: &str) -> String {
+        self.redactions
+            .iter()
+            .fold(text.to_string(), |text, regex| {
+                regex.replace_all(&text, "[REDACTED]").into_owned()
+            })
     }
 
     async fn ping(&self, ip: &IpAddr, id: u16) -> Option<Duration> {
-        let mut pinger = match ip {
+        let client = match ip {
             IpAddr::V4(_) => self.pinger4.as_ref(),
             IpAddr::V6(_) => self.pinger6.as_ref(),
+        };
+
+        let Some(client) = client else {
+            return self.tcp_ping(*ip).await;
+        };
+
+        let mut pinger = client.pinger(*ip, PingIdentifier(id)).await;
+
+        if let IpAddr::V6(ip) = ip {
+            pinger.scope_id(self.bind.scope_id(*ip));
         }
-        .unwrap()
-        .pinger(*ip, PingIdentifier(id))
-        .await;
 
         trace!("Pinging {ip}...");
 
+        // the pinger times out on its own too, but only once the request is
+        // sent, which may itself stall
+        let wait = self.ping_timeout.unwrap_or(DEFAULT_PING_TIMEOUT);
+        pinger.timeout(wait);
         let payload = [0; 56];
-        pinger
-            .ping(PingSequence(0), &payload)
+        let (reply, rtt) = timeout(wait, pinger.ping(PingSequence(0), &payload))
             .await
-            .map(|(_, rtt)| rtt)
-            .ok()
+            .ok()?
+            .ok()?;
+
+        // datagram sockets get ICMP without the IP header holding the TTL
+        if let IcmpPacket::V4(reply) = &reply {
+            if let Some(ttl) = reply.get_ttl().filter(|_| self.os_guess) {
+                self.fingerprints
+                    .lock()
+                    .unwrap()
+                    .entry(*ip)
+                    .or_default()
+                    .ttl = Some(ttl);
+            }
+        }
+
+        Some(rtt)
+    }
+
+    /// Connects to the [`TCP_PING_PORTS`] the policy allows on `ip` at once,
+    /// returning how long the first answer took. Hosts with none allowed are
+    /// assumed to be up.
+    async fn tcp_ping(&self, ip: IpAddr) -> Option<Duration> {
+        let start = Instant::now();
+        let wait = self
+            .ping_timeout
+            .unwrap_or(Duration::from_millis(self.timeout));
+        let deadline = start + wait;
+        let mut attempts = JoinSet::new();
+        for port in TCP_PING_PORTS {
+            if self.policy.allows_protocol(&ip, Protocol::Tcp) && self.policy.allows_port(&ip, port)
+            {
+                let bind = self.bind.clone();
+                attempts.spawn(timeout_at(
+                    deadline,
+                    async move { bind.connect(ip, port).await },
+                ));
+            }
+        }
+
+        if attempts.is_empty() {
+            trace!("Policy forbids connecting to {ip} to see if it's up, assuming it is");
+            return Some(Duration::ZERO);
+        }
+
+        trace!("Connecting to {ip} to see if it's up...");
+        while let Some(attempt) = attempts.join_next().await {
+            match attempt.unwrap() {
+                Ok(Ok(_)) => return Some(start.elapsed()),
+                Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    return Some(start.elapsed())
+                }
+                _ => {}
+            }
+        }
+
+        None
     }
 }
 
-type PortSender<'a> = mpsc::Sender<(&'a IpAddr, u16, bool)>;
-type PortReceiver<'a> = mpsc::Receiver<(&'a IpAddr, u16, bool)>;
+type PortSender = mpsc::Sender<ScanEvent>;
+type PortReceiver = mpsc::Receiver<ScanEvent>;
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    async fn check(port: u16) -> Vec<ScanEvent> {
+        PortScanner::builder()
+            .ports(port.to_string().parse().unwrap())
+            .targets(vec![LOCALHOST])
+            .discovery(false)
+            .build()
+            .unwrap()
+            .scan_stream()
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn counts_accepted_connections_as_open() {
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let events = check(port).await;
+        assert_eq!(events.len(), 1);
+        assert!(events[0].open);
+    }
+
+    #[tokio::test]
+    async fn counts_refused_connections_as_closed() {
+        // nothing listens on a port just given back
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let events = check(port).await;
+        assert_eq!(events.len(), 1);
+        assert!(!events[0].open);
+    }
+}
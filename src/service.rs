@@ -0,0 +1,89 @@
+//! The scanner as a [`tower_service::Service`], so tower middleware like rate
+//! limits, retries, timeouts and metrics can be wrapped around scans, e.g. in
+//! an axum app.
+
+use std::{
+    future::Future,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tower_service::Service;
+
+use crate::{
+    ports::Ports,
+    scanner::{PortScanner, PortScannerBuilder, ScanReport},
+};
+
+/// What to scan, sent to a [`ScanService`].
+#[derive(Clone, Debug, Default)]
+pub struct ScanRequest {
+    pub targets: Vec<IpAddr>,
+    pub ports: Ports,
+    pub udp_ports: Ports,
+}
+
+/// Scans the targets of each [`ScanRequest`], with every other setting
+/// taken from a builder made for each one, e.g.
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// use qapper::{PortScanner, ScanRequest, ScanService};
+/// use tower_service::Service;
+///
+/// let mut service = ScanService::new(|| PortScanner::builder().timeout(500));
+/// let report = service
+///     .call(ScanRequest {
+///         targets: vec!["127.0.0.1".parse().unwrap()],
+///         ports: "22,80,443".parse().unwrap(),
+///         ..Default::default()
+///     })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// It's always ready, leaving how many scans run at once to middleware.
+#[derive(Clone)]
+pub struct ScanService {
+    builder: Arc<dyn Fn() -> PortScannerBuilder + Send + Sync>,
+}
+
+impl ScanService {
+    /// Creates a service scanning with builders from `builder`, which
+    /// shouldn't set ports or targets.
+    pub fn new(builder: impl Fn() -> PortScannerBuilder + Send + Sync + 'static) -> Self {
+        Self {
+            builder: Arc::new(builder),
+        }
+    }
+}
+
+impl Default for ScanService {
+    fn default() -> Self {
+        Self::new(PortScanner::builder)
+    }
+}
+
+impl Service<ScanRequest> for ScanService {
+    type Response = ScanReport;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<ScanReport>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: ScanRequest) -> Self::Future {
+        let scanner = (self.builder)()
+            .ports(request.ports)
+            .udp_ports(request.udp_ports)
+            .targets(request.targets)
+            .build();
+
+        Box::pin(async move { scanner?.scan().await })
+    }
+}
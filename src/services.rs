@@ -0,0 +1,170 @@
+//! Well-known TCP service names from the IANA service name and port number
+//! registry, limited to services that are commonly seen in the wild.
+
+/// Sorted by port so it can be binary searched.
+const SERVICES: &[(u16, &str)] = &[
+    (7, "echo"),
+    (9, "discard"),
+    (13, "daytime"),
+    (17, "qotd"),
+    (19, "chargen"),
+    (20, "ftp-data"),
+    (21, "ftp"),
+    (22, "ssh"),
+    (23, "telnet"),
+    (25, "smtp"),
+    (37, "time"),
+    (43, "whois"),
+    (49, "tacacs"),
+    (53, "domain"),
+    (70, "gopher"),
+    (79, "finger"),
+    (80, "http"),
+    (88, "kerberos"),
+    (102, "iso-tsap"),
+    (110, "pop3"),
+    (111, "sunrpc"),
+    (113, "ident"),
+    (119, "nntp"),
+    (123, "ntp"),
+    (135, "msrpc"),
+    (137, "netbios-ns"),
+    (138, "netbios-dgm"),
+    (139, "netbios-ssn"),
+    (143, "imap"),
+    (161, "snmp"),
+    (162, "snmptrap"),
+    (179, "bgp"),
+    (194, "irc"),
+    (389, "ldap"),
+    (427, "svrloc"),
+    (443, "https"),
+    (444, "snpp"),
+    (445, "microsoft-ds"),
+    (464, "kpasswd"),
+    (465, "submissions"),
+    (500, "isakmp"),
+    (512, "exec"),
+    (513, "login"),
+    (514, "shell"),
+    (515, "printer"),
+    (543, "klogin"),
+    (544, "kshell"),
+    (548, "afp"),
+    (554, "rtsp"),
+    (587, "submission"),
+    (593, "http-rpc-epmap"),
+    (623, "asf-rmcp"),
+    (631, "ipp"),
+    (636, "ldaps"),
+    (646, "ldp"),
+    (873, "rsync"),
+    (902, "vmware-auth"),
+    (989, "ftps-data"),
+    (990, "ftps"),
+    (992, "telnets"),
+    (993, "imaps"),
+    (995, "pop3s"),
+    (1080, "socks"),
+    (1194, "openvpn"),
+    (1433, "ms-sql-s"),
+    (1434, "ms-sql-m"),
+    (1521, "oracle"),
+    (1723, "pptp"),
+    (1883, "mqtt"),
+    (2049, "nfs"),
+    (2082, "cpanel"),
+    (2083, "cpanels"),
+    (2181, "zookeeper"),
+    (2375, "docker"),
+    (2376, "docker-s"),
+    (2379, "etcd-client"),
+    (2380, "etcd-server"),
+    (3000, "hbci"),
+    (3128, "squid-http"),
+    (3268, "msft-gc"),
+    (3269, "msft-gc-ssl"),
+    (3306, "mysql"),
+    (3389, "ms-wbt-server"),
+    (3478, "stun"),
+    (3690, "svn"),
+    (4369, "epmd"),
+    (4443, "pharos"),
+    (4786, "smart-install"),
+    (5000, "upnp"),
+    (5060, "sip"),
+    (5061, "sips"),
+    (5222, "xmpp-client"),
+    (5269, "xmpp-server"),
+    (5349, "stuns"),
+    (5353, "mdns"),
+    (5432, "postgresql"),
+    (5671, "amqps"),
+    (5672, "amqp"),
+    (5900, "vnc"),
+    (5984, "couchdb"),
+    (5985, "wsman"),
+    (5986, "wsmans"),
+    (6000, "x11"),
+    (6379, "redis"),
+    (6443, "sun-sr-https"),
+    (6660, "irc"),
+    (6667, "ircu"),
+    (6697, "ircs-u"),
+    (7001, "afs3-callback"),
+    (8000, "http-alt"),
+    (8008, "http"),
+    (8080, "http-alt"),
+    (8081, "sunproxyadmin"),
+    (8086, "influxdb"),
+    (8443, "https-alt"),
+    (8883, "secure-mqtt"),
+    (8888, "ddi-tcp-1"),
+    (9000, "cslistener"),
+    (9042, "cassandra"),
+    (9090, "websm"),
+    (9092, "kafka"),
+    (9100, "jetdirect"),
+    (9200, "elasticsearch"),
+    (9300, "vrace"),
+    (9418, "git"),
+    (10000, "ndmp"),
+    (11211, "memcache"),
+    (15672, "rabbitmq-mgmt"),
+    (25565, "minecraft"),
+    (27017, "mongodb"),
+];
+
+/// Port the service called `name` usually listens on, e.g. 22 for `ssh`. For
+/// names registered on several ports, the lowest one.
+pub fn service_port(name: &str) -> Option<u16> {
+    SERVICES
+        .iter()
+        .find(|(_, service)| service.eq_ignore_ascii_case(name))
+        .map(|&(port, _)| port)
+}
+
+/// TCP ports by how often they're found open, most common first, after nmap's
+/// frequency data.
+const TOP_PORTS: &[u16] = &[
+    80, 23, 443, 21, 22, 25, 3389, 110, 445, 139, 143, 53, 135, 3306, 8080, 1723, 111, 995, 993,
+    5900, 1025, 587, 8888, 199, 1720, 465, 548, 113, 81, 6001, 10000, 514, 5060, 179, 1026, 2000,
+    8443, 8000, 32768, 554, 26, 1433, 49152, 2001, 515, 8008, 49154, 1027, 5666, 646, 5000, 5631,
+    631, 49153, 8081, 2049, 88, 79, 5800, 106, 2121, 1110, 49155, 6000, 513, 990, 5357, 427, 49156,
+    543, 544, 5101, 144, 7, 389, 8009, 3128, 444, 9999, 5009, 7070, 5190, 3000, 5432, 1900, 3986,
+    13, 1029, 9, 5051, 6646, 49157, 1028, 873, 1755, 2717, 4899, 9100, 119, 37,
+];
+
+/// The `n` ports most often found open, at most as many as there are in the
+/// table.
+pub(crate) fn top_ports(n: usize) -> &'static [u16] {
+    &TOP_PORTS[..n.min(TOP_PORTS.len())]
+}
+
+/// Name of the service usually listening on `port`, e.g. `ssh` for 22.
+pub fn service_name(port: u16) -> Option<&'static str> {
+    SERVICES
+        .binary_search_by_key(&port, |&(port, _)| port)
+        .ok()
+        .map(|idx| SERVICES[idx].1)
+}
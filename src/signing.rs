@@ -0,0 +1,56 @@
+//! Detached ed25519 signatures over result files, for --sign-key, so results
+//! kept for audits can be shown to be unmodified later, e.g.
+//!
+//! ```text
+//! openssl genpkey -algorithm ed25519 -out qapper.pem
+//! openssl pkey -in qapper.pem -pubout -out qapper.pub.pem
+//! qapper 10.0.0.0/24 -f json -o scan.json --sign-key qapper.pem
+//! qapper verify scan.json --key qapper.pub.pem
+//! ```
+//!
+//! The signature is written next to the file as `<file>.sig`, as the raw 64
+//! bytes `openssl pkeyutl -verify -rawin` takes too.
+
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    Signature, Signer, SigningKey, Verifier, VerifyingKey,
+};
+
+/// Loads a private key saved as PKCS#8 PEM, e.g. by `openssl genpkey`.
+pub(crate) fn load_key(path: &Path) -> io::Result<SigningKey> {
+    let pem = fs::read_to_string(path)?;
+    SigningKey::from_pkcs8_pem(&pem).map_err(invalid)
+}
+
+/// Signs the file at `path` with `key`, returning where the signature went.
+pub(crate) fn sign(path: &Path, key: &SigningKey) -> io::Result<PathBuf> {
+    let signature = key.sign(&fs::read(path)?);
+    let signature_path = signature_path(path);
+    fs::write(&signature_path, signature.to_bytes())?;
+    Ok(signature_path)
+}
+
+/// Whether the file at `path` is unmodified since it was signed with the
+/// private key of the public key at `key_path`, saved as PEM.
+pub(crate) fn verify(path: &Path, key_path: &Path) -> io::Result<bool> {
+    let key = VerifyingKey::from_public_key_pem(&fs::read_to_string(key_path)?).map_err(invalid)?;
+    let signature = Signature::from_slice(&fs::read(signature_path(path))?).map_err(invalid)?;
+    Ok(key.verify(&fs::read(path)?, &signature).is_ok())
+}
+
+/// `path` with `.sig` appended, e.g. `scan.json.sig`.
+fn signature_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".sig");
+    name.into()
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
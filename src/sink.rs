@@ -0,0 +1,794 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::{IpAddr, ToSocketAddrs, UdpSocket},
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    policy::Protocol,
+    ports::PortsStatus,
+    probes,
+    scanner::{ScanEvent, ScanReport},
+    stats::group_digits,
+};
+
+/// Receives results as each port is checked, and the whole report once the
+/// scan is done. Sinks are given to [`PortScannerBuilder::sink`] and driven by
+/// [`PortScanner::scan`].
+///
+/// [`PortScannerBuilder::sink`]: crate::PortScannerBuilder::sink
+/// [`PortScanner::scan`]: crate::PortScanner::scan
+pub trait ResultSink: Send {
+    fn record(&mut self, event: &ScanEvent) -> io::Result<()>;
+
+    fn finish(&mut self, report: &ScanReport) -> io::Result<()>;
+}
+
+/// Writes the report as human-readable text once the scan is done.
+pub struct TextSink<W> {
+    out: W,
+    open_only: bool,
+    show_latency: bool,
+    human: bool,
+    group_by: Option<String>,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            open_only: false,
+            show_latency: false,
+            human: false,
+            group_by: None,
+        }
+    }
+
+    /// Leave out closed ports.
+    pub fn open_only(mut self, open_only: bool) -> Self {
+        self.open_only = open_only;
+        self
+    }
+
+    /// Show how long connecting to each open port took.
+    pub fn show_latency(mut self, show_latency: bool) -> Self {
+        self.show_latency = show_latency;
+        self
+    }
+
+    /// Separate thousands in counts and show long durations in minutes and
+    /// hours, instead of the plain numbers scripts parse.
+    pub fn human(mut self, human: bool) -> Self {
+        self.human = human;
+        self
+    }
+
+    /// Group hosts by the domain of the names `enrichment` found for them,
+    /// e.g. all of `*.corp.example.com` for `rdns`, with how many hosts and
+    /// open ports each domain has.
+    pub fn group_by_domain(mut self, enrichment: impl Into<String>) -> Self {
+        self.group_by = Some(enrichment.into());
+        self
+    }
+
+    /// `n` with thousands separated, if asked to.
+    fn count(&self, n: u64) -> String {
+        if self.human {
+            group_digits(n)
+        } else {
+            n.to_string()
+        }
+    }
+}
+
+impl<W: Write + Send> ResultSink for TextSink<W> {
+    fn record(&mut self, _event: &ScanEvent) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self, report: &ScanReport) -> io::Result<()> {
+        if let Some(source) = &report.source {
+            writeln!(self.out, "scanned from {source}")?;
+        }
+
+        // hosts a name resolved to are listed under it instead
+        let named: HashSet<&IpAddr> = report.names.values().flat_map(|name| &name.addrs).collect();
+        for (name, group) in &report.names {
+            writeln!(self.out, "{name}:")?;
+            for ip in &group.addrs {
+                match report.hosts.get(ip) {
+                    Some(status) => self.write_host(ip, status, "\t")?,
+                    None => writeln!(self.out, "\t{ip}: down")?,
+                }
+            }
+
+            if !group.inconsistent.is_empty() || !group.inconsistent_udp.is_empty() {
+                let ports = group.inconsistent.iter().map(u16::to_string);
+                let udp = group
+                    .inconsistent_udp
+                    .iter()
+                    .map(|port| format!("udp {port}"));
+                let ports: Vec<_> = ports.chain(udp).collect();
+                writeln!(self.out, "\tinconsistent: {}", ports.join(","))?;
+            }
+        }
+
+        let mut domains: BTreeMap<String, Vec<(&IpAddr, &PortsStatus)>> = BTreeMap::new();
+        for (ip, status) in &report.hosts {
+            if named.contains(ip) {
+                continue;
+            }
+
+            match self.domain(status) {
+                Some(domain) => domains.entry(domain).or_default().push((ip, status)),
+                None => self.write_host(ip, status, "")?,
+            }
+        }
+
+        for (domain, hosts) in &domains {
+            let open: usize = hosts
+                .iter()
+                .map(|(_, status)| {
+                    status.open().len() + status.udp().map_or(0, |udp| udp.open().len())
+                })
+                .sum();
+            writeln!(
+                self.out,
+                "{domain}: {} hosts, {} open ports",
+                self.count(hosts.len() as u64),
+                self.count(open as u64)
+            )?;
+            for (ip, status) in hosts {
+                self.write_host(ip, status, "\t")?;
+            }
+        }
+
+        for (subnet, sample) in &report.samples {
+            writeln!(
+                self.out,
+                "{subnet}: {} of {} hosts sampled, {} up ({:.1}%), about {} up in all",
+                self.count(sample.sampled),
+                self.count(sample.hosts),
+                self.count(sample.up),
+                sample.density * 100.0,
+                self.count(sample.estimated_up)
+            )?;
+        }
+
+        for (ip, hops) in &report.routes {
+            writeln!(self.out, "route to {ip}: {}", probes::format_hops(hops))?;
+        }
+
+        if !report.unresolved.is_empty() {
+            writeln!(self.out, "unresolved: {}", report.unresolved.join(", "))?;
+        }
+
+        if let Some(exhaustion) = &report.exhaustion {
+            writeln!(
+                self.out,
+                "ran out of sockets {} times: {} ports retried, {} left out, at most {} checked at once since",
+                exhaustion.failures, exhaustion.retried, exhaustion.given_up, exhaustion.concurrency
+            )?;
+        }
+
+        if report.partial {
+            writeln!(
+                self.out,
+                "partial: the scan was stopped before every port was checked"
+            )?;
+        }
+
+        if self.human {
+            writeln!(self.out, "scanned {}", report.stats.human())?;
+        } else {
+            writeln!(self.out, "scanned {}", report.stats)?;
+        }
+        self.out.flush()
+    }
+}
+
+impl<W: Write> TextSink<W> {
+    /// The domain of the first name the enrichment hosts are grouped by
+    /// found for `status`, e.g. `corp.example.com` for
+    /// `web1.corp.example.com`, if asked to group them.
+    fn domain(&self, status: &PortsStatus) -> Option<String> {
+        let names = status.enrichments().get(self.group_by.as_ref()?)?;
+        let name = names.as_array()?.first()?.as_str()?;
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        match name.split_once('.') {
+            Some((_, domain)) => Some(domain.to_string()),
+            None => Some(name),
+        }
+    }
+
+    fn write_host(&mut self, ip: &IpAddr, status: &PortsStatus, indent: &str) -> io::Result<()> {
+        let mut status = Cow::Borrowed(status);
+        if !self.show_latency {
+            status.to_mut().hide_latency();
+        }
+
+        writeln!(self.out, "{indent}{ip}:")?;
+        for line in status.lines(!self.open_only) {
+            writeln!(self.out, "\t{indent}{line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the report as JSON once the scan is done.
+pub struct JsonSink<W> {
+    out: W,
+    open_only: bool,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            open_only: false,
+        }
+    }
+
+    /// Leave out closed ports.
+    pub fn open_only(mut self, open_only: bool) -> Self {
+        self.open_only = open_only;
+        self
+    }
+}
+
+impl<W: Write + Send> ResultSink for JsonSink<W> {
+    fn record(&mut self, _event: &ScanEvent) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self, report: &ScanReport) -> io::Result<()> {
+        if self.open_only {
+            let mut report = report.clone();
+            for status in report.hosts.values_mut() {
+                status.hide_closed();
+            }
+
+            serde_json::to_writer_pretty(&mut self.out, &report)?;
+        } else {
+            serde_json::to_writer_pretty(&mut self.out, report)?;
+        }
+
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+/// Writes a CSV row as each port is checked.
+pub struct CsvSink<W> {
+    out: W,
+    open_only: bool,
+    wrote_header: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            open_only: false,
+            wrote_header: false,
+        }
+    }
+
+    /// Leave out closed ports.
+    pub fn open_only(mut self, open_only: bool) -> Self {
+        self.open_only = open_only;
+        self
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            writeln!(
+                self.out,
+                "ip,port,protocol,open,filtered,time,banner,service,version,details,tls,alpn,alpn_offered,cert_cn,cert_sans,cert_expires,http_protocol,http_status,http_server,http_title,http_endpoints,http_alt_svc,http_api,dropped_ms,latency_us,rtt_us"
+            )?;
+            self.wrote_header = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> ResultSink for CsvSink<W> {
+    fn record(&mut self, event: &ScanEvent) -> io::Result<()> {
+        if self.open_only && !event.open {
+            return Ok(());
+        }
+
+        self.write_header()?;
+        let service = event.service.as_ref();
+        let tls = event.tls.as_ref();
+        let http = event.http.as_ref();
+        writeln!(
+            self.out,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            event.ip,
+            event.port,
+            event.protocol,
+            event.open,
+            event.filtered,
+            humantime::format_rfc3339_millis(event.time),
+            csv_field(event.banner.as_deref().unwrap_or_default()),
+            csv_field(service.map_or("", |s| &s.name)),
+            csv_field(
+                service
+                    .and_then(|s| s.version.as_deref())
+                    .unwrap_or_default()
+            ),
+            csv_field(
+                service
+                    .and_then(|s| s.details.as_deref())
+                    .unwrap_or_default()
+            ),
+            csv_field(tls.map_or("", |t| &t.version)),
+            csv_field(tls.and_then(|t| t.alpn.as_deref()).unwrap_or_default()),
+            csv_field(&tls.map(|t| t.protocols.join(" ")).unwrap_or_default()),
+            csv_field(
+                tls.and_then(|t| t.common_name.as_deref())
+                    .unwrap_or_default()
+            ),
+            csv_field(&tls.map(|t| t.alt_names.join(" ")).unwrap_or_default()),
+            tls.and_then(|t| t.expires)
+                .map(|expires| humantime::format_rfc3339_seconds(expires).to_string())
+                .unwrap_or_default(),
+            csv_field(http.map_or("", |h| &h.protocol)),
+            http.map(|h| h.status.to_string()).unwrap_or_default(),
+            csv_field(http.and_then(|h| h.server.as_deref()).unwrap_or_default()),
+            csv_field(http.and_then(|h| h.title.as_deref()).unwrap_or_default()),
+            csv_field(&http.map(|h| h.endpoints.join(" ")).unwrap_or_default()),
+            csv_field(http.and_then(|h| h.alt_svc.as_deref()).unwrap_or_default()),
+            csv_field(http.and_then(|h| h.api.as_deref()).unwrap_or_default()),
+            event
+                .dropped
+                .map(|millis| millis.to_string())
+                .unwrap_or_default(),
+            event
+                .latency
+                .map(|micros| micros.to_string())
+                .unwrap_or_default(),
+            event
+                .rtt
+                .map(|micros| micros.to_string())
+                .unwrap_or_default()
+        )
+    }
+
+    fn finish(&mut self, _report: &ScanReport) -> io::Result<()> {
+        self.write_header()?;
+        self.out.flush()
+    }
+}
+
+/// Appends each result to a state file as a line of JSON, so an interrupted
+/// scan can be resumed with [`PortScannerBuilder::resume`] from what
+/// [`CheckpointSink::open`] reads back. Writes are flushed at most once per
+/// interval, one second by default, and when the scan is done.
+///
+/// [`PortScannerBuilder::resume`]: crate::PortScannerBuilder::resume
+pub struct CheckpointSink<W> {
+    out: W,
+    interval: Duration,
+    flushed: Instant,
+    /// Ports already in the state file, which resumed scans pass along again.
+    recorded: HashSet<(IpAddr, Protocol, u16)>,
+}
+
+impl<W: Write> CheckpointSink<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            interval: Duration::from_secs(1),
+            flushed: Instant::now(),
+            recorded: HashSet::new(),
+        }
+    }
+
+    /// How often to flush results to the state file.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl CheckpointSink<BufWriter<File>> {
+    /// Opens the state file at `path` to append results to, creating it if
+    /// needed, and reads the results already in it. A last line cut short,
+    /// by a scan dying while writing it, is dropped.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<(Self, Vec<ScanEvent>)> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let complete = text.rfind('\n').map_or(0, |idx| idx + 1);
+        let mut events = vec![];
+        for (idx, line) in text[..complete].lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+
+            let event = serde_json::from_str(line).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", idx + 1))
+            })?;
+            events.push(event);
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.set_len(complete as u64)?;
+
+        let mut sink = Self::new(BufWriter::new(file));
+        sink.recorded = events.iter().map(key).collect();
+        Ok((sink, events))
+    }
+}
+
+impl<W: Write + Send> ResultSink for CheckpointSink<W> {
+    fn record(&mut self, event: &ScanEvent) -> io::Result<()> {
+        if self.recorded.contains(&key(event)) {
+            return Ok(());
+        }
+
+        serde_json::to_writer(&mut self.out, event)?;
+        writeln!(self.out)?;
+
+        if self.flushed.elapsed() >= self.interval {
+            self.out.flush()?;
+            self.flushed = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, _report: &ScanReport) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Sends a syslog message (RFC 5424) over UDP for each open port found, e.g.
+///
+/// ```text
+/// <13>1 2024-05-01T12:00:00.000Z 10.0.0.5 qapper 4242 open-port [qapper@32473 ip="10.0.0.1" port="22" protocol="tcp" service="ssh"] 10.0.0.1: 22 open (ssh OpenSSH_9.6)
+/// ```
+pub struct SyslogSink {
+    socket: UdpSocket,
+    /// Sent as the host name, which RFC 5424 allows when there's no name.
+    local: IpAddr,
+    facility: Facility,
+}
+
+/// Severity of the messages sent, notice.
+const NOTICE: u8 = 5;
+
+/// Structured data ID, under the enterprise number reserved for examples.
+const SD_ID: &str = "qapper@32473";
+
+impl SyslogSink {
+    /// Sends messages to the syslog server at `addr`.
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no address for syslog server")
+        })?;
+
+        let local: IpAddr = if addr.is_ipv4() {
+            [0; 4].into()
+        } else {
+            [0u16; 8].into()
+        };
+
+        let socket = UdpSocket::bind((local, 0))?;
+        socket.connect(addr)?;
+        Ok(Self {
+            local: socket.local_addr()?.ip(),
+            socket,
+            facility: Facility::default(),
+        })
+    }
+
+    /// Facility to send messages as. Defaults to `user`.
+    pub fn facility(mut self, facility: Facility) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    fn message(&self, event: &ScanEvent) -> String {
+        let mut data = format!(
+            "[{SD_ID} ip=\"{}\" port=\"{}\" protocol=\"{}\"",
+            event.ip, event.port, event.protocol
+        );
+
+        let mut text = format!("{}: ", event.ip);
+        if !event.protocol.is_tcp() {
+            text += &format!("{} ", event.protocol);
+        }
+
+        text += &format!("{} open", event.port);
+        if let Some(service) = &event.service {
+            data += &format!(" service=\"{}\"", sd_value(&service.name));
+            if let Some(version) = &service.version {
+                data += &format!(" version=\"{}\"", sd_value(version));
+            }
+
+            text += &format!(" ({service})");
+        }
+
+        data += "]";
+        format!(
+            "<{}>1 {} {} qapper {} open-port {data} {text}",
+            self.facility.0 * 8 + NOTICE,
+            humantime::format_rfc3339_millis(event.time),
+            self.local,
+            std::process::id(),
+        )
+    }
+}
+
+impl ResultSink for SyslogSink {
+    fn record(&mut self, event: &ScanEvent) -> io::Result<()> {
+        if !event.open {
+            return Ok(());
+        }
+
+        match self.socket.send(self.message(event).as_bytes()) {
+            // like syslog over UDP usually is, the messages are best effort
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            sent => sent.map(drop),
+        }
+    }
+
+    fn finish(&mut self, _report: &ScanReport) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A syslog facility, e.g. `local0`, parsed from and formatted as its name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Facility(u8);
+
+/// Facility names and codes.
+const FACILITIES: &[(&str, u8)] = &[
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+impl Default for Facility {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+impl FromStr for Facility {
+    type Err = String;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        FACILITIES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(value))
+            .map(|&(_, code)| Self(code))
+            .ok_or_else(|| format!("unknown syslog facility: {value}"))
+    }
+}
+
+impl Display for Facility {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (name, _) = FACILITIES
+            .iter()
+            .find(|(_, code)| *code == self.0)
+            .expect("facilities are only made from the table");
+        write!(f, "{name}")
+    }
+}
+
+fn key(event: &ScanEvent) -> (IpAddr, Protocol, u16) {
+    (event.ip, event.protocol, event.port)
+}
+
+/// Escapes what would end a structured data value early.
+fn sd_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+/// Quotes `field` if it contains anything that would break the row.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use serde_json::json;
+
+    use super::*;
+
+    const CSV_HEADER: &str = "ip,port,protocol,open,filtered,time,banner,service,version,details,tls,alpn,alpn_offered,cert_cn,cert_sans,cert_expires,http_protocol,http_status,http_server,http_title,http_endpoints,http_alt_svc,http_api,dropped_ms,latency_us,rtt_us\n";
+
+    fn event(port: u16, open: bool, banner: Option<&str>) -> ScanEvent {
+        serde_json::from_value(json!({
+            "ip": "192.0.2.1",
+            "port": port,
+            "open": open,
+            "time": "2024-05-01T12:00:00.000Z",
+            "banner": banner,
+            "latency": open.then_some(1500),
+        }))
+        .unwrap()
+    }
+
+    fn report() -> ScanReport {
+        let mut report = ScanReport::default();
+        let status = json!({ "open": [22], "closed": [23] });
+        report.hosts.insert(
+            "192.0.2.1".parse().unwrap(),
+            serde_json::from_value(status).unwrap(),
+        );
+        report
+    }
+
+    fn run(mut sink: impl ResultSink, events: &[ScanEvent]) {
+        for event in events {
+            sink.record(event).unwrap();
+        }
+
+        sink.finish(&report()).unwrap();
+    }
+
+    #[test]
+    fn writes_json_reports() {
+        let mut out = vec![];
+        run(JsonSink::new(&mut out).open_only(true), &[]);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"{
+  "hosts": {
+    "192.0.2.1": {
+      "open": [
+        22
+      ],
+      "started": null,
+      "finished": null
+    }
+  },
+  "stats": {
+    "hosts": 0,
+    "up": 0,
+    "down": 0,
+    "ports": 0,
+    "open": 0,
+    "closed": 0,
+    "filtered": 0,
+    "elapsed": 0,
+    "rate": 0.0
+  }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn writes_csv_rows() {
+        let mut out = vec![];
+        let events = [
+            event(22, true, Some("SSH-2.0-OpenSSH_9.6")),
+            event(23, false, None),
+        ];
+        run(CsvSink::new(&mut out), &events);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "{CSV_HEADER}\
+                192.0.2.1,22,tcp,true,false,2024-05-01T12:00:00.000Z,SSH-2.0-OpenSSH_9.6,,,,,,,,,,,,,,,,,,1500,\n\
+                192.0.2.1,23,tcp,false,false,2024-05-01T12:00:00.000Z,,,,,,,,,,,,,,,,,,,,\n"
+            )
+        );
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        let mut out = vec![];
+        let banners = [
+            event(21, true, Some("220 Welcome, stranger")),
+            event(22, true, Some("say \"hi\"")),
+            event(23, true, Some("login:\r\n")),
+        ];
+        run(CsvSink::new(&mut out).open_only(true), &banners);
+        let rest = ",,,,,,,,,,,,,,,,,,1500,";
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "{CSV_HEADER}\
+                192.0.2.1,21,tcp,true,false,2024-05-01T12:00:00.000Z,\"220 Welcome, stranger\"{rest}\n\
+                192.0.2.1,22,tcp,true,false,2024-05-01T12:00:00.000Z,\"say \"\"hi\"\"\"{rest}\n\
+                192.0.2.1,23,tcp,true,false,2024-05-01T12:00:00.000Z,\"login:\r\n\"{rest}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn writes_the_csv_header_without_rows() {
+        let mut out = vec![];
+        run(
+            CsvSink::new(&mut out).open_only(true),
+            &[event(23, false, None)],
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), CSV_HEADER);
+    }
+
+    #[test]
+    fn resumes_from_checkpoints() {
+        let path = env::temp_dir().join(format!("qapper-checkpoint-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let events = [
+            event(22, true, Some("SSH-2.0-OpenSSH_9.6")),
+            event(23, false, None),
+        ];
+        let (sink, resumed) = CheckpointSink::open(&path).unwrap();
+        assert!(resumed.is_empty());
+        run(sink, &events);
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            written,
+            "{\"ip\":\"192.0.2.1\",\"port\":22,\"open\":true,\"time\":\"2024-05-01T12:00:00.000Z\",\"banner\":\"SSH-2.0-OpenSSH_9.6\",\"latency\":1500}\n\
+            {\"ip\":\"192.0.2.1\",\"port\":23,\"open\":false,\"time\":\"2024-05-01T12:00:00.000Z\"}\n"
+        );
+
+        // a scan that died halfway through a line
+        fs::write(&path, format!("{written}{{\"ip\":\"192.0")).unwrap();
+        let (sink, resumed) = CheckpointSink::open(&path).unwrap();
+        assert_eq!(resumed, events);
+
+        // results passed along again aren't written twice
+        run(sink, &[events[1].clone(), event(25, false, None)]);
+        let (_, resumed) = CheckpointSink::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(resumed, [&events[..], &[event(25, false, None)]].concat());
+    }
+
+    #[test]
+    fn rejects_corrupt_checkpoints() {
+        let path = env::temp_dir().join(format!("qapper-corrupt-{}", std::process::id()));
+        fs::write(&path, "{\"ip\":\"192.0.2.1\"}\n").unwrap();
+        let err = CheckpointSink::open(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 1: "), "{err}");
+    }
+}
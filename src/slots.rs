@@ -0,0 +1,309 @@
+//! Sharing out the ports that may be checked at once between hosts, so one
+//! host with every port to check doesn't make hundreds of hosts with a few
+//! each wait until it's done. Hosts waiting for a slot take turns, getting a
+//! few slots in a row each, more for hosts with more ports, so those don't
+//! fall far behind either.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Display},
+    net::IpAddr,
+    sync::Mutex,
+};
+
+use tokio::sync::oneshot;
+
+/// Limits how many ports are checked at once, handing out free slots to
+/// waiting hosts in weighted round-robin. Shows how they're shared out
+/// while a scan runs, see [`PortScanner::slots`].
+///
+/// [`PortScanner::slots`]: crate::PortScanner::slots
+#[derive(Debug)]
+pub struct ProbeSlots {
+    total: usize,
+    state: Mutex<SlotsState>,
+}
+
+#[derive(Debug, Default)]
+struct SlotsState {
+    free: usize,
+    /// Hosts with ports waiting for a slot, whose turn it is first.
+    turns: VecDeque<IpAddr>,
+    waiting: HashMap<IpAddr, HostQueue>,
+    /// How many slots each host holds.
+    held: HashMap<IpAddr, usize>,
+}
+
+#[derive(Debug)]
+struct HostQueue {
+    weight: usize,
+    /// Slots the host may still get before the next host's turn.
+    credit: usize,
+    waiters: VecDeque<oneshot::Sender<()>>,
+}
+
+/// A slot to check a port in, given back when dropped.
+pub(crate) struct Slot<'a> {
+    slots: &'a ProbeSlots,
+    ip: IpAddr,
+}
+
+/// How the slots are shared out at some point during a scan.
+#[derive(Clone, Copy, Debug)]
+pub struct SlotStats {
+    /// Ports which may be checked at once.
+    pub total: usize,
+    /// Ports being checked.
+    pub in_use: usize,
+    /// Hosts with ports being checked.
+    pub hosts_holding: usize,
+    /// Most ports of one host being checked.
+    pub most_held: usize,
+    /// Hosts with ports waiting for a slot.
+    pub hosts_waiting: usize,
+    pub ports_waiting: usize,
+}
+
+impl ProbeSlots {
+    pub(crate) fn new(total: usize) -> Self {
+        Self {
+            total,
+            state: Mutex::new(SlotsState {
+                free: total,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Waits for `ip`'s turn for a slot. `ports` is how many ports are
+    /// checked on the host, which decides how many slots it gets per turn.
+    pub(crate) async fn acquire(&self, ip: IpAddr, ports: usize) -> Slot<'_> {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.free > 0 && state.turns.is_empty() {
+                state.free -= 1;
+                *state.held.entry(ip).or_default() += 1;
+                return Slot { slots: self, ip };
+            }
+
+            let (tx, rx) = oneshot::channel();
+            let weight = weight(ports);
+            let queue = state.waiting.entry(ip).or_insert_with(|| HostQueue {
+                weight,
+                credit: weight,
+                waiters: VecDeque::new(),
+            });
+
+            queue.waiters.push_back(tx);
+            if queue.waiters.len() == 1 {
+                state.turns.push_back(ip);
+            }
+
+            rx
+        };
+
+        let mut waiter = Waiter {
+            slots: self,
+            ip,
+            rx,
+        };
+        (&mut waiter.rx)
+            .await
+            .expect("waiters are only dropped after being handed a slot");
+        Slot { slots: self, ip }
+    }
+
+    /// How the slots are shared out right now.
+    pub fn stats(&self) -> SlotStats {
+        let state = self.state.lock().unwrap();
+        SlotStats {
+            total: self.total,
+            in_use: self.total - state.free,
+            hosts_holding: state.held.len(),
+            most_held: state.held.values().copied().max().unwrap_or(0),
+            hosts_waiting: state.waiting.len(),
+            ports_waiting: state
+                .waiting
+                .values()
+                .map(|queue| queue.waiters.len())
+                .sum(),
+        }
+    }
+
+    /// Gives a slot `ip` held to the host whose turn it is, or frees it.
+    fn release(&self, ip: IpAddr) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(held) = state.held.get_mut(&ip) {
+            *held -= 1;
+            if *held == 0 {
+                state.held.remove(&ip);
+            }
+        }
+
+        while let Some(&next) = state.turns.front() {
+            let queue = state.waiting.get_mut(&next).expect("hosts in turn wait");
+            let tx = queue.waiters.pop_front().expect("hosts in turn wait");
+            queue.credit -= 1;
+
+            if queue.waiters.is_empty() {
+                state.waiting.remove(&next);
+                state.turns.pop_front();
+            } else if queue.credit == 0 {
+                queue.credit = queue.weight;
+                state.turns.rotate_left(1);
+            }
+
+            // fails if the waiter gave up, e.g. the scan was cancelled
+            if tx.send(()).is_ok() {
+                *state.held.entry(next).or_default() += 1;
+                return;
+            }
+        }
+
+        state.free += 1;
+    }
+}
+
+/// How many slots in a row a host with `ports` ports gets per turn: one more
+/// for every tenfold.
+fn weight(ports: usize) -> usize {
+    1 + ports.max(1).ilog10() as usize
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.slots.release(self.ip);
+    }
+}
+
+/// A host waiting for a slot, which hands the slot on if it stops waiting
+/// right after being given one.
+struct Waiter<'a> {
+    slots: &'a ProbeSlots,
+    ip: IpAddr,
+    rx: oneshot::Receiver<()>,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        // slots that were received are given back by the `Slot` instead
+        self.rx.close();
+        if self.rx.try_recv().is_ok() {
+            self.slots.release(self.ip);
+        }
+    }
+}
+
+impl Display for SlotStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "probe slots in use: {}/{}, hosts holding them: {}, most held by one host: {}, \
+             hosts waiting: {}, ports waiting: {}",
+            self.in_use,
+            self.total,
+            self.hosts_holding,
+            self.most_held,
+            self.hosts_waiting,
+            self.ports_waiting
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+    use tokio::{
+        task::{self, JoinSet},
+        time::timeout,
+    };
+
+    use super::*;
+
+    fn ip(n: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, n))
+    }
+
+    /// Has `hosts` wait for the only slot in turn, as `(ip, ports)` with a
+    /// waiter for each port, and returns whose turn it was, in order.
+    async fn turns(hosts: &[(IpAddr, usize)]) -> Vec<IpAddr> {
+        let slots = Arc::new(ProbeSlots::new(1));
+        let held = slots.acquire(ip(0), 1).await;
+        let order = Arc::new(Mutex::new(vec![]));
+        let mut tasks = JoinSet::new();
+        for &(ip, ports) in hosts {
+            for _ in 0..ports.max(1) {
+                let (shared, order) = (Arc::clone(&slots), Arc::clone(&order));
+                tasks.spawn(async move {
+                    let _slot = shared.acquire(ip, ports).await;
+                    order.lock().unwrap().push(ip);
+                });
+
+                // queue up in the order given
+                while slots.stats().ports_waiting < tasks.len() {
+                    task::yield_now().await;
+                }
+            }
+        }
+
+        drop(held);
+        tasks.join_all().await;
+        assert_eq!(slots.stats().in_use, 0);
+        Arc::try_unwrap(order).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn weighs_hosts_by_ports() {
+        assert_eq!(weight(0), 1);
+        assert_eq!(weight(9), 1);
+        assert_eq!(weight(10), 2);
+        assert_eq!(weight(65535), 5);
+    }
+
+    #[tokio::test]
+    async fn hands_out_slots_by_weight() {
+        let (many, few) = (ip(1), ip(2));
+        let order = turns(&[(many, 10), (few, 2)]).await;
+        assert_eq!(
+            order[..6],
+            [many, many, few, many, many, few],
+            "hosts with 10 ports get 2 slots per turn"
+        );
+        assert!(order[6..].iter().all(|&ip| ip == many));
+    }
+
+    #[tokio::test]
+    async fn gives_hosts_without_ports_a_turn() {
+        let (many, none) = (ip(1), ip(2));
+        let order = turns(&[(many, 10), (none, 0)]).await;
+        assert_eq!(order[2], none);
+        assert_eq!(order.len(), 11);
+    }
+
+    #[tokio::test]
+    async fn hands_out_free_slots_at_once() {
+        let slots = ProbeSlots::new(2);
+        let first = slots.acquire(ip(1), 100).await;
+        let second = slots.acquire(ip(2), 1).await;
+        let stats = slots.stats();
+        assert_eq!((stats.in_use, stats.hosts_holding), (2, 2));
+
+        drop((first, second));
+        assert_eq!(slots.stats().in_use, 0);
+    }
+
+    #[tokio::test]
+    async fn passes_on_slots_of_hosts_which_gave_up() {
+        let slots = ProbeSlots::new(1);
+        let held = slots.acquire(ip(1), 1).await;
+        // queues up, then gives up waiting
+        let waiting = timeout(Duration::ZERO, slots.acquire(ip(2), 1)).await;
+        assert!(waiting.is_err());
+
+        drop(held);
+        let stats = slots.stats();
+        assert_eq!((stats.in_use, stats.hosts_waiting), (0, 0));
+        let _slot = slots.acquire(ip(3), 1).await;
+    }
+}
@@ -0,0 +1,86 @@
+//! Timing templates like nmap's `-T0` to `-T5`, setting the timeout,
+//! retries, concurrency and rate of a scan in one go.
+
+use clap::{parser::ValueSource, ArgMatches, ValueEnum};
+
+use crate::Config;
+
+/// How fast to scan, from slowest and least noticeable to fastest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Timing {
+    /// One port at a time, at most one a second, for evading intrusion detection
+    #[value(alias = "0")]
+    Paranoid,
+    /// A few ports at a time, at most five a second
+    #[value(alias = "1")]
+    Sneaky,
+    /// Gentle on the network and the targets, e.g. production hosts
+    #[value(alias = "2")]
+    Polite,
+    /// The defaults
+    #[value(alias = "3")]
+    Normal,
+    /// Shorter timeouts for fast, reliable networks
+    #[value(alias = "4")]
+    Aggressive,
+    /// Shortest timeouts, trading accuracy for speed
+    #[value(alias = "5")]
+    Insane,
+}
+
+/// What a timing template sets.
+struct Preset {
+    timeout: u64,
+    retries: u32,
+    concurrency: Option<usize>,
+    max_rate: Option<u32>,
+}
+
+impl Timing {
+    fn preset(self) -> Preset {
+        let (timeout, retries, concurrency, max_rate) = match self {
+            Self::Paranoid => (5000, 2, Some(1), Some(1)),
+            Self::Sneaky => (3000, 2, Some(5), Some(5)),
+            Self::Polite => (2000, 1, Some(50), Some(100)),
+            Self::Normal => (1000, 0, None, None),
+            Self::Aggressive => (500, 1, None, None),
+            Self::Insane => (250, 0, None, None),
+        };
+
+        Preset {
+            timeout,
+            retries,
+            concurrency,
+            max_rate,
+        }
+    }
+
+    /// Sets whatever `matches` shows wasn't given on the command line or in
+    /// the environment, over the config file. Limits the template leaves
+    /// unset are left alone.
+    pub(crate) fn apply(self, config: &mut Config, matches: &ArgMatches) {
+        let given = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        let preset = self.preset();
+
+        if !given("timeout") {
+            config.timeout = preset.timeout;
+        }
+
+        if !given("retries") {
+            config.retries = preset.retries;
+        }
+
+        if let Some(concurrency) = preset.concurrency.filter(|_| !given("concurrency")) {
+            config.concurrency = Some(concurrency);
+        }
+
+        if let Some(rate) = preset.max_rate.filter(|_| !given("max_rate")) {
+            config.max_rate = Some(rate);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Just enough HTTP/1.1 to post to webhooks, ask reflectors for a scan and
+//! answer requests for metrics and scans, one request per connection.
+
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use http::{uri::Scheme, Uri};
+use log::{error, trace};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tokio_rustls::{
+    rustls::{crypto::ring, pki_types::ServerName, ClientConfig, RootCertStore},
+    TlsConnector,
+};
+
+/// How long clients get to send their request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long connecting to a server, TLS handshake included, may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest response read.
+const MAX_RESPONSE: u64 = 16 * 1024 * 1024;
+
+static TLS_CONFIG: LazyLock<Arc<ClientConfig>> = LazyLock::new(|| {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    Arc::new(
+        ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )
+});
+
+/// An `http` or `https` URL.
+#[derive(Clone, Debug)]
+pub(crate) struct HttpUrl(Uri);
+
+impl FromStr for HttpUrl {
+    type Err = String;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let uri: Uri = value.parse().map_err(|e| format!("{e}"))?;
+        if uri.scheme() != Some(&Scheme::HTTP) && uri.scheme() != Some(&Scheme::HTTPS) {
+            return Err("expected an http:// or https:// URL".to_string());
+        }
+
+        if uri.host().is_none() {
+            return Err("expected a host in the URL".to_string());
+        }
+
+        Ok(Self(uri))
+    }
+}
+
+impl HttpUrl {
+    /// The URL with `params` added to its query string.
+    pub(crate) fn with_query(&self, params: &str) -> Self {
+        let separator = if self.0.query().is_some() { '&' } else { '?' };
+        let url = format!("{}{separator}{params}", self.0);
+        Self(url.parse().expect("adding a query keeps the URL valid"))
+    }
+
+    /// Sends a `method` request with `body`, if any, as `content_type`,
+    /// returning the body of the response if it's a success. Only
+    /// connecting is timed out, as some answers take a while, so callers
+    /// bound the whole request.
+    pub(crate) async fn request(
+        &self,
+        method: &str,
+        body: Option<(&str, &str)>,
+    ) -> io::Result<Vec<u8>> {
+        let host = self.0.host().expect("checked when parsing");
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let https = self.0.scheme() == Some(&Scheme::HTTPS);
+        let port = self.0.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+        let timed_out = |_| io::Error::new(io::ErrorKind::TimedOut, "failed to connect in time");
+        let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port)))
+            .await
+            .map_err(timed_out)??;
+        if https {
+            let server_name = ServerName::try_from(host.to_string())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let connect = TlsConnector::from(Arc::clone(&TLS_CONFIG)).connect(server_name, stream);
+            let stream = timeout(CONNECT_TIMEOUT, connect)
+                .await
+                .map_err(timed_out)??;
+            self.exchange(stream, method, body).await
+        } else {
+            self.exchange(stream, method, body).await
+        }
+    }
+
+    async fn exchange(
+        &self,
+        mut stream: impl AsyncRead + AsyncWrite + Unpin,
+        method: &str,
+        body: Option<(&str, &str)>,
+    ) -> io::Result<Vec<u8>> {
+        let path = self.0.path_and_query().map_or("/", |path| path.as_str());
+        let authority = self.0.authority().expect("checked when parsing");
+        let mut request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: qapper/{}\r\nConnection: close\r\n",
+            env!("CARGO_PKG_VERSION"),
+        );
+
+        if let Some((content_type, body)) = body {
+            request += &format!(
+                "Content-Type: {content_type}\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+        } else {
+            request += "\r\n";
+        }
+
+        stream.write_all(request.as_bytes()).await?;
+        stream.flush().await?;
+
+        let mut response = vec![];
+        (&mut stream)
+            .take(MAX_RESPONSE)
+            .read_to_end(&mut response)
+            .await?;
+
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or_else(|| invalid("incomplete response".to_string()))?;
+        let head = String::from_utf8_lossy(&response[..split]).into_owned();
+        let body = &response[split + 4..];
+
+        let status_line = head.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => {}
+            _ => {
+                // servers often explain themselves in the body
+                let body = String::from_utf8_lossy(body);
+                let reason = body.lines().next().unwrap_or_default().trim();
+                return Err(io::Error::other(format!(
+                    "unexpected answer: {status_line:?} {reason}"
+                )));
+            }
+        }
+
+        let chunked = head.lines().any(|line| {
+            line.split_once(':').is_some_and(|(name, value)| {
+                name.eq_ignore_ascii_case("transfer-encoding")
+                    && value.trim().eq_ignore_ascii_case("chunked")
+            })
+        });
+
+        if chunked {
+            dechunk(body).ok_or_else(|| invalid("invalid chunked body".to_string()))
+        } else {
+            Ok(body.to_vec())
+        }
+    }
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked`.
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+    loop {
+        let end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..end]).ok()?;
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        if size == 0 {
+            return Some(decoded);
+        }
+
+        // chunks are followed by CRLF too
+        let start = end + 2;
+        let chunk = body.get(start..start.checked_add(size)?)?;
+        body = body[start + size..].strip_prefix(b"\r\n")?;
+        decoded.extend_from_slice(chunk);
+    }
+}
+
+/// A request received by [`serve`].
+pub(crate) struct Request {
+    /// Where it came from.
+    pub(crate) peer: SocketAddr,
+    pub(crate) method: String,
+    /// The path, with the query string if any.
+    pub(crate) target: String,
+}
+
+/// An answer to a [`Request`].
+pub(crate) struct Response {
+    /// e.g. `200 OK`.
+    pub(crate) status: &'static str,
+    pub(crate) content_type: &'static str,
+    pub(crate) body: String,
+}
+
+impl Response {
+    pub(crate) fn new(status: &'static str, content_type: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    /// A plain text error, e.g. for `404 Not Found`.
+    pub(crate) fn error(status: &'static str) -> Self {
+        let (_, reason) = status.split_once(' ').unwrap_or_default();
+        Self::new(status, "text/plain", format!("{}\n", reason.to_lowercase()))
+    }
+}
+
+/// Answers requests on `listener` with `handler` until the program exits,
+/// logging what goes wrong as `name` requests.
+pub(crate) async fn serve<H, Fut>(listener: TcpListener, name: &'static str, handler: H)
+where
+    H: Fn(Request) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response> + Send,
+{
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Failed to accept {name} request: {e}");
+                continue;
+            }
+        };
+
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer(stream, peer, handler).await {
+                trace!("Failed to answer {name} request from {peer}: {e}");
+            }
+        });
+    }
+}
+
+async fn answer<H, Fut>(mut stream: TcpStream, peer: SocketAddr, handler: H) -> io::Result<()>
+where
+    H: Fn(Request) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    // only the request line matters
+    let mut buf = vec![0; 4096];
+    let mut len = 0;
+    while !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") && len < buf.len() {
+        match timeout(REQUEST_TIMEOUT, stream.read(&mut buf[len..])).await?? {
+            0 => break,
+            n => len += n,
+        }
+    }
+
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut words = request.split_whitespace();
+    let request = Request {
+        peer,
+        method: words.next().unwrap_or_default().to_string(),
+        target: words.next().unwrap_or_default().to_string(),
+    };
+
+    let Response {
+        status,
+        content_type,
+        body,
+    } = handler(request).await;
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dechunks_bodies() {
+        let body = b"5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n";
+        assert_eq!(dechunk(body).unwrap(), b"hello, world");
+    }
+
+    #[test]
+    fn dechunks_empty_bodies() {
+        assert_eq!(dechunk(b"0\r\n\r\n").unwrap(), b"");
+    }
+
+    #[test]
+    fn ignores_chunk_extensions_and_trailers() {
+        let body = b"A;name=value\r\n0123456789\r\n0\r\nExpires: never\r\n\r\n";
+        assert_eq!(dechunk(body).unwrap(), b"0123456789");
+    }
+
+    #[test]
+    fn rejects_truncated_bodies() {
+        let body = b"5\r\nhello\r\n0\r\n";
+        for end in 0..body.len() - 2 {
+            assert_eq!(dechunk(&body[..end]), None, "{end} bytes");
+        }
+    }
+
+    #[test]
+    fn rejects_chunks_of_the_wrong_size() {
+        assert_eq!(dechunk(b"4\r\nhello\r\n0\r\n\r\n"), None);
+        assert_eq!(dechunk(b"6\r\nhello\r\n0\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert_eq!(dechunk(b"x\r\nhello\r\n0\r\n\r\n"), None);
+        assert_eq!(dechunk(b"\r\n0\r\n\r\n"), None);
+        assert_eq!(dechunk(b"ffffffffffffffff\r\nhello\r\n"), None);
+    }
+}
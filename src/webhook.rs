@@ -0,0 +1,71 @@
+//! Posts ports found newly open to a webhook, e.g. a Slack or Teams incoming
+//! webhook, as JSON like
+//!
+//! ```json
+//! {
+//!   "text": "10.0.0.1: 443 opened",
+//!   "time": "2024-05-01T12:00:00Z",
+//!   "opened": [{"change": "opened", "ip": "10.0.0.1", "port": 443}]
+//! }
+//! ```
+//!
+//! `text` is what chat services show, the rest is for anything else.
+
+use std::{
+    io,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use qapper::Change;
+use serde_json::json;
+use tokio::time::timeout;
+
+use crate::web::HttpUrl;
+
+/// How long the webhook gets to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A URL to post to.
+#[derive(Clone, Debug)]
+pub(crate) struct Webhook {
+    url: HttpUrl,
+}
+
+impl FromStr for Webhook {
+    type Err = String;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            url: value.parse()?,
+        })
+    }
+}
+
+impl Webhook {
+    /// Posts the ports which opened among `changes`, if any, noting they were
+    /// found at `time`.
+    pub(crate) async fn notify(&self, changes: &[Change], time: SystemTime) -> io::Result<()> {
+        let opened: Vec<_> = changes
+            .iter()
+            .filter(|change| matches!(change, Change::Opened { .. }))
+            .collect();
+
+        if opened.is_empty() {
+            return Ok(());
+        }
+
+        let text: Vec<_> = opened.iter().map(|change| change.to_string()).collect();
+        let payload = json!({
+            "text": text.join("\n"),
+            "time": humantime::format_rfc3339_seconds(time).to_string(),
+            "opened": opened,
+        });
+
+        let body = payload.to_string();
+        let post = self.url.request("POST", Some(("application/json", &body)));
+        timeout(TIMEOUT, post)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no answer"))?
+            .map(drop)
+    }
+}